@@ -1,50 +1,258 @@
-use axum::{extract::{State, Path}, Json, http::StatusCode, response::IntoResponse};
+use axum::{extract::{ws::{Message, WebSocket, WebSocketUpgrade}, FromRequest, Multipart, Path, Query, Request, State}, Json, http::{header, HeaderMap, StatusCode}, response::{sse::{Event, KeepAlive, Sse}, IntoResponse}};
 use std::sync::Arc;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
 use crate::state::AppState;
-use crate::model::{Transaction, Account, VerifyResult, CloseAccountRequest};
+use crate::model::{Transaction, Account, Commodity, FormatRequest, FormatResult, LedgerOptions, OptionValue, Price, PriceFetchResult, PriceQuery, LatestPriceQuery, AccountDetail, AccountRoot, BalanceAssertion, Directive, DirectiveQuery, Document, Note, NoteRequest, Pad, AssertionCheck, AssertionCheckQuery, AccountActivityRange, VerifyResult, CloseAccountRequest, LotIssue, ActivityDay, ActivityQuery, TransactionQuery, ExportQuery, ConfigInfo, ImportQuery, PayeeInfo, PayeeQuery, PayeeCompleteQuery, PayeeLast, PendingCount, Suggestion, AutocompleteQuery, AccountSuggestion, AccountSuggestionQuery, AccountCompletion, AccountCompleteQuery, Metrics, LedgerStats, TagInfo, CurrencyInfo, IncomeStatement, IncomeStatementQuery, IncomeStatementPeriods, IncomeStatementPeriodsQuery, AccountBalance, BalanceQuery, BalanceSheet, BalanceSheetQuery, RegisterRow, RegisterQuery, TrialBalance, TrialBalanceQuery, MonthlyQuery, MonthlyReport, CashFlow, CashFlowQuery, CompareQuery, CompareReport, Budget, BudgetQuery, BudgetReport, BudgetRequest, Holdings, HoldingsQuery, SpendingGroup, SpendingQuery, DatedAmount, MonthEndNetWorthQuery, NetWorthQuery, NetWorthSeries, Page, AccountNode, AccountTreeQuery, VerifyQuery, AccountsQuery, DuplicateCluster, DuplicateQuery, DeleteAccountQuery, RenameAccountRequest, RenameResult, WriteQuery, TransactionWriteResult};
+use crate::autocomplete;
 use crate::beancount;
+use crate::error::{ApiError, LedgerError};
+use crate::export;
+use crate::format;
+use crate::i18n::Locale;
+use crate::import::{self, CsvMapping, ImportResult};
+use crate::rules::{self, ImportRule};
+use crate::quotes;
+use crate::reports;
+
+/// `Json` for request bodies, rejecting with an `ApiError`.
+#[derive(FromRequest)]
+#[from_request(via(Json), rejection(ApiError))]
+pub struct JsonBody<T>(pub T);
+
+/// Keeps the status of a failed body read, so an upload over the body limit
+/// is the same short `413` the JSON extractor gives rather than a generic
+/// `400`.
+fn body_error(status: StatusCode, text: String) -> ApiError {
+    if status == StatusCode::PAYLOAD_TOO_LARGE {
+        ApiError::too_large()
+    } else {
+        ApiError::from_status(status, text)
+    }
+}
+
+fn multipart_error(e: axum::extract::multipart::MultipartError) -> ApiError {
+    body_error(e.status(), e.body_text())
+}
+
+/// Whether a failed body read hit the length limit, either the one passed
+/// to `to_bytes` or the `RequestBodyLimitLayer`'s, which nests it.
+fn is_length_limit(e: axum::Error) -> bool {
+    let inner = e.into_inner();
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(inner.as_ref());
+    while let Some(e) = source {
+        if e.is::<http_body_util::LengthLimitError>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Applies `limit`/`offset` to a list. Without a `limit`, a list longer than
+/// the configured maximum is rejected so the client paginates instead.
+fn paginate<T>(items: Vec<T>, page: &Page, max: Option<usize>) -> anyhow::Result<Vec<T>> {
+    if let Some(max) = max {
+        match page.limit {
+            Some(limit) if limit > max => {
+                return Err(LedgerError::invalid(format!("limit {} exceeds the maximum of {} items", limit, max)).into());
+            }
+            None if items.len() > max => {
+                return Err(LedgerError::invalid(format!(
+                    "Response would contain {} items, more than the maximum of {}; paginate with limit and offset",
+                    items.len(),
+                    max
+                ))
+                .into());
+            }
+            _ => {}
+        }
+    }
+    let offset = page.offset.unwrap_or(0);
+    Ok(items
+        .into_iter()
+        .skip(offset)
+        .take(page.limit.unwrap_or(usize::MAX))
+        .collect())
+}
 
 #[utoipa::path(
     get,
     path = "/transactions",
+    params(TransactionQuery, Page),
     responses(
-        (status = 200, description = "List all transactions", body = Vec<Transaction>),
+        (status = 200, description = "List all transactions, newest first unless sorted otherwise", body = Vec<Transaction>),
+        (status = 400, description = "Unknown sort or order, or too many items without pagination"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn list_transactions(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Transaction>>, (StatusCode, String)> {
-    let data_dir = state.data_dir.clone();
-    tokio::task::spawn_blocking(move || beancount::list_transactions(&data_dir))
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
-        .map(Json)
-        .map_err(|e| {
-            tracing::error!("Failed to list transactions: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
-        })
+pub async fn list_transactions(State(state): State<Arc<AppState>>, Query(query): Query<TransactionQuery>, Query(page): Query<Page>) -> Result<Json<Vec<Transaction>>, ApiError> {
+    let state = state.clone();
+    let max = state.config.max_response_items;
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        let txs = beancount::list_transactions(&state.data_dir)?;
+        let mut txs = beancount::filter_transactions(txs, &query);
+        beancount::sort_transactions(&mut txs, &query)?;
+        paginate(txs, &page, max)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list transactions: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/transactions/pending",
+    params(Page),
+    responses(
+        (status = 200, description = "Transactions flagged `!` for review, oldest first", body = Vec<Transaction>),
+        (status = 400, description = "Too many items without pagination"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn pending_transactions(State(state): State<Arc<AppState>>, Query(page): Query<Page>) -> Result<Json<Vec<Transaction>>, ApiError> {
+    let state = state.clone();
+    let max = state.config.max_response_items;
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        paginate(beancount::pending_transactions(&state.data_dir)?, &page, max)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list pending transactions: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/pending",
+    responses(
+        (status = 200, description = "Number of pending (`!`) transactions per account, most first", body = Vec<PendingCount>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn pending_by_account(State(state): State<Arc<AppState>>) -> Result<Json<Vec<PendingCount>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::pending_by_account(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to count pending transactions: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/transactions/duplicates",
+    params(DuplicateQuery),
+    responses(
+        (status = 200, description = "Groups of likely duplicate transactions", body = Vec<DuplicateCluster>),
+        (status = 400, description = "Unknown matching key"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn find_duplicates(State(state): State<Arc<AppState>>, Query(query): Query<DuplicateQuery>) -> Result<Json<Vec<DuplicateCluster>>, ApiError> {
+    let key = match query.key.as_deref() {
+        None | Some("exact") => beancount::DuplicateKey::Exact,
+        Some("fuzzy") => beancount::DuplicateKey::FuzzyPayee,
+        Some(other) => return Err(ApiError::bad_request(format!("Unknown duplicate key: {}", other))),
+    };
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::find_duplicates(&state.data_dir, key)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to find duplicates: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/transactions/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Transactions as CSV, one row per posting", content_type = "text/csv", body = String),
+        (status = 400, description = "Unsupported export format"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn export_transactions(State(state): State<Arc<AppState>>, headers: HeaderMap, Query(query): Query<ExportQuery>) -> Result<impl IntoResponse, ApiError> {
+    let locale = Locale::negotiate(query.lang.as_deref(), &headers);
+    let format = query.format.as_deref().unwrap_or("csv");
+    if format != "csv" {
+        return Err(ApiError::bad_request(format!("Unsupported export format: {}", format)));
+    }
+    let state = state.clone();
+    let csv = tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        let txs = beancount::list_transactions(&state.data_dir)?;
+        export::transactions_csv(&beancount::filter_transactions(txs, &query.filter()), locale)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map_err(|e| {
+        tracing::error!("Failed to export transactions: {}", e);
+        ApiError::from_ledger(&e)
+    })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"transactions.csv\""),
+        ],
+        csv,
+    ))
 }
 
 #[utoipa::path(
     post,
     path = "/transactions",
+    params(WriteQuery),
     request_body = Transaction,
     responses(
-        (status = 201, description = "Transaction created"),
+        (status = 201, description = "Transaction created", body = TransactionWriteResult),
+        (status = 400, description = "A posting account name doesn't follow beancount's naming rules"),
+        (status = 422, description = "Transaction rejected by validation (including unknown accounts, if the profile rejects them), or posting to a closed account or one that doesn't allow its currency"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn add_transaction(State(state): State<Arc<AppState>>, Json(payload): Json<Transaction>) -> Result<impl IntoResponse, (StatusCode, String)> {
+pub async fn add_transaction(State(state): State<Arc<AppState>>, Query(query): Query<WriteQuery>, JsonBody(mut payload): JsonBody<Transaction>) -> Result<impl IntoResponse, ApiError> {
+    // IDs are assigned on write; a client-supplied one could collide.
+    payload.id = None;
     let state = state.clone();
-    tokio::task::spawn_blocking(move || {
-        let _lock = state.write_lock.lock().unwrap();
-        beancount::add_transaction(&state.data_dir, payload)
+    tokio::task::spawn_blocking(move || -> anyhow::Result<TransactionWriteResult> {
+        let _lock = state.lock_for_write();
+        let accounts = beancount::list_accounts(&state.data_dir)?;
+        let opens = beancount::prepare_transaction(&mut payload, &state.config, &accounts, query.auto_open)?;
+        let id = beancount::with_opened_accounts(&state.data_dir, &opens, || beancount::add_transaction(&state.data_dir, state.config.file_scheme, state.config.amount_column, payload))?;
+        // Nobody subscribed is not an error.
+        state.transactions_added.send(id).ok();
+        Ok(TransactionWriteResult {
+            auto_opened: opens.into_iter().map(|a| a.name).collect(),
+        })
     })
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
-    .map(|_| StatusCode::CREATED)
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|result| (StatusCode::CREATED, Json(result)))
     .map_err(|e| {
         tracing::error!("Failed to add transaction: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        ApiError::from_ledger(&e)
     })
 }
 
@@ -52,26 +260,34 @@ pub async fn add_transaction(State(state): State<Arc<AppState>>, Json(payload):
     put,
     path = "/transactions/{id}",
     params(
-        ("id" = String, Path, description = "Transaction ID")
+        ("id" = String, Path, description = "Transaction ID"),
+        WriteQuery
     ),
     request_body = Transaction,
     responses(
-        (status = 200, description = "Transaction updated"),
+        (status = 200, description = "Transaction updated", body = TransactionWriteResult),
+        (status = 400, description = "A posting account name doesn't follow beancount's naming rules"),
+        (status = 422, description = "Transaction rejected by validation (including unknown accounts, if the profile rejects them), or posting to a closed account or one that doesn't allow its currency"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn update_transaction(State(state): State<Arc<AppState>>, Path(id): Path<String>, Json(payload): Json<Transaction>) -> Result<impl IntoResponse, (StatusCode, String)> {
+pub async fn update_transaction(State(state): State<Arc<AppState>>, Path(id): Path<String>, Query(query): Query<WriteQuery>, JsonBody(mut payload): JsonBody<Transaction>) -> Result<impl IntoResponse, ApiError> {
     let state = state.clone();
-    tokio::task::spawn_blocking(move || {
-        let _lock = state.write_lock.lock().unwrap();
-        beancount::update_transaction(&state.data_dir, &id, payload)
+    tokio::task::spawn_blocking(move || -> anyhow::Result<TransactionWriteResult> {
+        let _lock = state.lock_for_write();
+        let accounts = beancount::list_accounts(&state.data_dir)?;
+        let opens = beancount::prepare_transaction(&mut payload, &state.config, &accounts, query.auto_open)?;
+        beancount::with_opened_accounts(&state.data_dir, &opens, || beancount::update_transaction(&state.data_dir, state.config.file_scheme, state.config.amount_column, &id, payload))?;
+        Ok(TransactionWriteResult {
+            auto_opened: opens.into_iter().map(|a| a.name).collect(),
+        })
     })
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
-    .map(|_| StatusCode::OK)
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
     .map_err(|e| {
         tracing::error!("Failed to update transaction: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        ApiError::from_ledger(&e)
     })
 }
 
@@ -86,18 +302,78 @@ pub async fn update_transaction(State(state): State<Arc<AppState>>, Path(id): Pa
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn delete_transaction(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+pub async fn delete_transaction(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<impl IntoResponse, ApiError> {
     let state = state.clone();
     tokio::task::spawn_blocking(move || {
-        let _lock = state.write_lock.lock().unwrap();
-        beancount::delete_transaction(&id)
+        let _lock = state.lock_for_write();
+        beancount::delete_transaction(&state.data_dir, &id)
     })
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
     .map(|_| StatusCode::OK)
     .map_err(|e| {
         tracing::error!("Failed to delete transaction: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/transactions/{id}/raw",
+    params(
+        ("id" = String, Path, description = "Transaction ID")
+    ),
+    responses(
+        (status = 200, description = "The transaction's source text as written in its file", content_type = "text/plain", body = String),
+        (status = 404, description = "Transaction not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn get_transaction_raw(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    let text = tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::transaction_source(&state.data_dir, &id)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map_err(|e| {
+        tracing::error!("Failed to read transaction source: {}", e);
+        ApiError::from_ledger(&e)
+    })?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text))
+}
+
+#[utoipa::path(
+    put,
+    path = "/transactions/{id}/raw",
+    params(
+        ("id" = String, Path, description = "Transaction ID")
+    ),
+    request_body(content = String, content_type = "text/plain", description = "Replacement source text for the transaction"),
+    responses(
+        (status = 200, description = "Source text replaced in place"),
+        (status = 400, description = "Text isn't exactly one transaction, or holds anything but comments around it; the file is left as it was"),
+        (status = 404, description = "Transaction not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn put_transaction_raw(State(state): State<Arc<AppState>>, Path(id): Path<String>, request: Request) -> Result<impl IntoResponse, ApiError> {
+    // Rejections (too large, not UTF-8) get the usual JSON error body.
+    let text = String::from_request(request, &())
+        .await
+        .map_err(|e| ApiError::from_status(e.status(), e.body_text()))?;
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::replace_transaction_source(&state.data_dir, &id, &text)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::OK)
+    .map_err(|e| {
+        tracing::error!("Failed to replace transaction source: {}", e);
+        ApiError::from_ledger(&e)
     })
 }
 
@@ -112,18 +388,18 @@ pub async fn delete_transaction(State(state): State<Arc<AppState>>, Path(id): Pa
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn clear_transaction(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+pub async fn clear_transaction(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<impl IntoResponse, ApiError> {
     let state = state.clone();
     tokio::task::spawn_blocking(move || {
-        let _lock = state.write_lock.lock().unwrap();
-        beancount::update_transaction_flag(&id, "*")
+        let _lock = state.lock_for_write();
+        beancount::update_transaction_flag(&state.data_dir, &id, "*")
     })
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
     .map(|_| StatusCode::OK)
     .map_err(|e| {
         tracing::error!("Failed to clear transaction: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        ApiError::from_ledger(&e)
     })
 }
 
@@ -138,62 +414,170 @@ pub async fn clear_transaction(State(state): State<Arc<AppState>>, Path(id): Pat
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn unclear_transaction(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+pub async fn unclear_transaction(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<impl IntoResponse, ApiError> {
     let state = state.clone();
     tokio::task::spawn_blocking(move || {
-        let _lock = state.write_lock.lock().unwrap();
-        beancount::update_transaction_flag(&id, "!")
+        let _lock = state.lock_for_write();
+        beancount::update_transaction_flag(&state.data_dir, &id, "!")
     })
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
     .map(|_| StatusCode::OK)
     .map_err(|e| {
         tracing::error!("Failed to unclear transaction: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/transactions/{id}/confirm",
+    params(
+        ("id" = String, Path, description = "Transaction ID")
+    ),
+    responses(
+        (status = 200, description = "Flag changed from `!` to `*` in place"),
+        (status = 404, description = "Transaction not found"),
+        (status = 409, description = "Transaction is not flagged `!`"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn confirm_transaction(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::confirm_transaction(&state.data_dir, &id)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::OK)
+    .map_err(|e| {
+        tracing::error!("Failed to confirm transaction: {}", e);
+        ApiError::from_ledger(&e)
     })
 }
 
 #[utoipa::path(
     get,
     path = "/accounts",
+    params(AccountsQuery, Page),
     responses(
         (status = 200, description = "List all accounts", body = Vec<Account>),
+        (status = 400, description = "Too many items without pagination"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn list_accounts(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Account>>, (StatusCode, String)> {
-    let data_dir = state.data_dir.clone();
-    tokio::task::spawn_blocking(move || beancount::list_accounts(&data_dir))
+pub async fn list_accounts(State(state): State<Arc<AppState>>, Query(query): Query<AccountsQuery>, Query(page): Query<Page>) -> Result<Json<Vec<Account>>, ApiError> {
+    let state = state.clone();
+    let max = state.config.max_response_items;
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        let accounts = beancount::filter_accounts(beancount::list_accounts(&state.data_dir)?, &query);
+        paginate(accounts, &page, max)
+    })
         .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
+        .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
         .map(Json)
         .map_err(|e| {
             tracing::error!("Failed to list accounts: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            ApiError::from_ledger(&e)
         })
 }
 
+#[utoipa::path(
+    get,
+    path = "/accounts/roots",
+    responses(
+        (status = 200, description = "Distinct top-level account components with account counts", body = Vec<AccountRoot>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_account_roots(State(state): State<Arc<AppState>>) -> Result<Json<Vec<AccountRoot>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::account_roots(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list account roots: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/tree",
+    params(AccountTreeQuery),
+    responses(
+        (status = 200, description = "Accounts as a tree with rolled-up balances", body = Vec<AccountNode>),
+        (status = 404, description = "Root account not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn account_tree(State(state): State<Arc<AppState>>, Query(query): Query<AccountTreeQuery>) -> Result<Json<Vec<AccountNode>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::account_tree(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build account tree: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/activity-range",
+    responses(
+        (status = 200, description = "First and last posting date per account", body = Vec<AccountActivityRange>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn account_activity_ranges(State(state): State<Arc<AppState>>) -> Result<Json<Vec<AccountActivityRange>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::account_activity_ranges(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to compute account activity ranges: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
 #[utoipa::path(
     post,
     path = "/accounts",
     request_body = Account,
     responses(
         (status = 201, description = "Account created"),
+        (status = 400, description = "Account name doesn't follow beancount's naming rules"),
+        (status = 422, description = "Invalid currency or open date"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn add_account(State(state): State<Arc<AppState>>, Json(payload): Json<Account>) -> Result<impl IntoResponse, (StatusCode, String)> {
+pub async fn add_account(State(state): State<Arc<AppState>>, JsonBody(payload): JsonBody<Account>) -> Result<impl IntoResponse, ApiError> {
     let state = state.clone();
     tokio::task::spawn_blocking(move || {
-        let _lock = state.write_lock.lock().unwrap();
+        let _lock = state.lock_for_write();
         beancount::add_account(&state.data_dir, payload)
     })
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
     .map(|_| StatusCode::CREATED)
     .map_err(|e| {
         tracing::error!("Failed to add account: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        ApiError::from_ledger(&e)
     })
 }
 
@@ -206,21 +590,23 @@ pub async fn add_account(State(state): State<Arc<AppState>>, Json(payload): Json
     request_body = Account,
     responses(
         (status = 200, description = "Account updated"),
+        (status = 400, description = "Account name doesn't follow beancount's naming rules, or the body names a different account than the path"),
+        (status = 422, description = "Invalid currency or open date"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn update_account(State(state): State<Arc<AppState>>, Path(name): Path<String>, Json(payload): Json<Account>) -> Result<impl IntoResponse, (StatusCode, String)> {
+pub async fn update_account(State(state): State<Arc<AppState>>, Path(name): Path<String>, JsonBody(payload): JsonBody<Account>) -> Result<impl IntoResponse, ApiError> {
     let state = state.clone();
     tokio::task::spawn_blocking(move || {
-        let _lock = state.write_lock.lock().unwrap();
+        let _lock = state.lock_for_write();
         beancount::update_account(&state.data_dir, &name, payload)
     })
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
     .map(|_| StatusCode::OK)
     .map_err(|e| {
         tracing::error!("Failed to update account: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        ApiError::from_ledger(&e)
     })
 }
 
@@ -228,25 +614,28 @@ pub async fn update_account(State(state): State<Arc<AppState>>, Path(name): Path
     delete,
     path = "/accounts/{name}",
     params(
-        ("name" = String, Path, description = "Account name")
+        ("name" = String, Path, description = "Account name"),
+        DeleteAccountQuery
     ),
     responses(
         (status = 200, description = "Account deleted"),
+        (status = 404, description = "Account not found"),
+        (status = 409, description = "Account is still used by transactions"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn delete_account(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+pub async fn delete_account(State(state): State<Arc<AppState>>, Path(name): Path<String>, Query(query): Query<DeleteAccountQuery>) -> Result<impl IntoResponse, ApiError> {
     let state = state.clone();
     tokio::task::spawn_blocking(move || {
-        let _lock = state.write_lock.lock().unwrap();
-        beancount::delete_account(&state.data_dir, &name)
+        let _lock = state.lock_for_write();
+        beancount::delete_account(&state.data_dir, &name, query.force)
     })
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
     .map(|_| StatusCode::OK)
     .map_err(|e| {
         tracing::error!("Failed to delete account: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        ApiError::from_ledger(&e)
     })
 }
 
@@ -259,40 +648,1848 @@ pub async fn delete_account(State(state): State<Arc<AppState>>, Path(name): Path
     request_body = CloseAccountRequest,
     responses(
         (status = 200, description = "Account closed"),
+        (status = 400, description = "Invalid date or a date before the account was opened"),
+        (status = 404, description = "Account not found"),
+        (status = 409, description = "Account already closed or has a non-zero balance"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn close_account(State(state): State<Arc<AppState>>, Path(name): Path<String>, Json(payload): Json<CloseAccountRequest>) -> Result<impl IntoResponse, (StatusCode, String)> {
+pub async fn close_account(State(state): State<Arc<AppState>>, Path(name): Path<String>, JsonBody(payload): JsonBody<CloseAccountRequest>) -> Result<impl IntoResponse, ApiError> {
     let state = state.clone();
     tokio::task::spawn_blocking(move || {
-        let _lock = state.write_lock.lock().unwrap();
+        let _lock = state.lock_for_write();
         beancount::close_account(&state.data_dir, &name, &payload.date)
     })
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
     .map(|_| StatusCode::OK)
     .map_err(|e| {
         tracing::error!("Failed to close account: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/accounts/{name}/rename",
+    params(
+        ("name" = String, Path, description = "Account name")
+    ),
+    request_body = RenameAccountRequest,
+    responses(
+        (status = 200, description = "Occurrences rewritten per file", body = RenameResult),
+        (status = 400, description = "New name doesn't follow beancount's naming rules"),
+        (status = 404, description = "Account not found"),
+        (status = 409, description = "New name collides with an existing account"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn rename_account(State(state): State<Arc<AppState>>, Path(name): Path<String>, JsonBody(payload): JsonBody<RenameAccountRequest>) -> Result<Json<RenameResult>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::rename_account(&state.data_dir, &name, &payload.new_name, payload.merge)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to rename account: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/accounts/{name}/reopen",
+    params(
+        ("name" = String, Path, description = "Account name")
+    ),
+    responses(
+        (status = 200, description = "Close directive removed"),
+        (status = 404, description = "Account is not closed"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn reopen_account(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::reopen_account(&state.data_dir, &name)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::OK)
+    .map_err(|e| {
+        tracing::error!("Failed to reopen account: {}", e);
+        ApiError::from_ledger(&e)
     })
 }
 
 #[utoipa::path(
     get,
-    path = "/verify",
+    path = "/accounts/{name}",
+    params(
+        ("name" = String, Path, description = "Account name")
+    ),
     responses(
-        (status = 200, description = "Verify ledger", body = VerifyResult),
+        (status = 200, description = "The account with its balance, usage and children", body = AccountDetail),
+        (status = 404, description = "Account not found"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn verify_ledger(State(state): State<Arc<AppState>>) -> Result<Json<VerifyResult>, (StatusCode, String)> {
-    let data_dir = state.data_dir.clone();
-    tokio::task::spawn_blocking(move || beancount::verify(&data_dir))
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task join error: {}", e)))?
-        .map(Json)
-        .map_err(|e| {
-            tracing::error!("Failed to verify ledger: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
-        })
+pub async fn get_account(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<AccountDetail>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::account_detail(&state.data_dir, &name)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to load account: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/balance",
+    params(
+        ("name" = String, Path, description = "Account name"),
+        BalanceQuery
+    ),
+    responses(
+        (status = 200, description = "Balance of the account and its sub-accounts", body = AccountBalance),
+        (status = 404, description = "Account not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn account_balance(State(state): State<Arc<AppState>>, Path(name): Path<String>, Query(query): Query<BalanceQuery>) -> Result<Json<AccountBalance>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::account_balance(&state.data_dir, &name, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to compute account balance: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/assertion-check",
+    params(
+        ("name" = String, Path, description = "Account name"),
+        AssertionCheckQuery
+    ),
+    responses(
+        (status = 200, description = "Whether a balance assertion would pass", body = AssertionCheck),
+        (status = 400, description = "Invalid date or amount"),
+        (status = 404, description = "Account not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn check_assertion(State(state): State<Arc<AppState>>, Path(name): Path<String>, Query(query): Query<AssertionCheckQuery>) -> Result<Json<AssertionCheck>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::check_assertion(&state.data_dir, &name, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to check balance assertion: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/balances/assertions",
+    responses(
+        (status = 200, description = "Every balance directive, by date", body = Vec<BalanceAssertion>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_balance_assertions(State(state): State<Arc<AppState>>) -> Result<Json<Vec<BalanceAssertion>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::list_balance_assertions(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list balance assertions: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/balances/assertions",
+    request_body = BalanceAssertion,
+    responses(
+        (status = 201, description = "Balance assertion written"),
+        (status = 400, description = "Invalid date, account, amount or currency"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn add_balance_assertion(State(state): State<Arc<AppState>>, JsonBody(payload): JsonBody<BalanceAssertion>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::add_balance_assertion(&state.data_dir, state.config.file_scheme, &payload)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::CREATED)
+    .map_err(|e| {
+        tracing::error!("Failed to add balance assertion: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/directives",
+    params(DirectiveQuery),
+    responses(
+        (status = 200, description = "Non-transaction directives, by date", body = Vec<Directive>),
+        (status = 400, description = "Unknown directive type"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_directives(State(state): State<Arc<AppState>>, Query(query): Query<DirectiveQuery>) -> Result<Json<Vec<Directive>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::list_directives(&state.data_dir, query.kind.as_deref())
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list directives: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    delete,
+    path = "/directives/{id}",
+    params(
+        ("id" = String, Path, description = "Directive ID (file:offset)")
+    ),
+    responses(
+        (status = 200, description = "Directive deleted"),
+        (status = 400, description = "The ID points at a transaction"),
+        (status = 404, description = "Directive not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn delete_directive(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::delete_directive(&state.data_dir, &id)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::OK)
+    .map_err(|e| {
+        tracing::error!("Failed to delete directive: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/pads",
+    request_body = Pad,
+    responses(
+        (status = 201, description = "Pad written"),
+        (status = 400, description = "Invalid date or account name"),
+        (status = 422, description = "An account isn't open on the pad's date"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn add_pad(State(state): State<Arc<AppState>>, JsonBody(payload): JsonBody<Pad>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::add_pad(&state.data_dir, state.config.file_scheme, &payload)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::CREATED)
+    .map_err(|e| {
+        tracing::error!("Failed to add pad: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/notes",
+    params(
+        ("name" = String, Path, description = "Account name")
+    ),
+    responses(
+        (status = 200, description = "Notes attached to the account, by date", body = Vec<Note>),
+        (status = 404, description = "Account not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_notes(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<Vec<Note>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::list_notes(&state.data_dir, &name)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list notes: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/accounts/{name}/notes",
+    params(
+        ("name" = String, Path, description = "Account name")
+    ),
+    request_body = NoteRequest,
+    responses(
+        (status = 201, description = "Note written"),
+        (status = 400, description = "Invalid date or a control character in the comment"),
+        (status = 404, description = "Account not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn add_note(State(state): State<Arc<AppState>>, Path(name): Path<String>, JsonBody(payload): JsonBody<NoteRequest>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::add_note(&state.data_dir, state.config.file_scheme, &name, &payload)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::CREATED)
+    .map_err(|e| {
+        tracing::error!("Failed to add note: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/documents",
+    params(
+        ("name" = String, Path, description = "Account name")
+    ),
+    responses(
+        (status = 200, description = "Documents linked to the account, by date", body = Vec<Document>),
+        (status = 404, description = "Account not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_documents(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<Vec<Document>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::list_documents(&state.data_dir, &name)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list documents: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/accounts/{name}/documents",
+    params(
+        ("name" = String, Path, description = "Account name")
+    ),
+    request_body(content = String, content_type = "multipart/form-data", description = "A `date` part (YYYY-MM-DD) and a `file` part with a file name"),
+    responses(
+        (status = 201, description = "Stored path of the document, relative to the data directory", body = String),
+        (status = 400, description = "Missing part, invalid date or unsafe file name"),
+        (status = 404, description = "Account not found"),
+        (status = 409, description = "A document with that name is already stored for the date"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn add_document(State(state): State<Arc<AppState>>, Path(name): Path<String>, mut multipart: Multipart) -> Result<impl IntoResponse, ApiError> {
+    let bad_request = |e: String| ApiError::bad_request(e);
+    let mut date = None;
+    let mut file = None;
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("date") => date = Some(field.text().await.map_err(multipart_error)?),
+            Some("file") => {
+                let filename = field.file_name().map(str::to_string).ok_or_else(|| bad_request("The 'file' part needs a file name".to_string()))?;
+                file = Some((filename, field.bytes().await.map_err(multipart_error)?));
+            }
+            _ => {}
+        }
+    }
+    let date = date.ok_or_else(|| bad_request("Missing 'date' part".to_string()))?;
+    let (filename, bytes) = file.ok_or_else(|| bad_request("Missing 'file' part".to_string()))?;
+
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::add_document(&state.data_dir, state.config.file_scheme, &name, date.trim(), &filename, &bytes)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|path| (StatusCode::CREATED, Json(path)))
+    .map_err(|e| {
+        tracing::error!("Failed to store document: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+/// Content type for a stored document, by extension. Anything a browser
+/// would run, like HTML, is served as plain bytes.
+fn document_content_type(path: &std::path::Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "json" => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Documents are always downloaded rather than shown inline, with the
+/// file name reduced to characters that are safe in the header.
+fn document_disposition(path: &std::path::Path) -> String {
+    let name: String = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c == ' ' || (c.is_ascii_graphic() && c != '"' && c != '\\') { c } else { '_' })
+        .collect();
+    format!("attachment; filename=\"{}\"", name)
+}
+
+#[utoipa::path(
+    get,
+    path = "/documents/{path}",
+    params(
+        ("path" = String, Path, description = "Path below documents/, e.g. Assets/Bank/2024-05-01-statement.pdf")
+    ),
+    responses(
+        (status = 200, description = "The document's contents, as an attachment", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 400, description = "Path leaves the documents directory"),
+        (status = 404, description = "Document not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn get_document(State(state): State<Arc<AppState>>, Path(path): Path<String>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+        let _lock = state.lock_for_read();
+        // Stored paths start with `documents/`, so `/{stored path}` is the URL.
+        let path = beancount::document_path(&state.data_dir, &format!("documents/{}", path))?;
+        Ok((document_content_type(&path), document_disposition(&path), std::fs::read(&path)?))
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|(content_type, disposition, bytes)| {
+        let headers = [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ];
+        (headers, bytes)
+    })
+    .map_err(|e| {
+        tracing::error!("Failed to read document: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/register",
+    params(
+        ("name" = String, Path, description = "Account name"),
+        RegisterQuery,
+        Page
+    ),
+    responses(
+        (status = 200, description = "Postings to the account with a running balance", body = Vec<RegisterRow>),
+        (status = 400, description = "Too many items without pagination"),
+        (status = 404, description = "Account was never opened"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn account_register(State(state): State<Arc<AppState>>, Path(name): Path<String>, Query(query): Query<RegisterQuery>, Query(page): Query<Page>) -> Result<Json<Vec<RegisterRow>>, ApiError> {
+    let state = state.clone();
+    let max = state.config.max_response_items;
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        paginate(reports::register(&state.data_dir, &name, &query)?, &page, max)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build account register: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/balances",
+    params(BalanceQuery, Page),
+    responses(
+        (status = 200, description = "Balance of every account", body = Vec<AccountBalance>),
+        (status = 400, description = "Invalid date or depth, or too many items without pagination"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_balances(State(state): State<Arc<AppState>>, Query(query): Query<BalanceQuery>, Query(page): Query<Page>) -> Result<Json<Vec<AccountBalance>>, ApiError> {
+    let state = state.clone();
+    let max = state.config.max_response_items;
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        paginate(reports::balance_report(&state.data_dir, &query)?, &page, max)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to compute balances: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/verify",
+    params(VerifyQuery),
+    responses(
+        (status = 200, description = "Verify ledger", body = VerifyResult),
+        (status = 400, description = "Invalid tolerance"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn verify_ledger(State(state): State<Arc<AppState>>, Query(query): Query<VerifyQuery>) -> Result<Json<VerifyResult>, ApiError> {
+    let tolerance = match query.tolerance.as_deref() {
+        Some(t) => beancount::parse_amount(t).map_err(|e| ApiError::from_ledger(&e))?,
+        None => reports::DEFAULT_TOLERANCE,
+    };
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || state.verify(tolerance))
+        .await
+        .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Failed to verify ledger: {}", e);
+            ApiError::from_ledger(&e)
+        })
+}
+
+#[utoipa::path(
+    get,
+    path = "/verify/lots",
+    responses(
+        (status = 200, description = "Lot reductions with no matching holding", body = Vec<LotIssue>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn verify_lots(State(state): State<Arc<AppState>>) -> Result<Json<Vec<LotIssue>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::check_lots(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to verify lots: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/activity",
+    params(ActivityQuery, Page),
+    responses(
+        (status = 200, description = "Postings per calendar day", body = Vec<ActivityDay>),
+        (status = 400, description = "Too many items without pagination"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn activity_report(State(state): State<Arc<AppState>>, Query(query): Query<ActivityQuery>, Query(page): Query<Page>) -> Result<Json<Vec<ActivityDay>>, ApiError> {
+    let state = state.clone();
+    let max = state.config.max_response_items;
+    tokio::task::spawn_blocking(move || paginate(state.activity(&query)?, &page, max))
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build activity report: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/income",
+    params(IncomeStatementQuery),
+    responses(
+        (status = 200, description = "Income, expenses and net income for the period", body = IncomeStatement),
+        (status = 400, description = "Invalid date"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn income_report(State(state): State<Arc<AppState>>, Query(query): Query<IncomeStatementQuery>) -> Result<Json<IncomeStatement>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::income_statement(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build income statement: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/income-statement",
+    params(IncomeStatementPeriodsQuery),
+    responses(
+        (status = 200, description = "Income, expenses, net income and per-account amounts for each period", body = IncomeStatementPeriods),
+        (status = 400, description = "Invalid date, interval or depth, or a range of more than 1200 intervals"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn income_statement_periods(State(state): State<Arc<AppState>>, Query(query): Query<IncomeStatementPeriodsQuery>) -> Result<Json<IncomeStatementPeriods>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::income_statement_periods(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build income statement: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/monthly",
+    params(MonthlyQuery),
+    responses(
+        (status = 200, description = "Per-month totals for the account subtree, zero months included", body = MonthlyReport),
+        (status = 400, description = "Invalid date, or a range of more than 1200 months"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn monthly_report(State(state): State<Arc<AppState>>, Query(query): Query<MonthlyQuery>) -> Result<Json<MonthlyReport>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::monthly(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build monthly report: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/net-worth",
+    params(NetWorthQuery),
+    responses(
+        (status = 200, description = "Assets, liabilities and net worth at the end of each interval, with warnings for missing prices", body = NetWorthSeries),
+        (status = 400, description = "Invalid date or interval, or a range of more than 1200 intervals"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn net_worth(State(state): State<Arc<AppState>>, Query(query): Query<NetWorthQuery>) -> Result<Json<NetWorthSeries>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::net_worth(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build net worth series: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/networth",
+    params(MonthEndNetWorthQuery),
+    responses(
+        (status = 200, description = "Assets plus liabilities at each month-end", body = Vec<DatedAmount>),
+        (status = 400, description = "Invalid date"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn month_end_net_worth(State(state): State<Arc<AppState>>, Query(query): Query<MonthEndNetWorthQuery>) -> Result<Json<Vec<DatedAmount>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::month_end_net_worth(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build net worth series: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/spending",
+    params(SpendingQuery),
+    responses(
+        (status = 200, description = "Totals, transaction counts and averages per group, largest first", body = Vec<SpendingGroup>),
+        (status = 400, description = "Invalid date, group_by or depth"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn spending_report(State(state): State<Arc<AppState>>, Query(query): Query<SpendingQuery>) -> Result<Json<Vec<SpendingGroup>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::spending(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build spending report: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/holdings",
+    params(HoldingsQuery),
+    responses(
+        (status = 200, description = "Holdings at cost per account and commodity, with lots, market value and unrealized gain", body = Holdings),
+        (status = 400, description = "Invalid date"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn holdings_report(State(state): State<Arc<AppState>>, Query(query): Query<HoldingsQuery>) -> Result<Json<Holdings>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::holdings(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build holdings report: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/budgets",
+    responses(
+        (status = 200, description = "The latest monthly budget for each account", body = [Budget]),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_budgets(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Budget>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<Budget>> {
+        let _lock = state.lock_for_read();
+        let mut latest = std::collections::BTreeMap::new();
+        for budget in beancount::list_budgets(&state.data_dir)? {
+            latest.insert(budget.account.clone(), budget);
+        }
+        Ok(latest.into_values().collect())
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list budgets: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    put,
+    path = "/budgets/{account}",
+    params(
+        ("account" = String, Path, description = "Expenses account, e.g. Expenses:Food")
+    ),
+    request_body = BudgetRequest,
+    responses(
+        (status = 200, description = "Budget appended to budgets.bean; earlier months keep their budgets", body = Budget),
+        (status = 400, description = "Invalid account, amount, currency or date"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn set_budget(State(state): State<Arc<AppState>>, Path(account): Path<String>, JsonBody(payload): JsonBody<BudgetRequest>) -> Result<Json<Budget>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::set_budget(&state.data_dir, &account, &payload)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to set budget: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/budget",
+    params(BudgetQuery),
+    responses(
+        (status = 200, description = "Budgeted, actual, remaining and percentage used per budgeted account, plus unbudgeted spending", body = BudgetReport),
+        (status = 400, description = "Invalid period"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn budget_report(State(state): State<Arc<AppState>>, Query(query): Query<BudgetQuery>) -> Result<Json<BudgetReport>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::budget(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build budget report: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/compare",
+    params(CompareQuery),
+    responses(
+        (status = 200, description = "Per-account totals for both periods with the change, nested by account", body = CompareReport),
+        (status = 400, description = "Invalid period"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn compare_report(State(state): State<Arc<AppState>>, Query(query): Query<CompareQuery>) -> Result<Json<CompareReport>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::compare(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build comparison: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/cash-flow",
+    params(CashFlowQuery),
+    responses(
+        (status = 200, description = "Inflows and outflows by counterpart account, with transfers between asset and liability accounts apart", body = CashFlow),
+        (status = 400, description = "Invalid date"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn cash_flow(State(state): State<Arc<AppState>>, Query(query): Query<CashFlowQuery>) -> Result<Json<CashFlow>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::cash_flow(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build cash flow report: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/trial-balance",
+    params(TrialBalanceQuery),
+    responses(
+        (status = 200, description = "Debit and credit balances per account, with totals and any residual", body = TrialBalance),
+        (status = 400, description = "Invalid date"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn trial_balance(State(state): State<Arc<AppState>>, Query(query): Query<TrialBalanceQuery>) -> Result<Json<TrialBalance>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::trial_balance(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build trial balance: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/balance-sheet",
+    params(BalanceSheetQuery),
+    responses(
+        (status = 200, description = "Assets, liabilities and equity as of a date", body = BalanceSheet),
+        (status = 400, description = "Invalid date or tolerance"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn balance_sheet_report(State(state): State<Arc<AppState>>, Query(query): Query<BalanceSheetQuery>) -> Result<Json<BalanceSheet>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        reports::balance_sheet(&state.data_dir, &query)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build balance sheet: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/config",
+    responses(
+        (status = 200, description = "Active server configuration", body = ConfigInfo)
+    )
+)]
+pub async fn get_config(State(state): State<Arc<AppState>>) -> Json<ConfigInfo> {
+    Json(ConfigInfo {
+        narration_default: state.config.narration_default.to_string(),
+        file_scheme: state.config.file_scheme.to_string(),
+        validation_profile: state.config.validation_profile.clone(),
+        available_profiles: state.config.available_profiles.clone(),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/format",
+    request_body = FormatRequest,
+    responses(
+        (status = 200, description = "The text reformatted like bean-format", body = FormatResult),
+        (status = 400, description = "Neither or both of text and file, an invalid file path, or a column over 200"),
+        (status = 404, description = "File not found"),
+        (status = 422, description = "The text doesn't parse"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn format_ledger(State(state): State<Arc<AppState>>, JsonBody(payload): JsonBody<FormatRequest>) -> Result<Json<FormatResult>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || -> anyhow::Result<FormatResult> {
+        if payload.column.is_some_and(|c| c > format::MAX_COLUMN) {
+            return Err(LedgerError::invalid(format!("column must be at most {}", format::MAX_COLUMN)).into());
+        }
+        let text = match (payload.text, payload.file) {
+            (Some(text), None) => text,
+            (None, Some(file)) => {
+                let _lock = state.lock_for_read();
+                std::fs::read_to_string(beancount::ledger_file_path(&state.data_dir, &file)?)?
+            }
+            _ => return Err(LedgerError::invalid("Give exactly one of text and file").into()),
+        };
+        Ok(FormatResult {
+            text: format::format(&text, payload.column)?,
+        })
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to format: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/ledger/options",
+    responses(
+        (status = 200, description = "Options and plugins declared in main.bean", body = LedgerOptions),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn ledger_options(State(state): State<Arc<AppState>>) -> Result<Json<LedgerOptions>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::ledger_options(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to read options: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    put,
+    path = "/ledger/options/{key}",
+    params(
+        ("key" = String, Path, description = "Option name, e.g. operating_currency")
+    ),
+    request_body = OptionValue,
+    responses(
+        (status = 200, description = "Option set in main.bean, replacing the existing line if there is one"),
+        (status = 400, description = "Invalid option name or value"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn set_option(State(state): State<Arc<AppState>>, Path(key): Path<String>, JsonBody(payload): JsonBody<OptionValue>) -> Result<StatusCode, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::set_option(&state.data_dir, &key, &payload.value)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::OK)
+    .map_err(|e| {
+        tracing::error!("Failed to set option: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/import/csv",
+    params(ImportQuery),
+    request_body(content = String, content_type = "multipart/form-data", description = "Either multipart with a `file` part (CSV) and a `mapping` part (JSON CsvMapping), or a raw `text/csv` body with the mapping JSON in the `mapping` query parameter"),
+    responses(
+        (status = 200, description = "Imported (or previewed) transactions and per-row errors", body = ImportResult),
+        (status = 400, description = "Missing or malformed upload"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn import_csv(State(state): State<Arc<AppState>>, Query(query): Query<ImportQuery>, request: Request) -> Result<Json<ImportResult>, ApiError> {
+    let bad_request = |e: String| ApiError::bad_request(e);
+    let parse_mapping = |bytes: &[u8]| -> Result<CsvMapping, ApiError> {
+        serde_json::from_slice(bytes).map_err(|e| bad_request(format!("Invalid mapping: {}", e)))
+    };
+
+    let is_multipart = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"));
+
+    let mut data = None;
+    let mut mapping = query.mapping.as_deref().map(|m| parse_mapping(m.as_bytes())).transpose()?;
+    if is_multipart {
+        let mut multipart = Multipart::from_request(request, &state)
+            .await
+            .map_err(|e| body_error(e.status(), e.body_text()))?;
+        while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+            let name = field.name().map(str::to_string);
+            let bytes = field.bytes().await.map_err(multipart_error)?;
+            match name.as_deref() {
+                Some("file") => data = Some(bytes),
+                Some("mapping") => mapping = Some(parse_mapping(&bytes)?),
+                _ => {}
+            }
+        }
+    } else {
+        let bytes = axum::body::to_bytes(request.into_body(), state.config.max_body_bytes)
+            .await
+            .map_err(|e| {
+                let message = e.to_string();
+                if is_length_limit(e) { ApiError::too_large() } else { bad_request(message) }
+            })?;
+        data = Some(bytes);
+    }
+    let data = data.ok_or_else(|| bad_request("Missing 'file' part".to_string()))?;
+    let mapping = mapping.ok_or_else(|| bad_request("Missing mapping".to_string()))?;
+
+    let (rows, errors) = import::parse_csv(&data, &mapping, state.config.decimal_separator).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || -> anyhow::Result<ImportResult> {
+        let _lock = state.lock_for_write();
+        let result = import::import_rows(&state.data_dir, &state.config, &mapping, rows, errors, query.dry_run, query.auto_open)?;
+        for id in result.transactions.iter().filter_map(|t| t.transaction.id.clone()) {
+            state.transactions_added.send(id).ok();
+        }
+        Ok(result)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to import CSV: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[cfg(feature = "fault-injection")]
+pub async fn get_faults() -> Json<crate::fsio::faults::FaultPlan> {
+    Json(crate::fsio::faults::get())
+}
+
+#[cfg(feature = "fault-injection")]
+pub async fn set_faults(Json(plan): Json<crate::fsio::faults::FaultPlan>) -> StatusCode {
+    crate::fsio::faults::set(plan);
+    StatusCode::OK
+}
+
+#[utoipa::path(
+    get,
+    path = "/import/rules",
+    responses(
+        (status = 200, description = "Import categorization rules", body = Vec<ImportRule>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn get_import_rules(State(state): State<Arc<AppState>>) -> Result<Json<Vec<ImportRule>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        rules::load_rules(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to load import rules: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    put,
+    path = "/import/rules",
+    request_body = Vec<ImportRule>,
+    responses(
+        (status = 200, description = "Import rules replaced"),
+        (status = 422, description = "A rule has no pattern or an invalid regex"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn put_import_rules(State(state): State<Arc<AppState>>, JsonBody(payload): JsonBody<Vec<ImportRule>>) -> Result<impl IntoResponse, ApiError> {
+    rules::compile_rules(&payload).map_err(|e| ApiError::from_status(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))?;
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        rules::save_rules(&state.data_dir, &payload)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::OK)
+    .map_err(|e| {
+        tracing::error!("Failed to save import rules: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/export/beancount",
+    responses(
+        (status = 200, description = "The whole ledger flattened into one file", content_type = "text/plain", body = String),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn export_beancount(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    let text = tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::export_flat(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map_err(|e| {
+        tracing::error!("Failed to export ledger: {}", e);
+        ApiError::from_ledger(&e)
+    })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"ledger.bean\""),
+        ],
+        text,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/payees",
+    params(PayeeQuery),
+    responses(
+        (status = 200, description = "Payees with usage counts, most used first", body = Vec<PayeeInfo>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_payees(State(state): State<Arc<AppState>>, Query(query): Query<PayeeQuery>) -> Result<Json<Vec<PayeeInfo>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::list_payees(&state.data_dir, query.q.as_deref())
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list payees: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/autocomplete",
+    params(AutocompleteQuery),
+    responses(
+        (status = 200, description = "Ranked suggestions for an entry form field", body = Vec<Suggestion>),
+        (status = 400, description = "Unknown field"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn autocomplete(State(state): State<Arc<AppState>>, Query(query): Query<AutocompleteQuery>) -> Result<Json<Vec<Suggestion>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        autocomplete::suggest(&state.data_dir, &query.field, &query.q, query.limit.unwrap_or(10))
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to autocomplete: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/complete",
+    params(AccountCompleteQuery),
+    responses(
+        (status = 200, description = "Account names starting with the prefix, most used first", body = Vec<AccountCompletion>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn complete_accounts(State(state): State<Arc<AppState>>, Query(query): Query<AccountCompleteQuery>) -> Result<Json<Vec<AccountCompletion>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        autocomplete::complete_accounts(&state.data_dir, &query.prefix, query.limit.unwrap_or(20))
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to complete accounts: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/suggest/account",
+    params(AccountSuggestionQuery),
+    responses(
+        (status = 200, description = "Non-asset accounts the payee's transactions post to, most used first", body = Vec<AccountSuggestion>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn suggest_account(State(state): State<Arc<AppState>>, Query(query): Query<AccountSuggestionQuery>) -> Result<Json<Vec<AccountSuggestion>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        autocomplete::suggest_account(&state.data_dir, &query.payee, query.limit.unwrap_or(5))
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to suggest account: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/payees/complete",
+    params(PayeeCompleteQuery),
+    responses(
+        (status = 200, description = "Payees starting with the prefix, with use counts and last-used dates", body = Vec<PayeeInfo>),
+        (status = 400, description = "Unknown order"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn complete_payees(State(state): State<Arc<AppState>>, Query(query): Query<PayeeCompleteQuery>) -> Result<Json<Vec<PayeeInfo>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        autocomplete::complete_payees(&state.data_dir, &query.prefix, query.order.as_deref(), query.limit.unwrap_or(20))
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to complete payees: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/payees/{name}/last",
+    params(
+        ("name" = String, Path, description = "Payee (case-insensitive)")
+    ),
+    responses(
+        (status = 200, description = "Most recent transaction for the payee and a template of it", body = PayeeLast),
+        (status = 404, description = "No transactions for this payee"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn last_for_payee(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<PayeeLast>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::last_for_payee(&state.data_dir, &name)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to find last transaction for payee: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats",
+    responses(
+        (status = 200, description = "Ledger overview: counts, date range and transactions per year and recent month", body = LedgerStats),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn stats(State(state): State<Arc<AppState>>) -> Result<Json<LedgerStats>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::stats(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to compute stats: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Server counters", body = Metrics)
+    )
+)]
+pub async fn metrics(State(state): State<Arc<AppState>>) -> Json<Metrics> {
+    Json(state.metrics())
+}
+
+#[utoipa::path(
+    get,
+    path = "/tags",
+    responses(
+        (status = 200, description = "Distinct tags with usage counts and date ranges", body = Vec<TagInfo>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_tags(State(state): State<Arc<AppState>>) -> Result<Json<Vec<TagInfo>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::list_tags(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list tags: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/currencies",
+    responses(
+        (status = 200, description = "Currencies used in postings or declared as commodities", body = Vec<CurrencyInfo>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_currencies(State(state): State<Arc<AppState>>) -> Result<Json<Vec<CurrencyInfo>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::list_currencies(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list currencies: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/commodities",
+    responses(
+        (status = 200, description = "Declared commodities with their display metadata", body = Vec<Commodity>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_commodities(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Commodity>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::list_commodities(&state.data_dir)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list commodities: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/commodities",
+    request_body = Commodity,
+    responses(
+        (status = 201, description = "Commodity declared in commodities.bean"),
+        (status = 400, description = "Invalid date, currency or precision"),
+        (status = 409, description = "The commodity is already declared"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn add_commodity(State(state): State<Arc<AppState>>, JsonBody(payload): JsonBody<Commodity>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::add_commodity(&state.data_dir, &payload)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::CREATED)
+    .map_err(|e| {
+        tracing::error!("Failed to add commodity: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/ws",
+    responses(
+        (status = 101, description = "WebSocket sending a LedgerChange message whenever ledger files change", body = crate::watch::LedgerChange)
+    )
+)]
+pub async fn ledger_updates(State(state): State<Arc<AppState>>, ws: WebSocketUpgrade) -> impl IntoResponse {
+    let changes = state.changes.subscribe();
+    ws.on_upgrade(move |socket| forward_changes(socket, changes))
+}
+
+/// Relays ledger changes to one client until either side goes away.
+async fn forward_changes(mut socket: WebSocket, mut changes: tokio::sync::broadcast::Receiver<crate::watch::LedgerChange>) {
+    loop {
+        tokio::select! {
+            change = changes.recv() => {
+                let change = match change {
+                    Ok(change) => change,
+                    // A slow client missed some changes; the next one still
+                    // tells it to refresh.
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                let Ok(text) = serde_json::to_string(&change) else { continue };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/prices",
+    params(PriceQuery),
+    responses(
+        (status = 200, description = "Price directives, by date", body = Vec<Price>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_prices(State(state): State<Arc<AppState>>, Query(query): Query<PriceQuery>) -> Result<Json<Vec<Price>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<Price>> {
+        let _lock = state.lock_for_read();
+        let prices = beancount::list_prices(&state.data_dir)?;
+        Ok(prices
+            .into_iter()
+            .filter(|p| query.currency.as_ref().is_none_or(|c| *c == p.currency))
+            .collect())
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to list prices: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/prices",
+    request_body = Price,
+    responses(
+        (status = 201, description = "Price written to prices.bean"),
+        (status = 400, description = "Invalid date, currency or amount"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn add_price(State(state): State<Arc<AppState>>, JsonBody(payload): JsonBody<Price>) -> Result<impl IntoResponse, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_write();
+        beancount::add_price(&state.data_dir, &payload)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(|_| StatusCode::CREATED)
+    .map_err(|e| {
+        tracing::error!("Failed to add price: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/prices/fetch",
+    responses(
+        (status = 200, description = "What happened for each commodity with a `price:` source; one failing doesn't fail the rest", body = Vec<PriceFetchResult>),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn fetch_prices(State(state): State<Arc<AppState>>) -> Result<Json<Vec<PriceFetchResult>>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || quotes::fetch_prices(&state))
+        .await
+        .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Failed to fetch prices: {}", e);
+            ApiError::from_ledger(&e)
+        })
+}
+
+#[utoipa::path(
+    get,
+    path = "/prices/latest",
+    params(LatestPriceQuery),
+    responses(
+        (status = 200, description = "The most recent price on or before the date", body = Price),
+        (status = 400, description = "Invalid date"),
+        (status = 404, description = "No price for the pair"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn latest_price(State(state): State<Arc<AppState>>, Query(query): Query<LatestPriceQuery>) -> Result<Json<Price>, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let _lock = state.lock_for_read();
+        beancount::latest_price(&state.data_dir, &query.base, &query.quote, query.at.as_deref())
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to look up price: {}", e);
+        ApiError::from_ledger(&e)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/events/stream",
+    responses(
+        (status = 200, description = "Server-sent events: a `transaction_added` event with the new transaction's ID as data for every transaction added through the API", content_type = "text/event-stream", body = String)
+    )
+)]
+pub async fn transaction_events(State(state): State<Arc<AppState>>) -> Sse<impl Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let stream = BroadcastStream::new(state.transactions_added.subscribe())
+        // A client that fell behind skips the IDs it missed.
+        .filter_map(|id| id.ok())
+        .map(|id| Ok(Event::default().event("transaction_added").data(id)));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::model::Posting;
+
+    fn test_state(dir: &std::path::Path) -> Arc<AppState> {
+        test_state_with(dir, |_| {})
+    }
+
+    fn test_state_with(dir: &std::path::Path, configure: impl FnOnce(&mut Config)) -> Arc<AppState> {
+        let mut config = Config::for_tests(dir);
+        configure(&mut config);
+        Arc::new(AppState::new(config).unwrap())
+    }
+
+    fn posting(account: &str, amount: &str) -> Posting {
+        Posting {
+            flag: None,
+            account: account.to_string(),
+            amount: amount.to_string(),
+            currency: "USD".to_string(),
+            cost: None,
+            price: None,
+        }
+    }
+
+    fn transaction(date: &str, narration: &str, postings: Vec<Posting>) -> Transaction {
+        Transaction {
+            id: None,
+            date: date.to_string(),
+            flag: "*".to_string(),
+            payee: None,
+            narration: Some(narration.to_string()),
+            tags: Vec::new(),
+            postings,
+            document: None,
+        }
+    }
+
+    fn lunch() -> Transaction {
+        transaction("2024-03-01", "Lunch", vec![posting("Expenses:Food", "12.50"), posting("Assets:Cash", "-12.50")])
+    }
+
+    async fn post(state: &Arc<AppState>, tx: Transaction) -> Result<(), ApiError> {
+        add_transaction(State(state.clone()), Query(WriteQuery::default()), JsonBody(tx)).await.map(|_| ())
+    }
+
+    async fn list(state: &Arc<AppState>) -> Vec<Transaction> {
+        let Json(txs) = list_transactions(State(state.clone()), Query(TransactionQuery::default()), Query(Page::default()))
+            .await
+            .unwrap();
+        txs
+    }
+
+    #[tokio::test]
+    async fn update_cycle_uses_the_configured_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+
+        post(&state, lunch()).await.unwrap();
+        assert!(dir.path().join("2024-03.bean").exists());
+        let txs = list(&state).await;
+        assert_eq!(txs.len(), 1);
+        let id = txs[0].id.clone().unwrap();
+
+        let mut changed = txs[0].clone();
+        changed.narration = Some("Dinner".to_string());
+        update_transaction(State(state.clone()), Path(id.clone()), Query(WriteQuery::default()), JsonBody(changed))
+            .await
+            .unwrap();
+        let txs = list(&state).await;
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0].id.as_deref(), Some(id.as_str()));
+        assert_eq!(txs[0].narration.as_deref(), Some("Dinner"));
+
+        delete_transaction(State(state.clone()), Path(id)).await.unwrap();
+        assert!(list(&state).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn quoted_narration_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let mut tx = lunch();
+        tx.narration = Some(r#"He said "hi""#.to_string());
+        post(&state, tx).await.unwrap();
+        assert_eq!(list(&state).await[0].narration.as_deref(), Some(r#"He said "hi""#));
+    }
+
+    #[tokio::test]
+    async fn newline_in_narration_cannot_add_a_directive() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let mut tx = lunch();
+        tx.narration = Some("Lunch\n2024-01-01 open Assets:Evil".to_string());
+        let err = post(&state, tx).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        assert!(list(&state).await.is_empty());
+        let accounts = beancount::list_accounts(&state.data_dir).unwrap();
+        assert!(accounts.iter().all(|a| a.name != "Assets:Evil"));
+    }
+
+    #[tokio::test]
+    async fn long_lists_must_be_paginated() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state_with(dir.path(), |config| config.max_response_items = Some(1));
+        post(&state, lunch()).await.unwrap();
+        post(&state, lunch()).await.unwrap();
+
+        let err = list_transactions(State(state.clone()), Query(TransactionQuery::default()), Query(Page::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        assert!(err.error.contains("limit"));
+
+        let page = Page { limit: Some(1), offset: Some(1) };
+        let Json(txs) = list_transactions(State(state.clone()), Query(TransactionQuery::default()), Query(page))
+            .await
+            .unwrap();
+        assert_eq!(txs.len(), 1);
+
+        let too_big = Page { limit: Some(2), offset: None };
+        assert!(list_transactions(State(state), Query(TransactionQuery::default()), Query(too_big)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn comma_decimal_amounts_are_normalized() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state_with(dir.path(), |config| config.decimal_separator = ',');
+        let tx = transaction("2024-03-01", "Lunch", vec![posting("Expenses:Food", "20,00"), posting("Assets:Cash", "-20,00")]);
+        post(&state, tx).await.unwrap();
+        let txs = list(&state).await;
+        assert_eq!(txs[0].postings[0].amount, "20.00");
+
+        let ambiguous = transaction("2024-03-02", "Lunch", vec![posting("Expenses:Food", "1.000,50"), posting("Assets:Cash", "-1.000,50")]);
+        let err = post(&state, ambiguous).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        assert_eq!(list(&state).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_write_is_a_500_with_nothing_written() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.bean"), "option \"title\" \"Test\"\n").unwrap();
+        let state = test_state(dir.path());
+        let plan = crate::fsio::faults::FaultPlan {
+            fail_nth_write: Some(1),
+            path: Some(dir.path().join("main.bean").to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+
+        let injected = crate::fsio::faults::inject(plan).await;
+        let err = post(&state, lunch()).await.unwrap_err();
+        drop(injected);
+
+        assert_eq!(err.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(list(&state).await.is_empty());
+        assert_eq!(std::fs::read_to_string(dir.path().join("main.bean")).unwrap(), "option \"title\" \"Test\"\n");
+        assert!(!dir.path().join("2024-03.bean").exists());
+    }
+
+    #[tokio::test]
+    async fn documents_are_downloads_never_inline_html() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("documents/Assets")).unwrap();
+        std::fs::write(dir.path().join("documents/Assets/statement.html"), "<script>alert(1)</script>").unwrap();
+        std::fs::write(dir.path().join("documents/Assets/receipt \"1\".pdf"), "%PDF").unwrap();
+        let state = test_state(dir.path());
+
+        let get = |path: &str| get_document(State(state.clone()), Path(path.to_string()));
+        let response = get("Assets/statement.html").await.unwrap().into_response();
+        let headers = response.headers();
+        assert_eq!(headers[header::CONTENT_TYPE], "application/octet-stream");
+        assert_eq!(headers[header::CONTENT_DISPOSITION], "attachment; filename=\"statement.html\"");
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+
+        let response = get("Assets/receipt \"1\".pdf").await.unwrap().into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/pdf");
+        assert_eq!(response.headers()[header::CONTENT_DISPOSITION], "attachment; filename=\"receipt _1_.pdf\"");
+    }
 }
@@ -0,0 +1,74 @@
+//! Optional bearer-token auth: with an API token configured, every request
+//! outside the docs and health check must carry `Authorization: Bearer
+//! <token>`. The streaming endpoints also take it from a `token` query
+//! parameter or a `beancounters_token` cookie, as browsers can't set headers
+//! on WebSocket and `EventSource` connections.
+
+use crate::error::ApiError;
+use crate::state::AppState;
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Paths served without a token.
+fn is_public(path: &str) -> bool {
+    path == "/health" || path == "/references" || path == "/docs" || path.starts_with("/docs/")
+}
+
+/// Paths that also take the token outside the `Authorization` header.
+fn is_stream(path: &str) -> bool {
+    path == "/ws" || path == "/events/stream"
+}
+
+/// The token given as a `token` query parameter or `beancounters_token`
+/// cookie.
+fn stream_token(request: &Request) -> Option<String> {
+    let query = Query::<HashMap<String, String>>::try_from_uri(request.uri()).ok();
+    if let Some(token) = query.and_then(|Query(mut q)| q.remove("token")) {
+        return Some(token);
+    }
+    request
+        .headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == "beancounters_token")
+        .map(|(_, value)| value.to_string())
+}
+
+/// Compares in time independent of where the two first differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub async fn require_token(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(token) = state.config.api_token.as_deref() else {
+        return next.run(request).await;
+    };
+    if is_public(request.uri().path()) {
+        return next.run(request).await;
+    }
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+        || (is_stream(request.uri().path())
+            && stream_token(&request).is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())));
+    if !authorized {
+        return (
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            ApiError::from_status(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token"),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
@@ -0,0 +1,282 @@
+//! Ranked suggestions for entry forms. Each field has its own candidate
+//! extraction; ranking is shared: prefix matches beat substring matches, and
+//! within each group candidates used more often and more recently win.
+
+use crate::beancount;
+use crate::error::LedgerError;
+use crate::model::{Account, AccountCompletion, AccountSuggestion, PayeeInfo, Suggestion, Transaction};
+use anyhow::Result;
+use chrono::NaiveDate;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Days after which a use counts half as much.
+const HALF_LIFE_DAYS: f64 = 90.0;
+
+fn recency_weight(date: &str, today: NaiveDate) -> f64 {
+    let age = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_or(0, |d| (today - d).num_days().max(0));
+    0.5f64.powf(age as f64 / HALF_LIFE_DAYS)
+}
+
+/// Sums recency-weighted uses per candidate value.
+fn weighted_candidates(
+    transactions: &[Transaction],
+    today: NaiveDate,
+    values: impl Fn(&Transaction) -> Vec<String>,
+) -> HashMap<String, f64> {
+    let mut scores = HashMap::new();
+    for tx in transactions {
+        let weight = recency_weight(&tx.date, today);
+        for value in values(tx) {
+            *scores.entry(value).or_default() += weight;
+        }
+    }
+    scores
+}
+
+/// Filters candidates by `q` (case-insensitive) and orders them: prefix
+/// matches first, then by score, then alphabetically.
+pub fn rank(candidates: HashMap<String, f64>, q: &str, limit: usize) -> Vec<Suggestion> {
+    let q = q.to_lowercase();
+    let mut matches: Vec<(bool, Suggestion)> = candidates
+        .into_iter()
+        .filter_map(|(value, score)| {
+            let lower = value.to_lowercase();
+            if !lower.contains(&q) {
+                return None;
+            }
+            // Account segments count as word starts, so "food" finds
+            // Expenses:Food:Coffee as a prefix match.
+            let prefix = lower.starts_with(&q) || lower.split(':').any(|seg| seg.starts_with(&q));
+            Some((prefix, Suggestion { value, score }))
+        })
+        .collect();
+    matches.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then_with(|| b.1.score.total_cmp(&a.1.score))
+            .then_with(|| a.1.value.cmp(&b.1.value))
+    });
+    matches.into_iter().take(limit).map(|(_, s)| s).collect()
+}
+
+fn account_candidates(accounts: &[Account], transactions: &[Transaction], today: NaiveDate) -> HashMap<String, f64> {
+    let mut scores = weighted_candidates(transactions, today, |tx| {
+        tx.postings.iter().map(|p| p.account.clone()).collect()
+    });
+    for account in accounts.iter().filter(|a| a.close_date.is_none()) {
+        scores.entry(account.name.clone()).or_default();
+    }
+    scores
+}
+
+fn currency_candidates(accounts: &[Account], transactions: &[Transaction], today: NaiveDate) -> HashMap<String, f64> {
+    let mut scores = weighted_candidates(transactions, today, |tx| {
+        tx.postings
+            .iter()
+            .filter(|p| !p.currency.is_empty())
+            .map(|p| p.currency.clone())
+            .collect()
+    });
+    for currency in accounts.iter().flat_map(|a| &a.currencies) {
+        scores.entry(currency.clone()).or_default();
+    }
+    scores
+}
+
+pub fn suggest(data_dir: &Path, field: &str, q: &str, limit: usize) -> Result<Vec<Suggestion>> {
+    let transactions = beancount::list_transactions(data_dir)?;
+    let today = chrono::Local::now().date_naive();
+    let candidates = match field {
+        "account" => account_candidates(&beancount::list_accounts(data_dir)?, &transactions, today),
+        "currency" => currency_candidates(&beancount::list_accounts(data_dir)?, &transactions, today),
+        "payee" => weighted_candidates(&transactions, today, |tx| tx.payee.iter().cloned().collect()),
+        "narration" => weighted_candidates(&transactions, today, |tx| tx.narration.iter().cloned().collect()),
+        _ => {
+            return Err(LedgerError::invalid(format!(
+                "Unknown field '{}': expected account, payee, narration or currency",
+                field
+            ))
+            .into())
+        }
+    };
+    Ok(rank(candidates, q, limit))
+}
+
+/// The non-asset accounts transactions with `payee` have posted to, most
+/// used first. Payees match case-insensitively.
+pub fn suggest_account(data_dir: &Path, payee: &str, limit: usize) -> Result<Vec<AccountSuggestion>> {
+    let payee = payee.trim().to_lowercase();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for tx in beancount::list_transactions(data_dir)? {
+        if tx.payee.as_ref().is_none_or(|p| p.to_lowercase() != payee) {
+            continue;
+        }
+        let accounts: HashSet<String> = tx
+            .postings
+            .into_iter()
+            .map(|p| p.account)
+            .filter(|a| a.split(':').next() != Some("Assets"))
+            .collect();
+        for account in accounts {
+            *counts.entry(account).or_default() += 1;
+        }
+    }
+    let mut suggestions: Vec<AccountSuggestion> = counts
+        .into_iter()
+        .map(|(account, count)| AccountSuggestion { account, count })
+        .collect();
+    suggestions.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.account.cmp(&b.account)));
+    suggestions.truncate(limit);
+    Ok(suggestions)
+}
+
+/// Account names starting with `prefix`, most posted to first: open accounts
+/// plus names used in postings without an `open`, which are flagged.
+pub fn complete_accounts(data_dir: &Path, prefix: &str, limit: usize) -> Result<Vec<AccountCompletion>> {
+    let prefix = prefix.to_lowercase();
+    let mut completions: HashMap<String, AccountCompletion> = HashMap::new();
+    for account in beancount::list_accounts(data_dir)? {
+        if account.open_date.is_empty() || account.close_date.is_some() {
+            continue;
+        }
+        completions.insert(account.name.clone(), AccountCompletion { name: account.name, uses: 0, opened: true });
+    }
+    for tx in beancount::list_transactions(data_dir)? {
+        for p in tx.postings {
+            completions
+                .entry(p.account.clone())
+                .or_insert_with(|| AccountCompletion { name: p.account, uses: 0, opened: false })
+                .uses += 1;
+        }
+    }
+    let mut matches: Vec<AccountCompletion> = completions
+        .into_values()
+        .filter(|c| c.name.to_lowercase().starts_with(&prefix))
+        .collect();
+    matches.sort_by(|a, b| b.uses.cmp(&a.uses).then_with(|| a.name.cmp(&b.name)));
+    matches.truncate(limit);
+    Ok(matches)
+}
+
+/// Payees starting with `prefix`, most recently used first or, with
+/// `order=frequent`, most used first.
+pub fn complete_payees(data_dir: &Path, prefix: &str, order: Option<&str>, limit: usize) -> Result<Vec<PayeeInfo>> {
+    let mut payees = beancount::list_payees(data_dir, Some(prefix))?;
+    match order {
+        None | Some("recent") => {
+            payees.sort_by(|a, b| b.last_used.cmp(&a.last_used).then_with(|| a.name.cmp(&b.name)))
+        }
+        // list_payees already sorts by count.
+        Some("frequent") => {}
+        Some(other) => {
+            return Err(LedgerError::invalid(format!("Unknown order '{}': expected recent or frequent", other)).into())
+        }
+    }
+    payees.truncate(limit);
+    Ok(payees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Posting;
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 6, 1).unwrap()
+    }
+
+    fn transaction(date: &str, payee: &str, account: &str) -> Transaction {
+        Transaction {
+            id: None,
+            date: date.to_string(),
+            flag: "*".to_string(),
+            payee: Some(payee.to_string()),
+            narration: None,
+            tags: Vec::new(),
+            postings: vec![Posting {
+                flag: None,
+                account: account.to_string(),
+                amount: "1".to_string(),
+                currency: "USD".to_string(),
+                cost: None,
+                price: None,
+            }],
+            document: None,
+        }
+    }
+
+    fn values(suggestions: Vec<Suggestion>) -> Vec<String> {
+        suggestions.into_iter().map(|s| s.value).collect()
+    }
+
+    #[test]
+    fn recency_weight_halves_every_half_life() {
+        assert_eq!(recency_weight("2024-06-01", today()), 1.0);
+        assert!((recency_weight("2024-03-03", today()) - 0.5).abs() < 1e-9);
+        // Future dates and unparseable ones count fully.
+        assert_eq!(recency_weight("2024-07-01", today()), 1.0);
+        assert_eq!(recency_weight("someday", today()), 1.0);
+    }
+
+    #[test]
+    fn prefix_matches_rank_before_substring_matches() {
+        let candidates = HashMap::from([
+            ("Bistro Market".to_string(), 5.0),
+            ("Market Hall".to_string(), 0.1),
+            ("Supermarket".to_string(), 3.0),
+        ]);
+        assert_eq!(values(rank(candidates, "mark", 10)), ["Market Hall", "Bistro Market", "Supermarket"]);
+    }
+
+    #[test]
+    fn account_segments_count_as_prefixes() {
+        let candidates = HashMap::from([
+            ("Expenses:Food:Coffee".to_string(), 1.0),
+            ("Expenses:Seafood".to_string(), 2.0),
+        ]);
+        assert_eq!(values(rank(candidates, "food", 10)), ["Expenses:Food:Coffee", "Expenses:Seafood"]);
+    }
+
+    #[test]
+    fn ties_break_alphabetically_and_limit_applies() {
+        let candidates = HashMap::from([
+            ("Cafe B".to_string(), 1.0),
+            ("Cafe A".to_string(), 1.0),
+            ("Cafe C".to_string(), 2.0),
+        ]);
+        assert_eq!(values(rank(candidates, "CAFE", 2)), ["Cafe C", "Cafe A"]);
+    }
+
+    #[test]
+    fn recent_uses_outweigh_older_ones() {
+        let transactions = [
+            transaction("2023-01-01", "Old Diner", "Expenses:Food"),
+            transaction("2023-01-02", "Old Diner", "Expenses:Food"),
+            transaction("2024-05-30", "New Diner", "Expenses:Food"),
+        ];
+        let payees = weighted_candidates(&transactions, today(), |tx| tx.payee.iter().cloned().collect());
+        assert_eq!(values(rank(payees, "diner", 10)), ["New Diner", "Old Diner"]);
+    }
+
+    #[test]
+    fn account_candidates_include_unused_open_accounts_but_not_closed_ones() {
+        let open = |name: &str, close_date: Option<&str>| Account {
+            name: name.to_string(),
+            open_date: "2020-01-01".to_string(),
+            currencies: vec!["EUR".to_string()],
+            close_date: close_date.map(str::to_string),
+            warning: None,
+        };
+        let accounts = [open("Assets:Savings", None), open("Assets:Old", Some("2021-01-01"))];
+        let transactions = [transaction("2024-05-01", "Shop", "Expenses:Food")];
+
+        let candidates = account_candidates(&accounts, &transactions, today());
+        assert_eq!(candidates.get("Assets:Savings"), Some(&0.0));
+        assert!(candidates["Expenses:Food"] > 0.0);
+        assert!(!candidates.contains_key("Assets:Old"));
+
+        let currencies = currency_candidates(&accounts, &transactions, today());
+        assert!(currencies.contains_key("EUR") && currencies.contains_key("USD"));
+    }
+}
@@ -1,13 +1,22 @@
-use crate::model::{Transaction, Posting, Account, VerifyResult};
+use crate::config::{Config, FileScheme, NarrationDefault};
+use crate::error::{ErrorKind, LedgerError};
+use crate::fsio;
+use crate::lots::{CostSpec, Inventory};
+use crate::model::{Transaction, TransactionQuery, Posting, Account, AccountsQuery, LedgerOption, LedgerOptions, LedgerStats, Plugin, Commodity, AccountDetail, AccountRoot, BalanceAssertion, Directive, Document, Note, NoteRequest, Pad, DIRECTIVE_KINDS, AccountActivityRange, PayeeInfo, PayeeLast, PendingCount, TagInfo, Budget, BudgetRequest, CurrencyInfo, Price, DuplicateCluster, RenameResult, RenamedFile, TransactionTemplate, VerifyResult, LotIssue};
 use anyhow::Result;
-use beancount_parser_lima::{BeancountParser, BeancountSources, DirectiveVariant};
+use beancount_parser_lima::{BeancountParser, BeancountSources, DirectiveVariant, Spanned};
+use chrono::Datelike;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
+use std::str::FromStr;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
-pub fn list_transactions(data_dir: &Path) -> Result<Vec<Transaction>> {
-    let mut transactions = Vec::new();
-
+/// The `.bean` files in the data directory other than `main.bean`, which only
+/// includes them (parsing it would load every file a second time).
+fn ledger_files(data_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
     for entry in WalkDir::new(data_dir).max_depth(1) {
         let entry = entry?;
         let path = entry.path();
@@ -15,25 +24,158 @@ pub fn list_transactions(data_dir: &Path) -> Result<Vec<Transaction>> {
             if path.file_name().map_or(false, |n| n == "main.bean") {
                 continue;
             }
-            
-            let txs = parse_file_transactions(path)?;
-            transactions.extend(txs);
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// Every ledger file: the include graph from `main.bean` plus any top-level
+/// `.bean` file it doesn't reach. Edits that must find every reference to
+/// something walk these, parsing each with `file_sources`.
+fn all_ledger_files(data_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = if data_dir.join("main.bean").exists() { include_graph(data_dir)? } else { Vec::new() };
+    let mut seen: Vec<PathBuf> = files.iter().map(|f| fs::canonicalize(f).unwrap_or_else(|_| f.clone())).collect();
+    for path in ledger_files(data_dir)? {
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if !seen.contains(&canonical) {
+            seen.push(canonical);
+            files.push(path);
         }
     }
+    Ok(files)
+}
+
+/// Loads `content` (the text of one ledger file) for parsing on its own.
+/// The parser follows `include`s, which would mix other files' directives,
+/// with spans into those files, into the result; so the file is parsed from
+/// a temporary copy with each `include` line blanked out, which keeps every
+/// byte offset the same as in `content`.
+pub(crate) fn file_sources(content: &str) -> Result<BeancountSources> {
+    let include = regex::Regex::new(r"^\s*include\s")?;
+    let isolated: String = content
+        .split_inclusive('\n')
+        .map(|line| {
+            if include.is_match(line) {
+                let body = line.trim_end_matches(['\r', '\n']);
+                format!(";{}{}", " ".repeat(body.len().saturating_sub(1)), &line[body.len()..])
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    let tmp = std::env::temp_dir().join(format!("beancounters-{}.bean", uuid::Uuid::new_v4()));
+    fs::write(&tmp, isolated)?;
+    let sources = BeancountSources::try_from(tmp.clone()).map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e));
+    fs::remove_file(&tmp).ok();
+    sources
+}
+
+pub fn list_transactions(data_dir: &Path) -> Result<Vec<Transaction>> {
+    let mut transactions = Vec::new();
+
+    for path in ledger_files(data_dir)? {
+        let txs = parse_file_transactions(data_dir, &path)?;
+        transactions.extend(txs);
+    }
     
     transactions.sort_by(|a, b| b.date.cmp(&a.date));
     
     Ok(transactions)
 }
 
-fn parse_file_transactions(path: &Path) -> Result<Vec<Transaction>> {
+/// Applies the date, account and flag filters shared by the transaction
+/// listing and export endpoints. ISO dates compare correctly as strings.
+pub fn filter_transactions(transactions: Vec<Transaction>, query: &TransactionQuery) -> Vec<Transaction> {
+    transactions
+        .into_iter()
+        .filter(|tx| !query.flagged || needs_review(tx))
+        .filter(|tx| query.from.as_ref().is_none_or(|from| tx.date >= *from))
+        .filter(|tx| query.to.as_ref().is_none_or(|to| tx.date <= *to))
+        .filter(|tx| {
+            query.account.as_ref().is_none_or(|account| {
+                tx.postings.iter().any(|p| account_matches(&p.account, account))
+            })
+        })
+        .collect()
+}
+
+/// Whether the transaction or any of its postings is flagged `!`.
+fn needs_review(tx: &Transaction) -> bool {
+    tx.flag == "!" || tx.postings.iter().any(|p| p.flag.as_deref() == Some("!"))
+}
+
+/// Transactions whose own flag is `!`, oldest first, in the order they'd
+/// be reconciled. Flagged postings in a `*` transaction don't count;
+/// `flagged` in a query includes those.
+pub fn pending_transactions(data_dir: &Path) -> Result<Vec<Transaction>> {
+    let mut pending: Vec<Transaction> = list_transactions(data_dir)?.into_iter().filter(|tx| tx.flag == "!").collect();
+    pending.sort_by(|a, b| a.date.cmp(&b.date));
+    Ok(pending)
+}
+
+/// How many pending transactions post to each account, most first.
+pub fn pending_by_account(data_dir: &Path) -> Result<Vec<PendingCount>> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for tx in pending_transactions(data_dir)? {
+        let accounts: BTreeSet<String> = tx.postings.into_iter().map(|p| p.account).collect();
+        for account in accounts {
+            *counts.entry(account).or_default() += 1;
+        }
+    }
+    let mut counts: Vec<PendingCount> = counts.into_iter().map(|(account, count)| PendingCount { account, count }).collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count));
+    Ok(counts)
+}
+
+/// Orders transactions by the query's `sort` and `order` (default: newest
+/// first). The sort is stable, so ties keep their current (file) order.
+pub fn sort_transactions(transactions: &mut [Transaction], query: &TransactionQuery) -> Result<()> {
+    fn sort_by_key<K: Ord>(transactions: &mut [Transaction], descending: bool, key: impl Fn(&Transaction) -> K) {
+        transactions.sort_by(|a, b| {
+            let ordering = key(a).cmp(&key(b));
+            if descending { ordering.reverse() } else { ordering }
+        });
+    }
+
+    let descending = match query.order.as_deref() {
+        None | Some("desc") => true,
+        Some("asc") => false,
+        Some(other) => return Err(LedgerError::invalid(format!("Unknown order '{}': expected asc or desc", other)).into()),
+    };
+    match query.sort.as_deref() {
+        None | Some("date") => sort_by_key(transactions, descending, |tx| {
+            chrono::NaiveDate::parse_from_str(&tx.date, "%Y-%m-%d").ok()
+        }),
+        Some("payee") => sort_by_key(transactions, descending, |tx| tx.payee.clone()),
+        Some("amount") => sort_by_key(transactions, descending, |tx| {
+            tx.postings
+                .iter()
+                .filter_map(|p| parse_amount(&p.amount).ok())
+                .map(|a| a.abs())
+                .max()
+        }),
+        Some(other) => {
+            return Err(LedgerError::invalid(format!("Unknown sort '{}': expected date, payee or amount", other)).into())
+        }
+    }
+    Ok(())
+}
+
+fn parse_file_transactions(data_dir: &Path, path: &Path) -> Result<Vec<Transaction>> {
     let sources = BeancountSources::try_from(path.to_path_buf())
         .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
     let parser = BeancountParser::new(&sources);
-    let result = parser.parse().map_err(|e| anyhow::anyhow!("Parse error: {:?}", e))?;
+    let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
 
     let mut transactions = Vec::new();
-    let path_str = path.to_string_lossy().to_string();
+    // IDs are relative to the data directory so they stay valid whatever
+    // directory the server was started with.
+    let path_str = path
+        .strip_prefix(data_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string();
 
     for directive in result.directives {
         if let DirectiveVariant::Transaction(t) = directive.variant() {
@@ -45,16 +187,20 @@ fn parse_file_transactions(path: &Path) -> Result<Vec<Transaction>> {
             let mut postings = Vec::new();
             for p in t.postings() {
                 postings.push(Posting {
+                    flag: p.flag().map(|f| f.item().to_string()),
                     account: p.account().item().to_string(),
                     amount: p.amount().map(|a| a.item().to_string()).unwrap_or_default(),
                     currency: p.currency().map(|c| c.item().to_string()).unwrap_or_default(),
-                    cost: None, 
-                    price: None, 
+                    cost: p.cost_spec().map(|c| c.item().to_string()),
+                    price: p.price_annotation().map(|pr| pr.item().to_string()),
                 });
             }
 
+            // Transactions written by us carry a stable `id:`; older ones
+            // fall back to their position.
             let start = directive.date().span().start;
-            let id = format!("{}:{}", path_str, start);
+            let id = meta_string(directive.metadata(), "id")
+                .unwrap_or_else(|| format!("{}:{}", path_str, start));
 
             transactions.push(Transaction {
                 id: Some(id),
@@ -62,8 +208,13 @@ fn parse_file_transactions(path: &Path) -> Result<Vec<Transaction>> {
                 flag,
                 payee,
                 narration,
-                tags: vec![], 
+                tags: directive
+                    .metadata()
+                    .tags()
+                    .map(|t| t.item().to_string().trim_start_matches('#').to_string())
+                    .collect(),
                 postings,
+                document: meta_string(directive.metadata(), "document"),
             });
         }
     }
@@ -71,21 +222,816 @@ fn parse_file_transactions(path: &Path) -> Result<Vec<Transaction>> {
     Ok(transactions)
 }
 
-pub fn update_transaction_flag(id: &str, new_flag: &str) -> Result<()> {
-    let parts: Vec<&str> = id.splitn(2, ':').collect();
-    if parts.len() != 2 {
-        return Err(anyhow::anyhow!("Invalid ID"));
+/// Counts for `/stats`, gathered in one parse of every ledger file.
+pub fn stats(data_dir: &Path) -> Result<LedgerStats> {
+    let today = chrono::Local::now().date_naive().with_day(1).unwrap();
+    let mut per_month: BTreeMap<String, usize> = (0..12)
+        .filter_map(|i| today.checked_sub_months(chrono::Months::new(i)))
+        .map(|month| (month.format("%Y-%m").to_string(), 0))
+        .collect();
+    let mut per_year: BTreeMap<String, usize> = BTreeMap::new();
+    let (mut transactions, mut postings, mut uncleared) = (0, 0, 0);
+    let (mut first_date, mut last_date): (Option<String>, Option<String>) = (None, None);
+    let mut currencies = BTreeSet::new();
+    let mut opened = BTreeSet::new();
+    let mut closed = BTreeSet::new();
+
+    let files = ledger_files(data_dir)?;
+    for path in &files {
+        let sources = BeancountSources::try_from(path.clone())
+            .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        for directive in result.directives {
+            match directive.variant() {
+                DirectiveVariant::Transaction(t) => {
+                    let date = directive.date().item().to_string();
+                    transactions += 1;
+                    if t.flag().to_string() == "!" {
+                        uncleared += 1;
+                    }
+                    for p in t.postings() {
+                        postings += 1;
+                        if let Some(currency) = p.currency() {
+                            currencies.insert(currency.item().to_string());
+                        }
+                    }
+                    *per_year.entry(date[..4].to_string()).or_default() += 1;
+                    if let Some(count) = per_month.get_mut(&date[..7]) {
+                        *count += 1;
+                    }
+                    if first_date.as_ref().is_none_or(|d| date < *d) {
+                        first_date = Some(date.clone());
+                    }
+                    if last_date.as_ref().is_none_or(|d| date > *d) {
+                        last_date = Some(date);
+                    }
+                }
+                DirectiveVariant::Open(o) => {
+                    opened.insert(o.account().item().to_string());
+                }
+                DirectiveVariant::Close(c) => {
+                    closed.insert(c.account().item().to_string());
+                }
+                _ => {}
+            }
+        }
     }
-    let path_str = parts[0];
-    let start_byte: usize = parts[1].parse()?;
-    
-    let path = PathBuf::from(path_str);
+
+    let closed_accounts = opened.intersection(&closed).count();
+    Ok(LedgerStats {
+        transactions,
+        postings,
+        uncleared,
+        accounts: opened.len(),
+        open_accounts: opened.len() - closed_accounts,
+        closed_accounts,
+        first_date,
+        last_date,
+        currencies: currencies.len(),
+        files: files.len(),
+        transactions_per_year: per_year,
+        transactions_per_month: per_month,
+    })
+}
+
+/// How `find_duplicates` compares payees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKey {
+    /// Payees must match exactly.
+    Exact,
+    /// Payees match ignoring case, punctuation and whitespace.
+    FuzzyPayee,
+}
+
+/// Groups transactions with the same date, set of posting amounts and payee,
+/// returning the groups with more than one member.
+pub fn find_duplicates(data_dir: &Path, key: DuplicateKey) -> Result<Vec<DuplicateCluster>> {
+    let mut groups: BTreeMap<(String, Vec<String>, String), Vec<Transaction>> = BTreeMap::new();
+    for tx in list_transactions(data_dir)? {
+        let mut amounts: Vec<String> = tx
+            .postings
+            .iter()
+            .filter(|p| !p.amount.is_empty())
+            .map(|p| {
+                let amount = parse_amount(&p.amount).map(|a| a.normalize().to_string()).unwrap_or_else(|_| p.amount.clone());
+                format!("{} {}", amount, p.currency)
+            })
+            .collect();
+        amounts.sort();
+        let payee = tx.payee.clone().unwrap_or_default();
+        let payee = match key {
+            DuplicateKey::Exact => payee,
+            DuplicateKey::FuzzyPayee => payee.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect(),
+        };
+        groups.entry((tx.date.clone(), amounts, payee)).or_default().push(tx);
+    }
+
+    Ok(groups
+        .into_iter()
+        .filter(|(_, txs)| txs.len() > 1)
+        .map(|((date, amounts, _), txs)| DuplicateCluster {
+            date,
+            amounts,
+            payee: txs[0].payee.clone(),
+            transaction_ids: txs.into_iter().filter_map(|tx| tx.id).collect(),
+        })
+        .collect())
+}
+
+/// Reads a string metadata value, without its surrounding quotes.
+fn meta_string(metadata: &beancount_parser_lima::Metadata, key: &str) -> Option<String> {
+    metadata
+        .key_values()
+        .find(|(k, _)| k.item().to_string() == key)
+        .map(|(_, v)| v.item().to_string().trim_matches('"').to_string())
+}
+
+/// Payees with usage counts and last-used date, most used first. Payees that
+/// differ only in case are merged under their most common spelling; `q`
+/// filters by case-insensitive prefix.
+pub fn list_payees(data_dir: &Path, q: Option<&str>) -> Result<Vec<PayeeInfo>> {
+    // lowercase name -> (spelling -> count, last used)
+    let mut payees: HashMap<String, (HashMap<String, usize>, String)> = HashMap::new();
+    for tx in list_transactions(data_dir)? {
+        let Some(payee) = tx.payee.filter(|p| !p.is_empty()) else { continue };
+        let entry = payees.entry(payee.to_lowercase()).or_default();
+        *entry.0.entry(payee).or_default() += 1;
+        if tx.date > entry.1 {
+            entry.1 = tx.date;
+        }
+    }
+
+    let q = q.map(str::to_lowercase);
+    let mut result: Vec<PayeeInfo> = payees
+        .into_iter()
+        .filter(|(key, _)| q.as_deref().is_none_or(|q| key.starts_with(q)))
+        .map(|(_, (spellings, last_used))| {
+            let count = spellings.values().sum();
+            let name = spellings
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+                .map(|(name, _)| name)
+                .unwrap_or_default();
+            PayeeInfo { name, count, last_used }
+        })
+        .collect();
+    result.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    Ok(result)
+}
+
+/// Distinct tags with usage counts and the date range they appear in. Tags
+/// come from each transaction's own tag list, which includes those applied
+/// by a surrounding `pushtag`/`poptag` region as far as the parser attaches
+/// them to the transaction.
+pub fn list_tags(data_dir: &Path) -> Result<Vec<TagInfo>> {
+    let mut tags: BTreeMap<String, TagInfo> = BTreeMap::new();
+    for tx in list_transactions(data_dir)? {
+        for tag in &tx.tags {
+            let info = tags.entry(tag.clone()).or_insert_with(|| TagInfo {
+                name: tag.clone(),
+                count: 0,
+                first_used: tx.date.clone(),
+                last_used: tx.date.clone(),
+            });
+            info.count += 1;
+            if tx.date < info.first_used {
+                info.first_used = tx.date.clone();
+            }
+            if tx.date > info.last_used {
+                info.last_used = tx.date.clone();
+            }
+        }
+    }
+    Ok(tags.into_values().collect())
+}
+
+/// Largest `precision:` accepted on a commodity; `Decimal` keeps at most 28
+/// decimal places.
+const MAX_PRECISION: u32 = 28;
+
+/// Every `commodity` directive, by currency.
+pub fn list_commodities(data_dir: &Path) -> Result<Vec<Commodity>> {
+    let mut commodities = Vec::new();
+    for path in ledger_files(data_dir)? {
+        let sources = BeancountSources::try_from(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        for directive in result.directives {
+            if let DirectiveVariant::Commodity(c) = directive.variant() {
+                commodities.push(Commodity {
+                    date: directive.date().item().to_string(),
+                    currency: c.currency().item().to_string(),
+                    name: meta_string(directive.metadata(), "name"),
+                    precision: meta_string(directive.metadata(), "precision")
+                        .and_then(|p| p.parse().ok())
+                        .filter(|p| *p <= MAX_PRECISION),
+                    price: meta_string(directive.metadata(), "price"),
+                });
+            }
+        }
+    }
+    commodities.sort_by(|a, b| a.currency.cmp(&b.currency));
+    Ok(commodities)
+}
+
+/// Declared display precision per currency.
+pub fn commodity_precisions(data_dir: &Path) -> Result<HashMap<String, u32>> {
+    Ok(list_commodities(data_dir)?
+        .into_iter()
+        .filter_map(|c| Some((c.currency, c.precision?)))
+        .collect())
+}
+
+/// Declares a commodity in `commodities.bean`, creating the file and its
+/// include in `main.bean` on first use.
+pub fn add_commodity(data_dir: &Path, commodity: &Commodity) -> Result<()> {
+    chrono::NaiveDate::parse_from_str(&commodity.date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", commodity.date, e)))?;
+    if !is_valid_currency(&commodity.currency) {
+        return Err(LedgerError::invalid(format!("Invalid currency {:?}", commodity.currency)).into());
+    }
+    if commodity.precision.is_some_and(|p| p > MAX_PRECISION) {
+        return Err(LedgerError::invalid(format!("precision must be at most {}", MAX_PRECISION)).into());
+    }
+    if list_commodities(data_dir)?.iter().any(|c| c.currency == commodity.currency) {
+        return Err(LedgerError::new(ErrorKind::Conflict, format!("Commodity {} is already declared", commodity.currency)).into());
+    }
+
+    let mut text = format!("\n{} commodity {}\n", commodity.date, commodity.currency);
+    if let Some(name) = &commodity.name {
+        text.push_str(&format!("  name: {}\n", quote_string(name)?));
+    }
+    if let Some(precision) = commodity.precision {
+        text.push_str(&format!("  precision: {}\n", precision));
+    }
+    if let Some(price) = &commodity.price {
+        crate::quotes::parse_source(price)?;
+        text.push_str(&format!("  price: {}\n", quote_string(price)?));
+    }
+
+    append_included(data_dir, "commodities.bean", &text)
+}
+
+/// Currencies used in postings or declared with a `commodity` directive,
+/// with the first date each appears and how many postings use it.
+pub fn list_currencies(data_dir: &Path) -> Result<Vec<CurrencyInfo>> {
+    fn seen<'a>(currencies: &'a mut BTreeMap<String, CurrencyInfo>, currency: &str, date: &str) -> &'a mut CurrencyInfo {
+        let info = currencies.entry(currency.to_string()).or_insert_with(|| CurrencyInfo {
+            currency: currency.to_string(),
+            first_seen: date.to_string(),
+            postings: 0,
+            declared: false,
+            undeclared: false,
+        });
+        if date < info.first_seen.as_str() {
+            info.first_seen = date.to_string();
+        }
+        info
+    }
+
+    let mut currencies = BTreeMap::new();
+    for path in ledger_files(data_dir)? {
+        let sources = BeancountSources::try_from(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        for directive in result.directives {
+            let date = directive.date().item().to_string();
+            match directive.variant() {
+                DirectiveVariant::Commodity(c) => {
+                    seen(&mut currencies, &c.currency().item().to_string(), &date).declared = true;
+                }
+                DirectiveVariant::Transaction(t) => {
+                    for p in t.postings() {
+                        if let Some(currency) = p.currency() {
+                            seen(&mut currencies, &currency.item().to_string(), &date).postings += 1;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(currencies
+        .into_values()
+        .map(|mut info| {
+            info.undeclared = !info.declared && info.postings > 0;
+            info
+        })
+        .collect())
+}
+
+/// Every `price` directive in the ledger, oldest first.
+pub fn list_prices(data_dir: &Path) -> Result<Vec<Price>> {
+    let mut prices = Vec::new();
+    for path in ledger_files(data_dir)? {
+        let sources = BeancountSources::try_from(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        for directive in result.directives {
+            if let DirectiveVariant::Price(p) = directive.variant() {
+                let amount = p.amount().item();
+                prices.push(Price {
+                    date: directive.date().item().to_string(),
+                    currency: p.currency().item().to_string(),
+                    amount: amount.number().item().to_string(),
+                    quote_currency: amount.currency().item().to_string(),
+                });
+            }
+        }
+    }
+    prices.sort_by(|a, b| a.date.cmp(&b.date));
+    Ok(prices)
+}
+
+/// The most recent price of `base` in `quote` on or before `at` (default
+/// today). Without a direct price, the inverse of the latest `quote` price
+/// in `base` is used.
+pub fn latest_price(data_dir: &Path, base: &str, quote: &str, at: Option<&str>) -> Result<Price> {
+    let at = match at {
+        Some(at) => chrono::NaiveDate::parse_from_str(at, "%Y-%m-%d")
+            .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", at, e)))?
+            .to_string(),
+        None => chrono::Local::now().date_naive().to_string(),
+    };
+    let prices = list_prices(data_dir)?;
+    let latest = |from: &str, to: &str| {
+        prices
+            .iter()
+            .rev()
+            .find(|p| p.currency == from && p.quote_currency == to && p.date <= at)
+    };
+    if let Some(price) = latest(base, quote) {
+        return Ok(price.clone());
+    }
+    let inverse = latest(quote, base).and_then(|p| {
+        let rate = parse_amount(&p.amount).ok().filter(|r| !r.is_zero())?;
+        Some(Price {
+            date: p.date.clone(),
+            currency: base.to_string(),
+            amount: (Decimal::ONE / rate).normalize().to_string(),
+            quote_currency: quote.to_string(),
+        })
+    });
+    inverse.ok_or_else(|| LedgerError::not_found(format!("No price for {} in {} on or before {}", base, quote, at)).into())
+}
+
+/// Appends a `price` directive to `prices.bean`, creating the file and its
+/// include in `main.bean` on first use.
+pub fn add_price(data_dir: &Path, price: &Price) -> Result<()> {
+    chrono::NaiveDate::parse_from_str(&price.date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", price.date, e)))?;
+    for currency in [&price.currency, &price.quote_currency] {
+        if !is_valid_currency(currency) {
+            return Err(LedgerError::invalid(format!("Invalid currency {:?}", currency)).into());
+        }
+    }
+    parse_amount(&price.amount)?;
+    let text = format!(
+        "\n{} price {} {} {}\n",
+        price.date, price.currency, price.amount.trim(), price.quote_currency
+    );
+    append_included(data_dir, "prices.bean", &text)
+}
+
+fn budget_regex() -> &'static regex::Regex {
+    static BUDGET: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    BUDGET.get_or_init(|| {
+        regex::Regex::new(r#"^(\d{4}-\d{2}-\d{2})\s+custom\s+"budget"\s+([A-Z][^\s;"]*)\s+"(\w+)"\s+([-+]?[0-9][0-9.]*)\s+([A-Z][A-Z0-9'._-]*)"#).unwrap()
+    })
+}
+
+/// Every monthly `custom "budget"` directive in the ledger, oldest first.
+/// Budgets for other periods are left to tools that understand them.
+pub fn list_budgets(data_dir: &Path) -> Result<Vec<Budget>> {
+    let mut budgets = Vec::new();
+    for path in include_graph(data_dir)? {
+        let content = fs::read_to_string(&path)?;
+        for c in content.lines().filter_map(|l| budget_regex().captures(l)) {
+            if &c[3] != "monthly" {
+                continue;
+            }
+            budgets.push(Budget {
+                date: c[1].to_string(),
+                account: c[2].to_string(),
+                amount: c[4].to_string(),
+                currency: c[5].to_string(),
+            });
+        }
+    }
+    budgets.sort_by(|a, b| a.date.cmp(&b.date));
+    Ok(budgets)
+}
+
+/// Appends a monthly budget for `account` to `budgets.bean`. Earlier budgets
+/// stay in the ledger; the latest one on or before a month applies to it.
+pub fn set_budget(data_dir: &Path, account: &str, budget: &BudgetRequest) -> Result<Budget> {
+    check_account_name("account", account)?;
+    if !account_matches(account, "Expenses") {
+        return Err(LedgerError::invalid(format!("Budgets are for Expenses accounts, not '{}'", account)).into());
+    }
+    let date = match budget.date.as_deref() {
+        Some(date) => chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", date, e)))?,
+        None => chrono::Local::now().date_naive().with_day(1).unwrap(),
+    };
+    let amount = parse_amount(&budget.amount)?;
+    if amount.is_sign_negative() {
+        return Err(LedgerError::invalid(format!("Budget amount must not be negative: {}", budget.amount)).into());
+    }
+    if !is_valid_currency(&budget.currency) {
+        return Err(LedgerError::invalid(format!("Invalid currency {:?}", budget.currency)).into());
+    }
+    let budget = Budget {
+        account: account.to_string(),
+        amount: budget.amount.trim().to_string(),
+        currency: budget.currency.clone(),
+        date: date.to_string(),
+    };
+    let text = format!(
+        "{} custom \"budget\" {} \"monthly\" {} {}\n",
+        budget.date, budget.account, budget.amount, budget.currency
+    );
+    append_included(data_dir, "budgets.bean", &text)?;
+    Ok(budget)
+}
+
+/// Every `balance` directive in the ledger, sorted by date.
+pub fn list_balance_assertions(data_dir: &Path) -> Result<Vec<BalanceAssertion>> {
+    let mut assertions = Vec::new();
+    for path in ledger_files(data_dir)? {
+        let sources = BeancountSources::try_from(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        for directive in result.directives {
+            if let DirectiveVariant::Balance(b) = directive.variant() {
+                let amount = b.atol().item().amount();
+                assertions.push(BalanceAssertion {
+                    date: directive.date().item().to_string(),
+                    account: b.account().item().to_string(),
+                    amount: amount.number().item().to_string(),
+                    currency: amount.currency().item().to_string(),
+                });
+            }
+        }
+    }
+    assertions.sort_by(|a, b| a.date.cmp(&b.date));
+    Ok(assertions)
+}
+
+/// Appends a `balance` directive to the dated file for its date.
+pub fn add_balance_assertion(data_dir: &Path, scheme: FileScheme, assertion: &BalanceAssertion) -> Result<()> {
+    let date = chrono::NaiveDate::parse_from_str(&assertion.date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", assertion.date, e)))?;
+    check_account_name("account", &assertion.account)?;
+    parse_amount(&assertion.amount)?;
+    if !is_valid_currency(&assertion.currency) {
+        return Err(LedgerError::invalid(format!("Invalid currency {:?}", assertion.currency)).into());
+    }
+
+    append_dated(
+        data_dir,
+        scheme,
+        date,
+        &format!(
+            "\n{} balance {} {} {}\n",
+            assertion.date, assertion.account, assertion.amount.trim(), assertion.currency
+        ),
+    )
+}
+
+/// Appends `text` to the file `scheme` puts `date` in.
+fn append_dated(data_dir: &Path, scheme: FileScheme, date: chrono::NaiveDate, text: &str) -> Result<()> {
+    append_included(data_dir, &scheme.filename(date), text)
+}
+
+/// Appends `text` to `filename`, making sure `main.bean` includes it; if
+/// that fails the file is put back.
+fn append_included(data_dir: &Path, filename: &str, text: &str) -> Result<()> {
+    let path = data_dir.join(filename);
+    let original = fs::read_to_string(&path).ok();
+    fsio::append(&path, text)?;
+    if let Err(e) = ensure_included(data_dir, filename) {
+        fsio::restore(&path, original.as_deref())?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Non-transaction directives of the kinds the API exposes, optionally only
+/// those of `kind`. Each carries a `file:offset` ID for deletion.
+pub fn list_directives(data_dir: &Path, kind: Option<&str>) -> Result<Vec<Directive>> {
+    if let Some(kind) = kind.filter(|k| !DIRECTIVE_KINDS.contains(k)) {
+        return Err(LedgerError::invalid(format!(
+            "Unknown directive type '{}': expected one of {}",
+            kind,
+            DIRECTIVE_KINDS.join(", ")
+        ))
+        .into());
+    }
+    let mut directives = Vec::new();
+    for path in ledger_files(data_dir)? {
+        let sources = BeancountSources::try_from(path.clone())
+            .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+        let path_str = path.strip_prefix(data_dir).unwrap_or(&path).to_string_lossy().to_string();
+
+        for directive in result.directives {
+            let id = format!("{}:{}", path_str, directive.date().span().start);
+            let date = directive.date().item().to_string();
+            let parsed = match directive.variant() {
+                DirectiveVariant::Pad(pad) => Directive::Pad(Pad {
+                    id: Some(id),
+                    date,
+                    account: pad.account().item().to_string(),
+                    source_account: pad.source().item().to_string(),
+                }),
+                DirectiveVariant::Note(note) => Directive::Note(Note {
+                    id: Some(id),
+                    date,
+                    account: note.account().item().to_string(),
+                    comment: note.comment().item().to_string(),
+                }),
+                DirectiveVariant::Document(document) => Directive::Document(Document {
+                    id: Some(id),
+                    date,
+                    account: document.account().item().to_string(),
+                    path: document.path().item().to_string(),
+                }),
+                _ => continue,
+            };
+            if kind.is_none_or(|k| k == parsed.kind()) {
+                directives.push(parsed);
+            }
+        }
+    }
+    directives.sort_by(|a, b| a.date().cmp(b.date()));
+    Ok(directives)
+}
+
+/// Appends a `pad` directive to the dated file for its date. Both accounts
+/// must already be open on that date.
+pub fn add_pad(data_dir: &Path, scheme: FileScheme, pad: &Pad) -> Result<()> {
+    let date = chrono::NaiveDate::parse_from_str(&pad.date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", pad.date, e)))?;
+    check_account_name("account", &pad.account)?;
+    check_account_name("source_account", &pad.source_account)?;
+
+    let accounts = list_accounts(data_dir)?;
+    let problems: Vec<String> = [&pad.account, &pad.source_account]
+        .into_iter()
+        .filter_map(|name| match accounts.iter().find(|a| a.name == *name && !a.open_date.is_empty()) {
+            None => Some(format!("{} is not open", name)),
+            Some(a) if pad.date < a.open_date => Some(format!("{} is not opened until {}", name, a.open_date)),
+            Some(_) => None,
+        })
+        .collect();
+    if !problems.is_empty() {
+        return Err(LedgerError::new(ErrorKind::Validation, format!("Invalid pad accounts: {}", problems.join("; "))).into());
+    }
+
+    append_dated(
+        data_dir,
+        scheme,
+        date,
+        &format!("\n{} pad {} {}\n", pad.date, pad.account, pad.source_account),
+    )
+}
+
+/// Notes attached to exactly `name`, by date.
+pub fn list_notes(data_dir: &Path, name: &str) -> Result<Vec<Note>> {
+    if !list_accounts(data_dir)?.iter().any(|a| a.name == name) {
+        return Err(LedgerError::not_found(format!("Account {} not found", name)).into());
+    }
+    Ok(list_directives(data_dir, Some("note"))?
+        .into_iter()
+        .filter_map(|d| match d {
+            Directive::Note(note) if note.account == name => Some(note),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Appends a `note` directive for an existing account to the dated file for
+/// its date.
+pub fn add_note(data_dir: &Path, scheme: FileScheme, name: &str, note: &NoteRequest) -> Result<()> {
+    let date = chrono::NaiveDate::parse_from_str(&note.date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", note.date, e)))?;
+    if !list_accounts(data_dir)?.iter().any(|a| a.name == name && !a.open_date.is_empty()) {
+        return Err(LedgerError::not_found(format!("Account {} not found", name)).into());
+    }
+    append_dated(
+        data_dir,
+        scheme,
+        date,
+        &format!("\n{} note {} {}\n", note.date, name, quote_string(&note.comment)?),
+    )
+}
+
+/// Documents linked to exactly `name`, by date.
+pub fn list_documents(data_dir: &Path, name: &str) -> Result<Vec<Document>> {
+    if !list_accounts(data_dir)?.iter().any(|a| a.name == name) {
+        return Err(LedgerError::not_found(format!("Account {} not found", name)).into());
+    }
+    Ok(list_directives(data_dir, Some("document"))?
+        .into_iter()
+        .filter_map(|d| match d {
+            Directive::Document(document) if document.account == name => Some(document),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Stores an uploaded file as `documents/<account path>/<date>-<filename>`
+/// and links it with a `document` directive in the dated file. Returns the
+/// stored path, relative to the data directory.
+pub fn add_document(data_dir: &Path, scheme: FileScheme, name: &str, date: &str, filename: &str, bytes: &[u8]) -> Result<String> {
+    let parsed = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", date, e)))?;
+    if filename.is_empty()
+        || filename.starts_with('.')
+        || filename.chars().any(|c| c == '/' || c == '\\' || c.is_control())
+    {
+        return Err(LedgerError::invalid(format!("Invalid file name {:?}", filename)).into());
+    }
+    if !list_accounts(data_dir)?.iter().any(|a| a.name == name && !a.open_date.is_empty()) {
+        return Err(LedgerError::not_found(format!("Account {} not found", name)).into());
+    }
+
+    let relative = format!("documents/{}/{}-{}", name.replace(':', "/"), date, filename);
+    let path = data_dir.join(&relative);
+    if path.exists() {
+        return Err(LedgerError::new(ErrorKind::Conflict, format!("{} already exists", relative)).into());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fsio::write_atomic_bytes(&path, bytes)?;
+
+    let text = format!("\n{} document {} {}\n", date, name, quote_string(&relative)?);
+    if let Err(e) = append_dated(data_dir, scheme, parsed, &text) {
+        fs::remove_file(&path).ok();
+        return Err(e);
+    }
+    Ok(relative)
+}
+
+/// Resolves a stored document path, refusing anything outside
+/// `documents/`.
+pub fn document_path(data_dir: &Path, relative: &str) -> Result<PathBuf> {
+    let relative = Path::new(relative);
+    let inside = relative.starts_with("documents")
+        && relative.components().all(|c| matches!(c, std::path::Component::Normal(_)));
+    if !inside {
+        return Err(LedgerError::invalid(format!("Invalid document path {:?}", relative)).into());
+    }
+    let path = data_dir.join(relative);
+    if !path.is_file() {
+        return Err(LedgerError::not_found(format!("Document {} not found", relative.display())).into());
+    }
+    Ok(path)
+}
+
+/// Resolves a `.bean` file given relative to the data directory, refusing
+/// paths that would leave it.
+pub fn ledger_file_path(data_dir: &Path, relative: &str) -> Result<PathBuf> {
+    let relative = Path::new(relative);
+    let inside = relative.extension().is_some_and(|e| e == "bean")
+        && relative.components().all(|c| matches!(c, std::path::Component::Normal(_)));
+    if !inside {
+        return Err(LedgerError::invalid(format!("Invalid ledger file {:?}", relative)).into());
+    }
+    let path = data_dir.join(relative);
+    if !path.is_file() {
+        return Err(LedgerError::not_found(format!("Ledger file {} not found", relative.display())).into());
+    }
+    Ok(path)
+}
+
+/// Removes the non-transaction directive at a `file:offset` ID.
+/// Transactions have their own endpoint, which also cleans up attachments.
+pub fn delete_directive(data_dir: &Path, id: &str) -> Result<()> {
+    let not_found = || LedgerError::not_found(format!("Directive {} not found", id));
+    if is_stable_id(id) {
+        return Err(not_found().into());
+    }
+    let (path, start_byte) = resolve_id(data_dir, id).map_err(|_| not_found())?;
+    let content = fs::read_to_string(&path)?;
+
+    let sources = BeancountSources::try_from(path.clone())
+        .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
+    let parser = BeancountParser::new(&sources);
+    let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+    let directive = result
+        .directives
+        .iter()
+        .find(|d| d.date().span().start == start_byte)
+        .ok_or_else(not_found)?;
+    if matches!(directive.variant(), DirectiveVariant::Transaction(_)) {
+        return Err(LedgerError::invalid(format!("{} is a transaction; delete it through /transactions", id)).into());
+    }
+
+    let mut span = start_byte..directive.span().end;
+    with_leading_blank(&content, &mut span);
+    fsio::write_atomic(&path, &remove_spans(&content, vec![span]))?;
+    Ok(())
+}
+
+/// The most recent transaction for `payee` (case-insensitive) plus a
+/// template of it without ID and date, for pre-filling a new entry.
+pub fn last_for_payee(data_dir: &Path, payee: &str) -> Result<PayeeLast> {
+    let wanted = payee.to_lowercase();
+    let transaction = list_transactions(data_dir)?
+        .into_iter()
+        .find(|tx| tx.payee.as_deref().is_some_and(|p| p.to_lowercase() == wanted))
+        .ok_or_else(|| LedgerError::not_found(format!("No transactions for payee '{}'", payee)))?;
+    let template = TransactionTemplate {
+        flag: transaction.flag.clone(),
+        payee: transaction.payee.clone(),
+        narration: transaction.narration.clone(),
+        tags: transaction.tags.clone(),
+        postings: transaction.postings.clone(),
+    };
+    Ok(PayeeLast { transaction, template })
+}
+
+/// Whether `id` is a stable `id:` metadata value rather than a
+/// `file:offset` position.
+fn is_stable_id(id: &str) -> bool {
+    !id.is_empty() && !id.contains(':') && !id.chars().any(|c| c.is_whitespace() || c.is_control() || c == '"')
+}
+
+/// Splits a `file:offset` transaction ID and resolves the file against `data_dir`.
+/// A stable ID is instead looked up in the `id:` metadata of every ledger
+/// file.
+fn resolve_id(data_dir: &Path, id: &str) -> Result<(PathBuf, usize)> {
+    if is_stable_id(id) {
+        for path in ledger_files(data_dir)? {
+            // Parsed on its own: a directive from an included file would
+            // carry an offset into that file, not this one.
+            let sources = file_sources(&fs::read_to_string(&path)?)?;
+            let parser = BeancountParser::new(&sources);
+            let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+            let found = result.directives.iter().find(|directive| {
+                matches!(directive.variant(), DirectiveVariant::Transaction(_))
+                    && meta_string(directive.metadata(), "id").as_deref() == Some(id)
+            });
+            if let Some(directive) = found {
+                return Ok((path.clone(), directive.date().span().start));
+            }
+        }
+        return Err(LedgerError::not_found("Transaction not found").into());
+    }
+
+    let (file, offset) = id
+        .rsplit_once(':')
+        .ok_or_else(|| LedgerError::invalid("Invalid ID"))?;
+    let file = Path::new(file);
+    if file.components().count() != 1 || file.file_name().is_none() {
+        return Err(LedgerError::invalid("Invalid ID").into());
+    }
+    let start_byte: usize = offset.parse().map_err(|_| LedgerError::invalid("Invalid ID"))?;
+    let path = data_dir.join(file);
+    if !path.is_file() {
+        return Err(LedgerError::not_found("Transaction not found").into());
+    }
+    Ok((path, start_byte))
+}
+
+pub fn update_transaction_flag(data_dir: &Path, id: &str, new_flag: &str) -> Result<()> {
+    replace_flag(data_dir, id, new_flag, |_| Ok(()))
+}
+
+/// Marks a pending (`!`) transaction as confirmed (`*`), replacing only the
+/// flag character so the rest of the entry keeps its formatting.
+pub fn confirm_transaction(data_dir: &Path, id: &str) -> Result<()> {
+    replace_flag(data_dir, id, "*", |flag| match flag {
+        "!" => Ok(()),
+        other => Err(LedgerError::new(ErrorKind::Conflict, format!("Transaction is not pending review (flag {:?})", other)).into()),
+    })
+}
+
+/// Rewrites the flag of the transaction `id` in place once `check` accepts
+/// its current flag.
+fn replace_flag(data_dir: &Path, id: &str, new_flag: &str, check: impl Fn(&str) -> Result<()>) -> Result<()> {
+    let (path, start_byte) = resolve_id(data_dir, id)?;
     let content = fs::read_to_string(&path)?;
     
     let sources = BeancountSources::try_from(path.clone())
         .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
     let parser = BeancountParser::new(&sources);
-    let result = parser.parse().map_err(|e| anyhow::anyhow!("Parse error: {:?}", e))?;
+    let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
     
     let mut flag_span_indices = None;
     
@@ -100,186 +1046,1727 @@ pub fn update_transaction_flag(id: &str, new_flag: &str) -> Result<()> {
     }
     
     if let Some((start, end)) = flag_span_indices {
+        check(&content[start..end])?;
         let new_content = format!("{}{}{}", &content[..start], new_flag, &content[end..]);
-        fs::write(&path, new_content)?;
+        fsio::write_atomic(&path, &new_content)?;
     } else {
-        return Err(anyhow::anyhow!("Transaction not found"));
+        return Err(LedgerError::not_found("Transaction not found").into());
     }
     
     Ok(())
 }
 
+/// Accounts opened anywhere in the ledger, with close dates from `close`
+/// directives. An account opened more than once keeps its earliest open. A
+/// close with no matching open is listed with an empty open date and a
+/// warning rather than dropped.
 pub fn list_accounts(data_dir: &Path) -> Result<Vec<Account>> {
-    let path = data_dir.join("accounts.bean");
-    if !path.exists() {
-        return Ok(vec![]);
+    let mut accounts: Vec<Account> = Vec::new();
+    let mut closes = Vec::new();
+
+    // The same files transactions are read from, plus everything main.bean
+    // includes, each parsed once on its own.
+    for path in all_ledger_files(data_dir)? {
+        let sources = file_sources(&fs::read_to_string(&path)?)?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        for directive in result.directives {
+            match directive.variant() {
+                DirectiveVariant::Open(o) => {
+                    let account = Account {
+                        name: o.account().item().to_string(),
+                        open_date: directive.date().item().to_string(),
+                        currencies: o.currencies().map(|c| c.item().to_string()).collect(),
+                        close_date: None,
+                        warning: None,
+                    };
+                    match accounts.iter_mut().find(|a| a.name == account.name) {
+                        Some(existing) if account.open_date < existing.open_date => *existing = account,
+                        Some(_) => {}
+                        None => accounts.push(account),
+                    }
+                }
+                DirectiveVariant::Close(c) => {
+                    closes.push((c.account().item().to_string(), directive.date().item().to_string()));
+                }
+                _ => {}
+            }
+        }
     }
-    
-    let sources = BeancountSources::try_from(path)
-        .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
-    let parser = BeancountParser::new(&sources);
-    let result = parser.parse().map_err(|e| anyhow::anyhow!("Parse error: {:?}", e))?;
 
-    let mut accounts = Vec::new();
-    
-    for directive in result.directives {
-        if let DirectiveVariant::Open(o) = directive.variant() {
-            accounts.push(Account {
-                name: o.account().item().to_string(),
-                open_date: directive.date().item().to_string(),
-                currencies: o.currencies().map(|c| c.item().to_string()).collect(),
-                close_date: None,
-            });
+    for (name, date) in closes {
+        match accounts.iter_mut().find(|a| a.name == name) {
+            Some(account) => account.close_date = Some(date),
+            None => accounts.push(Account {
+                name,
+                open_date: String::new(),
+                currencies: Vec::new(),
+                close_date: Some(date),
+                warning: Some("closed without a matching open directive".to_string()),
+            }),
         }
     }
     
     Ok(accounts)
 }
 
-pub fn close_account(data_dir: &Path, name: &str, date: &str) -> Result<()> {
-    let path = data_dir.join("accounts.bean");
-    let text = format!("{} close {}\n", date, name);
-    use std::io::Write;
-    let mut file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
-    file.write_all(text.as_bytes())?;
-    Ok(())
+/// Applies the `GET /accounts` filters to a `list_accounts` result.
+pub fn filter_accounts(accounts: Vec<Account>, query: &AccountsQuery) -> Vec<Account> {
+    accounts
+        .into_iter()
+        .filter(|a| match query.open {
+            Some(open) => a.close_date.is_none() == open,
+            None => query.include_closed || a.close_date.is_none(),
+        })
+        .filter(|a| query.root.as_ref().is_none_or(|root| a.name.split(':').next() == Some(root.as_str())))
+        .filter(|a| query.currency.as_ref().is_none_or(|c| a.currencies.contains(c)))
+        .collect()
 }
 
-pub fn verify(data_dir: &Path) -> Result<VerifyResult> {
-    let path = data_dir.join("main.bean");
-    let sources = BeancountSources::try_from(path)
-        .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
-    let parser = BeancountParser::new(&sources);
-    
-    let (errors, warnings) = match parser.parse() {
-        Ok(success) => (vec![], success.warnings),
-        Err(error) => (error.errors, error.warnings),
-    };
-    
-    let temp_path = std::env::temp_dir().join("beancount_verify.log");
-    let file = fs::File::create(&temp_path)?;
-    sources.write_errors_or_warnings(&file, errors).unwrap();
-    let errors_str = fs::read_to_string(&temp_path)?;
-    
-    let file = fs::File::create(&temp_path)?;
-    sources.write_errors_or_warnings(&file, warnings).unwrap();
-    let warnings_str = fs::read_to_string(&temp_path)?;
-    
-    fs::remove_file(temp_path).ok();
-    
-    Ok(VerifyResult {
-        errors: errors_str.lines().map(|s| s.to_string()).collect(),
-        warnings: warnings_str.lines().map(|s| s.to_string()).collect(),
-    })
-}
+/// Distinct top-level account components (`Assets`, `Expenses`, ...) across
+/// opened accounts and accounts used in postings, with how many accounts
+/// fall under each.
+pub fn account_roots(data_dir: &Path) -> Result<Vec<AccountRoot>> {
+    let mut names: BTreeSet<String> = list_accounts(data_dir)?.into_iter().map(|a| a.name).collect();
+    for tx in list_transactions(data_dir)? {
+        names.extend(tx.postings.into_iter().map(|p| p.account));
+    }
 
-pub fn add_transaction(data_dir: &Path, tx: Transaction) -> Result<()> {
-    let date = chrono::NaiveDate::parse_from_str(&tx.date, "%Y-%m-%d")?;
-    let filename = format!("{}-{:02}.bean", date.format("%Y"), date.format("%m"));
-    let path = data_dir.join(&filename);
-    
-    let mut text = format!("\n{} {} \"{}\" \"{}\"\n", tx.date, tx.flag, tx.payee.unwrap_or_default(), tx.narration.unwrap_or_default());
-    for p in tx.postings {
-        text.push_str(&format!("  {} {} {}\n", p.account, p.amount, p.currency));
+    let mut roots: BTreeMap<String, usize> = BTreeMap::new();
+    for name in &names {
+        let root = name.split(':').next().unwrap_or(name);
+        *roots.entry(root.to_string()).or_default() += 1;
+    }
+
+    Ok(roots
+        .into_iter()
+        .map(|(name, count)| AccountRoot { name, count })
+        .collect())
+}
+
+/// Earliest and latest posting date per account, in one pass over the
+/// transactions.
+pub fn account_activity_ranges(data_dir: &Path) -> Result<Vec<AccountActivityRange>> {
+    let mut ranges: BTreeMap<String, (String, String)> = BTreeMap::new();
+    for tx in list_transactions(data_dir)? {
+        for p in &tx.postings {
+            let range = ranges
+                .entry(p.account.clone())
+                .or_insert_with(|| (tx.date.clone(), tx.date.clone()));
+            if tx.date < range.0 {
+                range.0 = tx.date.clone();
+            }
+            if tx.date > range.1 {
+                range.1 = tx.date.clone();
+            }
+        }
+    }
+    Ok(ranges
+        .into_iter()
+        .map(|(account, (first, last))| AccountActivityRange { account, first, last })
+        .collect())
+}
+
+/// One account's dates, balance, posting count and direct children, with
+/// its sub-accounts counted in.
+pub fn account_detail(data_dir: &Path, name: &str) -> Result<AccountDetail> {
+    let accounts = list_accounts(data_dir)?;
+    let account = accounts
+        .iter()
+        .find(|a| a.name == name)
+        .cloned()
+        .ok_or_else(|| LedgerError::not_found(format!("Account {} not found", name)))?;
+
+    let mut children: BTreeSet<String> = BTreeSet::new();
+    let mut add_child = |account: &str| {
+        if let Some(rest) = account.strip_prefix(name).and_then(|r| r.strip_prefix(':')) {
+            let child = rest.split(':').next().unwrap_or_default();
+            children.insert(format!("{}:{}", name, child));
+        }
+    };
+    accounts.iter().for_each(|a| add_child(&a.name));
+
+    let mut balances = crate::reports::Amounts::new();
+    let mut postings = 0;
+    let mut first: Option<String> = None;
+    let mut last: Option<String> = None;
+    for tx in list_transactions(data_dir)? {
+        tx.postings.iter().for_each(|p| add_child(&p.account));
+        let count = tx.postings.iter().filter(|p| account_matches(&p.account, name)).count();
+        if count == 0 {
+            continue;
+        }
+        postings += count;
+        for (account, currency, amount) in crate::reports::posting_amounts(&tx)? {
+            if account_matches(&account, name) {
+                *balances.entry(currency).or_default() += amount;
+            }
+        }
+        if first.as_ref().is_none_or(|f| tx.date < *f) {
+            first = Some(tx.date.clone());
+        }
+        if last.as_ref().is_none_or(|l| tx.date > *l) {
+            last = Some(tx.date.clone());
+        }
+    }
+
+    Ok(AccountDetail {
+        name: account.name,
+        open_date: account.open_date,
+        close_date: account.close_date,
+        currencies: account.currencies,
+        balances: crate::reports::format_amounts(&balances, &commodity_precisions(data_dir)?),
+        postings,
+        first_transaction: first,
+        last_transaction: last,
+        children: children.into_iter().collect(),
+    })
+}
+
+/// Appends a `close` directive for an open account. Closing an account that
+/// doesn't exist, is already closed or still holds a balance on `date` is
+/// refused.
+pub fn close_account(data_dir: &Path, name: &str, date: &str) -> Result<()> {
+    check_token("account", name)?;
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", date, e)))?;
+
+    let account = list_accounts(data_dir)?
+        .into_iter()
+        .find(|a| a.name == name && !a.open_date.is_empty())
+        .ok_or_else(|| LedgerError::not_found(format!("Account {} not found", name)))?;
+    if let Some(closed) = &account.close_date {
+        return Err(LedgerError::new(ErrorKind::Conflict, format!("Account {} is already closed on {}", name, closed)).into());
+    }
+    if date < account.open_date.as_str() {
+        return Err(LedgerError::invalid(format!(
+            "Close date {} is before the open date {} of {}",
+            date, account.open_date, name
+        ))
+        .into());
+    }
+    let balance: Vec<String> = crate::reports::balances(data_dir, Some(date))?
+        .remove(name)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(currency, amount)| format!("{} {}", amount, currency))
+        .collect();
+    if !balance.is_empty() {
+        return Err(LedgerError::new(
+            ErrorKind::Conflict,
+            format!("Account {} has a non-zero balance on {}: {}", name, date, balance.join(", ")),
+        )
+        .into());
+    }
+
+    let path = data_dir.join("accounts.bean");
+    let text = format!("{} close {}\n", date, name);
+    fsio::append(&path, &text)?;
+    Ok(())
+}
+
+/// Removes the `close` directive(s) for `name`, with any metadata under
+/// them, located by parsing so only the exact account is affected. Closes
+/// are looked for in every file `main.bean` includes, nested ones too, as
+/// `list_accounts` reads them from there.
+pub fn reopen_account(data_dir: &Path, name: &str) -> Result<()> {
+    let mut reopened = false;
+    for path in all_ledger_files(data_dir)? {
+        let content = fs::read_to_string(&path)?;
+        let sources = file_sources(&content)?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        let spans: Vec<_> = result
+            .directives
+            .iter()
+            .filter_map(|directive| match directive.variant() {
+                DirectiveVariant::Close(c) if c.account().item().to_string() == name => {
+                    let mut span = directive.date().span().start..directive.span().end;
+                    with_leading_blank(&content, &mut span);
+                    Some(span)
+                }
+                _ => None,
+            })
+            .collect();
+        if !spans.is_empty() {
+            fsio::write_atomic(&path, &remove_spans(&content, spans))?;
+            reopened = true;
+        }
+    }
+    if !reopened {
+        return Err(LedgerError::not_found(format!("Account {} is not closed", name)).into());
+    }
+    Ok(())
+}
+
+/// `account` with its `old` prefix replaced by `new`, if it is `old` or one
+/// of its sub-accounts.
+fn renamed(account: &str, old: &str, new: &str) -> Option<String> {
+    account_matches(account, old).then(|| format!("{}{}", new, &account[old.len()..]))
+}
+
+/// The edit renaming a parsed account reference, if it falls under `old`.
+fn account_edit<T: std::fmt::Display>(account: &Spanned<T>, old: &str, new: &str) -> Option<(std::ops::Range<usize>, String)> {
+    renamed(&account.item().to_string(), old, new).map(|name| (account.span().start..account.span().end, name))
+}
+
+/// Replaces byte ranges of `content`, given in any order and not overlapping.
+fn apply_edits(content: &str, mut edits: Vec<(std::ops::Range<usize>, String)>) -> String {
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    let mut content = content.to_string();
+    for (range, replacement) in edits {
+        content.replace_range(range, &replacement);
+    }
+    content
+}
+
+/// Renames `old` and its sub-accounts to `new` in every posting and in
+/// open, close, balance, pad, note, document and budget directives across
+/// every ledger file, included ones too. Matching is by whole account, not
+/// substring, so `Assets:Bank` leaves `Assets:Banking` alone. Renaming onto
+/// an existing account is a conflict unless `merge` is set, in which case
+/// the duplicate `open` directives of the renamed accounts are dropped.
+/// Every file is rewritten or none is.
+pub fn rename_account(data_dir: &Path, old: &str, new: &str, merge: bool) -> Result<RenameResult> {
+    check_account_name("new_name", new)?;
+    if account_matches(new, old) {
+        return Err(LedgerError::invalid(format!("Cannot rename {} to {}, which is inside it", old, new)).into());
+    }
+    let existing: BTreeSet<String> = list_accounts(data_dir)?
+        .into_iter()
+        .filter(|a| !a.open_date.is_empty())
+        .map(|a| a.name)
+        .collect();
+    if !existing.iter().any(|a| account_matches(a, old)) {
+        return Err(LedgerError::not_found(format!("Account {} not found", old)).into());
+    }
+    let collisions: Vec<&String> = existing
+        .iter()
+        .filter(|a| account_matches(a, old))
+        .filter(|a| renamed(a, old, new).is_some_and(|r| existing.contains(&r)))
+        .collect();
+    if !collisions.is_empty() && !merge {
+        return Err(LedgerError::new(
+            ErrorKind::Conflict,
+            format!("Renaming {} to {} collides with existing accounts; pass merge=true to merge them", old, new),
+        )
+        .into());
+    }
+
+    let mut rewrites = Vec::new();
+    let mut files = Vec::new();
+    for path in all_ledger_files(data_dir)? {
+        let content = fs::read_to_string(&path)?;
+        let sources = file_sources(&content)?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        let rename = |account: &Spanned<_>| account_edit(account, old, new);
+        let mut edits = Vec::new();
+        for directive in &result.directives {
+            match directive.variant() {
+                DirectiveVariant::Transaction(t) => edits.extend(t.postings().filter_map(|p| rename(p.account()))),
+                DirectiveVariant::Open(o) => {
+                    let target = renamed(&o.account().item().to_string(), old, new);
+                    if target.is_some_and(|t| existing.contains(&t)) {
+                        // Merging: the target's own open directive stays.
+                        let start = directive.span().start;
+                        let end = directive.span().end;
+                        let end = content[end..].find('\n').map_or(content.len(), |i| end + i + 1);
+                        edits.push((start..end, String::new()));
+                    } else {
+                        edits.extend(rename(o.account()));
+                    }
+                }
+                DirectiveVariant::Close(c) => edits.extend(rename(c.account())),
+                DirectiveVariant::Balance(b) => edits.extend(rename(b.account())),
+                DirectiveVariant::Pad(p) => {
+                    edits.extend(rename(p.account()));
+                    edits.extend(rename(p.source()));
+                }
+                DirectiveVariant::Note(n) => edits.extend(rename(n.account())),
+                DirectiveVariant::Document(d) => edits.extend(rename(d.account())),
+                _ => {}
+            }
+        }
+        // Budgets are `custom` directives, whose account is just a value;
+        // they're found the same way `list_budgets` finds them.
+        let mut offset = 0;
+        for line in content.split_inclusive('\n') {
+            if let Some(account) = budget_regex().captures(line).and_then(|c| c.get(2)) {
+                if let Some(name) = renamed(account.as_str(), old, new) {
+                    edits.push((offset + account.start()..offset + account.end(), name));
+                }
+            }
+            offset += line.len();
+        }
+        if !edits.is_empty() {
+            let name = path.strip_prefix(data_dir).unwrap_or(&path).to_string_lossy().to_string();
+            files.push(RenamedFile { file: name, occurrences: edits.len() });
+            rewrites.push((path, content.clone(), apply_edits(&content, edits)));
+        }
+    }
+
+    // Write every file, putting back the ones already written if one fails.
+    for (i, (path, _, updated)) in rewrites.iter().enumerate() {
+        if let Err(e) = fsio::write_atomic(path, updated) {
+            for (path, original, _) in &rewrites[..i] {
+                fsio::restore(path, Some(original))?;
+            }
+            return Err(e.into());
+        }
+    }
+
+    Ok(RenameResult {
+        total: files.iter().map(|f| f.occurrences).sum(),
+        files,
+    })
+}
+
+/// Lists the ledger's files in include order, starting with `main.bean`.
+/// Include paths are resolved relative to the including file; files already
+/// visited are skipped so include cycles terminate.
+pub fn include_graph(data_dir: &Path) -> Result<Vec<PathBuf>> {
+    let include = regex::Regex::new(r#"^\s*include\s+"([^"]+)""#)?;
+    let mut order = Vec::new();
+    let mut stack = vec![data_dir.join("main.bean")];
+    while let Some(path) = stack.pop() {
+        if order.contains(&path) {
+            continue;
+        }
+        let content = fs::read_to_string(&path)?;
+        let dir = path.parent().unwrap_or(data_dir).to_path_buf();
+        let children: Vec<PathBuf> = content
+            .lines()
+            .filter_map(|l| include.captures(l))
+            .map(|c| dir.join(&c[1]))
+            .collect();
+        order.push(path);
+        stack.extend(children.into_iter().rev());
+    }
+    Ok(order)
+}
+
+fn option_regex() -> &'static regex::Regex {
+    static OPTION: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    OPTION.get_or_init(|| regex::Regex::new(r#"^\s*option\s+"((?:[^"\\]|\\.)*)"\s+"((?:[^"\\]|\\.)*)""#).unwrap())
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        out.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+    }
+    out
+}
+
+/// The `option` and `plugin` lines of `main.bean`.
+pub fn ledger_options(data_dir: &Path) -> Result<LedgerOptions> {
+    let plugin = regex::Regex::new(r#"^\s*plugin\s+"((?:[^"\\]|\\.)*)"(?:\s+"((?:[^"\\]|\\.)*)")?"#)?;
+    let content = fs::read_to_string(data_dir.join("main.bean")).unwrap_or_default();
+    let mut options = LedgerOptions { options: Vec::new(), plugins: Vec::new() };
+    for line in content.lines() {
+        if let Some(c) = option_regex().captures(line) {
+            options.options.push(LedgerOption {
+                key: unescape(&c[1]),
+                value: unescape(&c[2]),
+            });
+        } else if let Some(c) = plugin.captures(line) {
+            options.plugins.push(Plugin {
+                name: unescape(&c[1]),
+                config: c.get(2).map(|m| unescape(m.as_str())),
+            });
+        }
+    }
+    Ok(options)
+}
+
+/// The first `operating_currency` option, if any.
+pub fn operating_currency(data_dir: &Path) -> Result<Option<String>> {
+    Ok(ledger_options(data_dir)?
+        .options
+        .into_iter()
+        .find(|o| o.key == "operating_currency")
+        .map(|o| o.value))
+}
+
+/// Sets an option in `main.bean`: the first line with that key is rewritten
+/// in place, or a new line goes after the last option (at the top of the
+/// file if there are none).
+pub fn set_option(data_dir: &Path, key: &str, value: &str) -> Result<()> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(LedgerError::invalid(format!("Invalid option name {:?}", key)).into());
+    }
+    let line = format!("option {} {}", quote_string(key)?, quote_string(value)?);
+    let path = data_dir.join("main.bean");
+    let content = fs::read_to_string(&path).unwrap_or_default();
+    let mut lines: Vec<&str> = content.lines().collect();
+    let options: Vec<(usize, bool)> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, l)| option_regex().captures(l).map(|c| (i, unescape(&c[1]) == key)))
+        .collect();
+    match options.iter().find(|(_, same)| *same) {
+        Some((i, _)) => lines[*i] = &line,
+        None => {
+            let at = options.last().map_or(0, |(i, _)| i + 1);
+            lines.insert(at, &line);
+        }
+    }
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    fsio::write_atomic(&path, &updated)?;
+    Ok(())
+}
+
+/// Concatenates the whole ledger into one file: each source file in include
+/// order under a comment header, with the `include` lines removed.
+pub fn export_flat(data_dir: &Path) -> Result<String> {
+    let include = regex::Regex::new(r#"^\s*include\s+""#)?;
+    let mut out = String::new();
+    for path in include_graph(data_dir)? {
+        let content = fs::read_to_string(&path)?;
+        let name = path.strip_prefix(data_dir).unwrap_or(&path);
+        out.push_str(&format!(";; ==== {} ====\n", name.display()));
+        for line in content.lines().filter(|l| !include.is_match(l)) {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Transactions whose postings don't net to zero within `tolerance`, as
+/// verify errors naming the transaction ID.
+fn unbalanced_transactions(data_dir: &Path, tolerance: Decimal) -> Result<Vec<String>> {
+    let mut errors = Vec::new();
+    for tx in list_transactions(data_dir)? {
+        let residual: Vec<String> = crate::reports::residual(&tx)?
+            .into_iter()
+            .filter(|(_, sum)| sum.abs() > tolerance)
+            .map(|(currency, sum)| format!("{} {}", sum, currency))
+            .collect();
+        if !residual.is_empty() {
+            errors.push(format!(
+                "{}: transaction does not balance: residual {}",
+                tx.id.unwrap_or_default(),
+                residual.join(", ")
+            ));
+        }
+    }
+    Ok(errors)
+}
+
+/// Postings to accounts that aren't open on the transaction's date: never
+/// opened, opened later, or already closed.
+fn inactive_account_postings(data_dir: &Path) -> Result<Vec<String>> {
+    let accounts: HashMap<String, Account> = list_accounts(data_dir)?
+        .into_iter()
+        .filter(|a| !a.open_date.is_empty())
+        .map(|a| (a.name.clone(), a))
+        .collect();
+    let mut transactions = list_transactions(data_dir)?;
+    transactions.sort_by(|a, b| a.date.cmp(&b.date));
+
+    let mut errors = Vec::new();
+    for tx in &transactions {
+        let id = tx.id.as_deref().unwrap_or_default();
+        for p in &tx.postings {
+            let problem = match accounts.get(&p.account) {
+                None => "was never opened".to_string(),
+                Some(a) if tx.date < a.open_date => format!("is not opened until {}", a.open_date),
+                Some(a) => match &a.close_date {
+                    Some(closed) if tx.date > *closed => format!("was closed on {}", closed),
+                    _ => continue,
+                },
+            };
+            errors.push(format!("{}: posting to {} on {}, which {}", id, p.account, tx.date, problem));
+        }
     }
+    Ok(errors)
+}
+
+/// Parses the whole ledger and, if that succeeds, checks every transaction
+/// balances within `tolerance` and only posts to open accounts.
+pub fn verify(data_dir: &Path, tolerance: Decimal) -> Result<VerifyResult> {
+    let path = data_dir.join("main.bean");
+    let sources = BeancountSources::try_from(path)
+        .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
+    let parser = BeancountParser::new(&sources);
     
-    use std::io::Write;
-    let mut file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
-    file.write_all(text.as_bytes())?;
+    let (errors, warnings) = match parser.parse() {
+        Ok(success) => (vec![], success.warnings),
+        Err(error) => (error.errors, error.warnings),
+    };
+    
+    let temp_path = std::env::temp_dir().join("beancount_verify.log");
+    let file = fs::File::create(&temp_path)?;
+    sources.write_errors_or_warnings(&file, errors).unwrap();
+    let errors_str = fs::read_to_string(&temp_path)?;
+    
+    let file = fs::File::create(&temp_path)?;
+    sources.write_errors_or_warnings(&file, warnings).unwrap();
+    let warnings_str = fs::read_to_string(&temp_path)?;
+    
+    fs::remove_file(temp_path).ok();
     
+    let mut errors: Vec<String> = errors_str.lines().map(|s| s.to_string()).collect();
+    if errors.is_empty() {
+        errors.extend(unbalanced_transactions(data_dir, tolerance)?);
+        errors.extend(inactive_account_postings(data_dir)?);
+    }
+
+    Ok(VerifyResult {
+        errors,
+        warnings: warnings_str.lines().map(|s| s.to_string()).collect(),
+    })
+}
+
+/// Renders `value` as a beancount string literal, escaping `"` and `\`.
+/// Control characters are rejected so a client can't smuggle a newline (and
+/// with it an extra directive) into the ledger.
+pub fn quote_string(value: &str) -> Result<String> {
+    if let Some(c) = value.chars().find(|c| c.is_control()) {
+        return Err(LedgerError::invalid(format!("String contains control character {:?}: {:?}", c, value)).into());
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    Ok(quoted)
+}
+
+/// Rewrites posting amounts that use `separator` as the decimal mark (e.g.
+/// `20,00`) into the canonical `.` form before they are written.
+pub fn normalize_amounts(tx: &mut Transaction, separator: char) -> Result<()> {
+    for p in &mut tx.postings {
+        p.amount = normalize_decimal(&p.amount, separator)?;
+    }
+    Ok(())
+}
+
+/// Converts `amount` from `separator` to `.` decimals. With `,` configured,
+/// an amount containing both `,` and `.` is ambiguous (`1.000,50` or
+/// `1,000.50`?) and rejected.
+pub fn normalize_decimal(amount: &str, separator: char) -> Result<String> {
+    if separator == '.' || !amount.contains(separator) {
+        return Ok(amount.to_string());
+    }
+    if amount.contains('.') {
+        return Err(LedgerError::invalid(format!(
+            "Ambiguous amount {:?}: contains both '{}' and '.'",
+            amount, separator
+        ))
+        .into());
+    }
+    Ok(amount.replace(separator, "."))
+}
+
+/// Fills in or rejects a missing narration according to `mode`.
+pub fn apply_narration_default(tx: &mut Transaction, mode: NarrationDefault) -> Result<()> {
+    if tx.narration.is_some() {
+        return Ok(());
+    }
+    match mode {
+        NarrationDefault::Payee => tx.narration = tx.payee.clone(),
+        NarrationDefault::Omit => {}
+        NarrationDefault::Require => {
+            return Err(LedgerError::new(ErrorKind::Validation, "Narration is required").into())
+        }
+    }
+    Ok(())
+}
+
+/// Renders the payee/narration part of a transaction header, including the
+/// leading space. Empty strings count as absent. A lone string is read by
+/// beancount as the narration, so a payee without a narration still needs an
+/// empty narration after it.
+fn format_txn_strings(payee: Option<&str>, narration: Option<&str>) -> Result<String> {
+    let payee = payee.filter(|p| !p.is_empty());
+    let narration = narration.filter(|n| !n.is_empty());
+    Ok(match (payee, narration) {
+        (Some(p), Some(n)) => format!(" {} {}", quote_string(p)?, quote_string(n)?),
+        (Some(p), None) => format!(" {} \"\"", quote_string(p)?),
+        (None, Some(n)) => format!(" {}", quote_string(n)?),
+        (None, None) => String::new(),
+    })
+}
+
+/// Rejects bare tokens (accounts, amounts, currencies, flags) that contain
+/// whitespace or control characters and would break the directive's line.
+fn check_token(field: &str, value: &str) -> Result<()> {
+    if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(LedgerError::invalid(format!("Invalid {} {:?}: must not contain whitespace", field, value)).into());
+    }
+    Ok(())
+}
+
+/// Rejects posting flags other than a single beancount flag character.
+fn check_posting_flag(flag: &str) -> Result<()> {
+    const FLAGS: &str = "*!&#?%PSTCURM";
+    let mut chars = flag.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if FLAGS.contains(c) => Ok(()),
+        _ => Err(LedgerError::invalid(format!("Invalid posting flag {:?}: expected one of {}", flag, FLAGS)).into()),
+    }
+}
+
+/// Whether `account` is `prefix` itself or one of its sub-accounts.
+pub fn account_matches(account: &str, prefix: &str) -> bool {
+    account == prefix
+        || (account.starts_with(prefix) && account[prefix.len()..].starts_with(':'))
+}
+
+/// The root account types beancount accepts.
+const ACCOUNT_TYPES: [&str; 5] = ["Assets", "Liabilities", "Equity", "Income", "Expenses"];
+
+/// Rejects account names beancount can't parse: a root type followed by
+/// one or more `:`-separated components, each starting with a capital
+/// letter or digit and containing only letters, digits and dashes.
+fn check_account_name(field: &str, name: &str) -> Result<()> {
+    let invalid = |reason: String| -> Result<()> {
+        Err(LedgerError::new(
+            ErrorKind::Invalid,
+            format!("{}: invalid account name {:?}: {}", field, name, reason),
+        )
+        .into())
+    };
+    let mut segments = name.split(':');
+    let root = segments.next().unwrap_or_default();
+    if !ACCOUNT_TYPES.contains(&root) {
+        return invalid(format!(
+            "must start with one of {}, e.g. Assets:Bank",
+            ACCOUNT_TYPES.join(", ")
+        ));
+    }
+    let mut count = 0;
+    for segment in segments {
+        count += 1;
+        let Some(first) = segment.chars().next() else {
+            return invalid("contains an empty component".to_string());
+        };
+        if !(first.is_ascii_uppercase() || first.is_ascii_digit()) {
+            return invalid(format!("component {:?} must start with a capital letter or digit", segment));
+        }
+        if let Some(c) = segment.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '-')) {
+            return invalid(format!("component {:?} contains {:?}; only letters, digits and - are allowed", segment, c));
+        }
+    }
+    if count == 0 {
+        return invalid(format!("needs at least one component after {}, e.g. {}:Bank", root, root));
+    }
+    Ok(())
+}
+
+/// Validates the fields of an account about to be written.
+fn check_account(account: &Account) -> Result<()> {
+    if chrono::NaiveDate::parse_from_str(&account.open_date, "%Y-%m-%d").is_err() {
+        return Err(LedgerError::new(
+            ErrorKind::Validation,
+            format!("open_date: invalid date {:?}: expected YYYY-MM-DD", account.open_date),
+        )
+        .into());
+    }
+    check_account_name("name", &account.name)?;
+    for c in &account.currencies {
+        if !is_valid_currency(c) {
+            return Err(LedgerError::new(
+                ErrorKind::Validation,
+                format!("currencies: invalid currency {:?}: expected 2-24 uppercase characters like USD", c),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Whether `currency` is a valid beancount commodity token.
+pub fn is_valid_currency(currency: &str) -> bool {
+    static CURRENCY: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    CURRENCY
+        .get_or_init(|| regex::Regex::new(r"^[A-Z][A-Z0-9'._-]{0,22}[A-Z0-9]$").unwrap())
+        .is_match(currency)
+}
+
+/// Checks that a posting's amount is a plain number and its currency a
+/// commodity token, so the two can't run together when written. Both may be
+/// empty for a posting whose amount beancount interpolates.
+fn check_posting_amount(p: &Posting) -> Result<()> {
+    if p.amount.is_empty() && p.currency.is_empty() {
+        return Ok(());
+    }
+    if let Some((number, rest)) = p.amount.trim().split_once(char::is_whitespace) {
+        return Err(LedgerError::invalid(format!(
+            "Invalid amount {:?} for {}: the amount must be a number only; put {:?} in the currency field and {:?} in the amount",
+            p.amount, p.account, rest.trim(), number
+        ))
+        .into());
+    }
+    parse_amount(&p.amount)?;
+    if !is_valid_currency(&p.currency) {
+        return Err(LedgerError::invalid(format!(
+            "Invalid currency {:?} for {}: expected an uppercase commodity like USD",
+            p.currency, p.account
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks that a transaction's postings sum to zero per currency. Postings
+/// without an amount are interpolated by beancount and postings held at cost
+/// or with a price weigh in another currency, so either makes the check pass.
+pub fn check_balanced(tx: &Transaction) -> Result<()> {
+    if tx.postings.iter().any(|p| p.amount.is_empty() || p.cost.is_some() || p.price.is_some()) {
+        return Ok(());
+    }
+    let mut sums: BTreeMap<&str, Decimal> = BTreeMap::new();
+    for p in &tx.postings {
+        *sums.entry(p.currency.as_str()).or_default() += parse_amount(&p.amount)?;
+    }
+    let residual: Vec<String> = sums
+        .into_iter()
+        .filter(|(_, sum)| !sum.is_zero())
+        .map(|(currency, sum)| format!("{} {}", sum, currency))
+        .collect();
+    if residual.is_empty() {
+        Ok(())
+    } else {
+        Err(LedgerError::new(
+            ErrorKind::Validation,
+            format!("Transaction does not balance: residual {}", residual.join(", ")),
+        )
+        .into())
+    }
+}
+
+/// Parses a posting amount as written in the ledger, tolerating a leading `+`.
+/// Only plain beancount numbers are accepted (digits with an optional
+/// fraction), not everything `Decimal` parses, such as `1_000`, since the
+/// text is written to the ledger as given.
+pub fn parse_amount(amount: &str) -> Result<Decimal> {
+    static NUMBER: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let number = NUMBER.get_or_init(|| regex::Regex::new(r"^[-+]?[0-9]+(\.[0-9]+)?$").unwrap());
+    let trimmed = amount.trim();
+    if !number.is_match(trimmed) {
+        return Err(LedgerError::invalid(format!("Invalid amount '{}': expected a number like 12.50", amount)).into());
+    }
+    Decimal::from_str(trimmed.trim_start_matches('+'))
+        .map_err(|e| LedgerError::invalid(format!("Invalid amount '{}': {}", amount, e)).into())
+}
+
+/// Tracks lot acquisitions and reductions per account/commodity and reports
+/// reductions that don't match a held lot. Costs are compared as numbers,
+/// and a reduction that doesn't name a lot date matches any date.
+pub fn check_lots(data_dir: &Path) -> Result<Vec<LotIssue>> {
+    let mut transactions = list_transactions(data_dir)?;
+    transactions.sort_by(|a, b| a.date.cmp(&b.date));
+
+    let mut inventories: HashMap<(String, String), Inventory> = HashMap::new();
+    let mut issues = Vec::new();
+
+    for tx in &transactions {
+        for p in &tx.postings {
+            let Some(cost) = p.cost.as_deref() else { continue };
+            let Ok(units) = parse_amount(&p.amount) else { continue };
+            let spec = CostSpec::parse(cost, units);
+            let inventory = inventories.entry((p.account.clone(), p.currency.clone())).or_default();
+
+            if units.is_sign_positive() {
+                inventory.add(units, &spec, &tx.date);
+                continue;
+            }
+
+            let (_, unmatched) = inventory.reduce(-units, &spec);
+            if !unmatched.is_zero() {
+                issues.push(LotIssue {
+                    transaction_id: tx.id.clone().unwrap_or_default(),
+                    date: tx.date.clone(),
+                    account: p.account.clone(),
+                    currency: p.currency.clone(),
+                    cost: cost.trim().trim_start_matches('{').trim_end_matches('}').trim().to_string(),
+                    unmatched_units: unmatched.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+/// One posting line. With `amount_column`, the amount is padded to end at
+/// that column, as `bean-format` does; a line too long for it keeps two
+/// spaces before the amount.
+fn posting_line(flag: &str, p: &Posting, amount_column: Option<usize>) -> String {
+    let Some(column) = amount_column.filter(|_| !p.amount.trim().is_empty()) else {
+        return format!("  {}{} {} {}\n", flag, p.account, p.amount, p.currency);
+    };
+    let lead = format!("  {}{}", flag, p.account);
+    let amount = p.amount.trim();
+    let used = lead.chars().count() + amount.chars().count();
+    let padding = column.saturating_sub(used).max(2);
+    format!("{}{}{} {}\n", lead, " ".repeat(padding), amount, p.currency)
+}
+
+/// The checks every transaction write runs before touching a file, shared by
+/// `POST`/`PUT /transactions` and the CSV import: amounts normalized to `.`
+/// decimals, the narration default, the syntax of every field, posting
+/// accounts open on the date, and the validation profile. Returns the
+/// accounts to open first when `auto_open` is set.
+pub fn prepare_transaction(tx: &mut Transaction, config: &Config, accounts: &[Account], auto_open: bool) -> Result<Vec<Account>> {
+    normalize_amounts(tx, config.decimal_separator)?;
+    apply_narration_default(tx, config.narration_default)?;
+    transaction_text(tx, "", None)?;
+    let opens = check_posting_accounts(tx, accounts, auto_open)?;
+    let mut known = accounts.to_vec();
+    known.extend(opens.iter().cloned());
+    config.validation_profile.validate(tx, &known)?;
+    Ok(opens)
+}
+
+/// Appends a transaction to its dated file and returns the ID it was
+/// written with.
+pub fn add_transaction(data_dir: &Path, scheme: FileScheme, amount_column: Option<usize>, tx: Transaction) -> Result<String> {
+    let date = chrono::NaiveDate::parse_from_str(&tx.date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", tx.date, e)))?;
+    let filename = scheme.filename(date);
+    let path = data_dir.join(&filename);
+    let id = match tx.id.as_deref().filter(|id| is_stable_id(id)) {
+        Some(id) => id.to_string(),
+        None => uuid::Uuid::new_v4().to_string(),
+    };
+    let text = transaction_text(&tx, &id, amount_column)?;
+
+    let original = fs::read_to_string(&path).ok();
+    fsio::append(&path, &text)?;
+
+    // The dated file and main.bean change together: if the include can't be
+    // added, put the dated file back.
+    if let Err(e) = ensure_included(data_dir, &filename) {
+        fsio::restore(&path, original.as_deref())?;
+        return Err(e);
+    }
+
+    Ok(id)
+}
+
+/// Appends `txs` in order, all or nothing: if one fails, every file written
+/// so far is put back. Returns the new IDs.
+pub fn add_transactions(data_dir: &Path, scheme: FileScheme, amount_column: Option<usize>, txs: Vec<Transaction>) -> Result<Vec<String>> {
+    let mut paths = vec![data_dir.join("main.bean")];
+    for tx in &txs {
+        if let Ok(date) = chrono::NaiveDate::parse_from_str(&tx.date, "%Y-%m-%d") {
+            let path = data_dir.join(scheme.filename(date));
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    let originals: Vec<(PathBuf, Option<String>)> = paths.into_iter().map(|p| (p.clone(), fs::read_to_string(&p).ok())).collect();
+    let result: Result<Vec<String>> = txs.into_iter().map(|tx| add_transaction(data_dir, scheme, amount_column, tx)).collect();
+    if result.is_err() {
+        for (path, original) in &originals {
+            fsio::restore(path, original.as_deref())?;
+        }
+    }
+    result
+}
+
+/// Renders `tx` as it's appended to a ledger file, checking every field.
+fn transaction_text(tx: &Transaction, id: &str, amount_column: Option<usize>) -> Result<String> {
+    chrono::NaiveDate::parse_from_str(&tx.date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", tx.date, e)))?;
+    check_token("flag", &tx.flag)?;
+    let strings = format_txn_strings(tx.payee.as_deref(), tx.narration.as_deref())?;
+
+    let mut text = format!("\n{} {}{}", tx.date, tx.flag, strings);
+    for tag in &tx.tags {
+        check_token("tag", tag)?;
+        text.push_str(&format!(" #{}", tag.trim_start_matches('#')));
+    }
+    text.push('\n');
+    text.push_str(&format!("  id: {}\n", quote_string(id)?));
+    if let Some(document) = &tx.document {
+        text.push_str(&format!("  document: {}\n", quote_string(document)?));
+    }
+    for p in &tx.postings {
+        check_account_name("posting account", &p.account)?;
+        check_posting_amount(p)?;
+        let flag = match &p.flag {
+            Some(flag) => {
+                check_posting_flag(flag)?;
+                format!("{} ", flag)
+            }
+            None => String::new(),
+        };
+        text.push_str(&posting_line(&flag, p, amount_column));
+    }
+    if let Some(document) = &tx.document {
+        // Link the attachment to the expense leg, or the first posting.
+        let account = tx
+            .postings
+            .iter()
+            .find(|p| account_matches(&p.account, "Expenses"))
+            .or_else(|| tx.postings.first())
+            .map(|p| p.account.as_str())
+            .ok_or_else(|| LedgerError::invalid("A transaction with a document needs a posting"))?;
+        text.push_str(&format!("{} document {} {}\n", tx.date, account, quote_string(document)?));
+    }
+    Ok(text)
+}
+
+fn ensure_included(data_dir: &Path, filename: &str) -> Result<()> {
     let main_path = data_dir.join("main.bean");
     let main_content = fs::read_to_string(&main_path).unwrap_or_default();
     let include_line = format!("include \"{}\"", filename);
     if !main_content.contains(&include_line) {
-        let mut main_file = fs::OpenOptions::new().create(true).append(true).open(&main_path)?;
-        main_file.write_all(format!("\n{}\n", include_line).as_bytes())?;
+        fsio::append(&main_path, &format!("\n{}\n", include_line))?;
     }
-    
     Ok(())
 }
 
-pub fn delete_transaction(id: &str) -> Result<()> {
-    let parts: Vec<&str> = id.splitn(2, ':').collect();
-    if parts.len() != 2 {
-        return Err(anyhow::anyhow!("Invalid ID"));
-    }
-    let path_str = parts[0];
-    let start_byte: usize = parts[1].parse()?;
-    
-    let path = PathBuf::from(path_str);
+pub fn delete_transaction(data_dir: &Path, id: &str) -> Result<()> {
+    let (path, start_byte) = resolve_id(data_dir, id)?;
     let content = fs::read_to_string(&path)?;
     
-    let sources = BeancountSources::try_from(path.clone())
-        .map_err(|e| anyhow::anyhow!("Failed to load sources: {}", e))?;
+    let sources = file_sources(&content)?;
     let parser = BeancountParser::new(&sources);
-    let result = parser.parse().map_err(|e| anyhow::anyhow!("Parse error: {:?}", e))?;
+    let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
     
     let mut target_span = None;
+    let mut attachment = None;
     
-    for directive in result.directives {
-        if let DirectiveVariant::Transaction(t) = directive.variant() {
-             if directive.date().span().start == start_byte {
-                 let mut end = directive.date().span().end;
-                 if let Some(n) = t.narration() {
-                     end = std::cmp::max(end, n.span().end);
-                 }
-                 for p in t.postings() {
-                     end = std::cmp::max(end, p.account().span().end);
-                     if let Some(a) = p.amount() {
-                         end = std::cmp::max(end, a.span().end);
-                     }
-                 }
-                 
-                 target_span = Some(start_byte..end);
-                 break;
-             }
+    for directive in &result.directives {
+        if matches!(directive.variant(), DirectiveVariant::Transaction(_)) && directive.date().span().start == start_byte {
+            // The directive's own span runs through its last posting,
+            // including costs, prices and posting metadata.
+            target_span = Some(start_byte..directive.span().end);
+            attachment = meta_string(directive.metadata(), "document")
+                .map(|doc| (directive.date().item().to_string(), doc));
+            break;
         }
     }
-    
-    if let Some(span) = target_span {
-        let mut end = span.end;
-        while end < content.len() && &content[end..end+1] != "\n" {
-            end += 1;
-        }
-        if end < content.len() && &content[end..end+1] == "\n" {
-            end += 1;
+
+    let Some(mut span) = target_span else {
+        return Err(LedgerError::not_found("Transaction not found").into());
+    };
+    with_leading_blank(&content, &mut span);
+    let mut spans = vec![span];
+
+    // A transaction written with an attachment has a matching `document`
+    // directive; remove it too so the two stay consistent.
+    if let Some((date, doc)) = attachment {
+        let linked = result.directives.iter().find(|d| match d.variant() {
+            DirectiveVariant::Document(document) => {
+                d.date().item().to_string() == date && document.path().item().to_string() == doc
+            }
+            _ => false,
+        });
+        if let Some(d) = linked {
+            spans.push(d.date().span().start..d.span().end);
         }
-        
-        let new_content = format!("{}{}", &content[..start_byte], &content[end..]);
-        fs::write(&path, new_content)?;
-    } else {
-        return Err(anyhow::anyhow!("Transaction not found"));
     }
+
+    let new_content = remove_spans(&content, spans);
+    fsio::write_atomic(&path, &new_content)?;
     
     Ok(())
 }
 
-pub fn update_transaction(data_dir: &Path, id: &str, tx: Transaction) -> Result<()> {
-    delete_transaction(id)?;
-    add_transaction(data_dir, tx)?; 
+/// The file holding transaction `id`, its contents, and the byte span of
+/// the entry from its date through its last posting.
+fn transaction_span(data_dir: &Path, id: &str) -> Result<(PathBuf, String, std::ops::Range<usize>)> {
+    let (path, start_byte) = resolve_id(data_dir, id)?;
+    let content = fs::read_to_string(&path)?;
+    let sources = file_sources(&content)?;
+    let parser = BeancountParser::new(&sources);
+    let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+    let span = result
+        .directives
+        .iter()
+        .find(|d| matches!(d.variant(), DirectiveVariant::Transaction(_)) && d.date().span().start == start_byte)
+        .map(|d| start_byte..d.span().end)
+        .ok_or_else(|| LedgerError::not_found("Transaction not found"))?;
+    Ok((path, content, span))
+}
+
+/// The transaction's source text exactly as it is in the file.
+pub fn transaction_source(data_dir: &Path, id: &str) -> Result<String> {
+    let (_, content, span) = transaction_span(data_dir, id)?;
+    Ok(content[span].to_string())
+}
+
+/// Replaces the transaction's source text with `text`, leaving the rest of
+/// the file untouched. `text` is checked on its own before the file is
+/// written: it must parse as exactly one transaction, with nothing but
+/// comments around it.
+pub fn replace_transaction_source(data_dir: &Path, id: &str, text: &str) -> Result<()> {
+    let (path, content, span) = transaction_span(data_dir, id)?;
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(LedgerError::invalid("Replacement text is empty; delete the transaction instead").into());
+    }
+    check_transaction_source(text)?;
+    // Keep whatever line ending the old entry's span included.
+    let ending = if content[span.clone()].ends_with('\n') { "\n" } else { "" };
+    let updated = format!("{}{}{}{}", &content[..span.start], text, ending, &content[span.end..]);
+    fsio::write_atomic(&path, &updated)?;
+    Ok(())
+}
+
+/// Rejects replacement source that isn't a single transaction. Lines
+/// outside it must be blank or comments, so no `include`, `option`,
+/// `plugin` or `pushtag` can be spliced into the ledger along with it.
+fn check_transaction_source(text: &str) -> Result<()> {
+    let sources = file_sources(text)?;
+    let parser = BeancountParser::new(&sources);
+    let result = parser.parse().map_err(|e| LedgerError::invalid(format!("Parse error: {:?}", e)))?;
+    let not_one = || LedgerError::invalid("Replacement text must be exactly one transaction");
+    let [directive] = result.directives.as_slice() else {
+        return Err(not_one().into());
+    };
+    if !matches!(directive.variant(), DirectiveVariant::Transaction(_)) {
+        return Err(not_one().into());
+    }
+
+    let entry = directive.date().span().start..directive.span().end;
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        let end = start + line.len();
+        let outside = end <= entry.start || start >= entry.end;
+        let trimmed = line.trim();
+        if outside && !trimmed.is_empty() && !trimmed.starts_with(';') {
+            return Err(LedgerError::invalid(format!(
+                "Replacement text may hold only the transaction and comments, not {:?}",
+                trimmed
+            ))
+            .into());
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+/// Widens `span` over the blank line written before each new entry, so
+/// repeated adds and deletes don't pile up blank lines. A comment line
+/// directly above is left alone.
+fn with_leading_blank(content: &str, span: &mut std::ops::Range<usize>) {
+    let before = &content[..span.start];
+    if before == "\n" || before.ends_with("\n\n") {
+        span.start -= 1;
+    }
+}
+
+/// Removes each span from `content`, extending it through the end of its
+/// last line.
+fn remove_spans(content: &str, mut spans: Vec<std::ops::Range<usize>>) -> String {
+    spans.sort_by_key(|s| std::cmp::Reverse(s.start));
+    let mut content = content.to_string();
+    for span in spans {
+        // A span that already ends with its newline is a whole line.
+        let end = if content[..span.end].ends_with('\n') {
+            span.end
+        } else {
+            content[span.end..].find('\n').map_or(content.len(), |i| span.end + i + 1)
+        };
+        content.replace_range(span.start..end, "");
+    }
+    content
+}
+
+/// Replaces a transaction, keeping its stable ID (a transaction addressed by
+/// position is given one).
+pub fn update_transaction(data_dir: &Path, scheme: FileScheme, amount_column: Option<usize>, id: &str, mut tx: Transaction) -> Result<()> {
+    let (path, _) = resolve_id(data_dir, id)?;
+    tx.id = is_stable_id(id).then(|| id.to_string());
+    let original = fs::read_to_string(&path)?;
+    delete_transaction(data_dir, id)?;
+    if let Err(e) = add_transaction(data_dir, scheme, amount_column, tx) {
+        fsio::restore(&path, Some(&original))?;
+        return Err(e);
+    }
     Ok(())
 }
 
+/// Checks every posting account of `tx` that has an `open` is open on its
+/// date and not yet closed. Accounts that were never opened are returned,
+/// dated to the transaction, when `auto_open` is set; otherwise they are
+/// left to the validation profile's `reject_unknown_accounts`. Postings must
+/// also be in one of the currencies the account was opened with, if it
+/// declared any.
+pub fn check_posting_accounts(tx: &Transaction, accounts: &[Account], auto_open: bool) -> Result<Vec<Account>> {
+    let mut to_open: Vec<Account> = Vec::new();
+    let mut problems = Vec::new();
+    for p in &tx.postings {
+        match accounts.iter().find(|a| a.name == p.account && !a.open_date.is_empty()) {
+            None if auto_open => {
+                if !to_open.iter().any(|a| a.name == p.account) {
+                    to_open.push(Account {
+                        name: p.account.clone(),
+                        open_date: tx.date.clone(),
+                        currencies: Vec::new(),
+                        close_date: None,
+                        warning: None,
+                    });
+                }
+            }
+            None => {}
+            Some(a) if tx.date < a.open_date => problems.push(format!("{} is not opened until {}", p.account, a.open_date)),
+            Some(a) => {
+                if let Some(closed) = a.close_date.as_ref().filter(|closed| tx.date > **closed) {
+                    problems.push(format!("{} was closed on {}", p.account, closed));
+                }
+                if !p.currency.is_empty() && !a.currencies.is_empty() && !a.currencies.contains(&p.currency) {
+                    problems.push(format!(
+                        "{} doesn't allow {} (allowed: {})",
+                        p.account,
+                        p.currency,
+                        a.currencies.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return Err(LedgerError::new(ErrorKind::Validation, format!("Invalid posting accounts: {}", problems.join("; "))).into());
+    }
+    Ok(to_open)
+}
+
+/// Opens `accounts`, then runs `write`; if that fails the opens are removed
+/// again so the two land together or not at all.
+pub fn with_opened_accounts<T>(data_dir: &Path, accounts: &[Account], write: impl FnOnce() -> Result<T>) -> Result<T> {
+    if accounts.is_empty() {
+        return write();
+    }
+    let path = data_dir.join("accounts.bean");
+    let original = fs::read_to_string(&path).ok();
+    let result = accounts
+        .iter()
+        .try_for_each(|a| add_account(data_dir, a.clone()))
+        .and_then(|_| write());
+    if result.is_err() {
+        fsio::restore(&path, original.as_deref())?;
+    }
+    result
+}
+
 pub fn add_account(data_dir: &Path, account: Account) -> Result<()> {
+    check_account(&account)?;
     let path = data_dir.join("accounts.bean");
     let text = format!("{} open {} {}\n", account.open_date, account.name, account.currencies.join(","));
-    use std::io::Write;
-    let mut file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
-    file.write_all(text.as_bytes())?;
+    fsio::append(&path, &text)?;
     Ok(())
 }
 
-pub fn delete_account(data_dir: &Path, name: &str) -> Result<()> {
-    let path = data_dir.join("accounts.bean");
-    let content = fs::read_to_string(&path)?;
-    
-    let lines: Vec<&str> = content.lines().filter(|l| !l.contains(&format!("open {}", name))).collect();
-    fs::write(&path, lines.join("\n") + "\n")?;
+/// Removes every `open` directive for exactly `name`, from whichever ledger
+/// files hold one, included ones too. Unless `force` is set, an account still
+/// referenced by postings, its own or a sub-account's, is refused with a
+/// conflict.
+pub fn delete_account(data_dir: &Path, name: &str, force: bool) -> Result<()> {
+    // Match on parsed `open` directives rather than text so that e.g.
+    // Assets:Bank never takes Assets:Bank2 with it, and remove only their
+    // spans so every other line of the file is left as it was.
+    let mut rewrites = Vec::new();
+    for path in all_ledger_files(data_dir)? {
+        let content = fs::read_to_string(&path)?;
+        let sources = file_sources(&content)?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        let spans: Vec<_> = result
+            .directives
+            .iter()
+            .filter(|directive| matches!(directive.variant(), DirectiveVariant::Open(o) if o.account().item().to_string() == name))
+            .map(|directive| {
+                let mut span = directive.date().span().start..directive.span().end;
+                with_leading_blank(&content, &mut span);
+                span
+            })
+            .collect();
+        if !spans.is_empty() {
+            let updated = remove_spans(&content, spans);
+            rewrites.push((path, content, updated));
+        }
+    }
+    if rewrites.is_empty() {
+        return Err(LedgerError::not_found(format!("Account {} not found", name)).into());
+    }
+
+    if !force {
+        let referencing = list_transactions(data_dir)?
+            .iter()
+            .filter(|tx| tx.postings.iter().any(|p| account_matches(&p.account, name)))
+            .count();
+        if referencing > 0 {
+            return Err(LedgerError::new(
+                ErrorKind::Conflict,
+                format!("Account {} or its sub-accounts are used by {} transaction(s); pass force=true to delete it anyway", name, referencing),
+            )
+            .into());
+        }
+    }
+
+    // Write every file, putting back the ones already written if one fails.
+    for (i, (path, _, updated)) in rewrites.iter().enumerate() {
+        if let Err(e) = fsio::write_atomic(path, updated) {
+            for (path, original, _) in &rewrites[..i] {
+                fsio::restore(path, Some(original))?;
+            }
+            return Err(e.into());
+        }
+    }
     Ok(())
 }
 
+/// Upserts an account. An existing `open` directive, in whichever ledger
+/// file holds it, is rewritten in place: only its date and currencies
+/// change, so the directive keeps its position, metadata, booking method and
+/// trailing comment. A body naming a different account is refused; renames
+/// go through `rename_account`, which rewrites every reference.
 pub fn update_account(data_dir: &Path, name: &str, account: Account) -> Result<()> {
-    delete_account(data_dir, name)?;
-    add_account(data_dir, account)?;
+    if account.name != name {
+        return Err(LedgerError::invalid(format!(
+            "Account name {} in the body doesn't match {} in the path; use the rename endpoint to rename an account",
+            account.name, name
+        ))
+        .into());
+    }
+
+    let mut target = None;
+    for path in all_ledger_files(data_dir)? {
+        let content = fs::read_to_string(&path)?;
+        let sources = file_sources(&content)?;
+        let parser = BeancountParser::new(&sources);
+        let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+        let span = result.directives.iter().find_map(|directive| match directive.variant() {
+            DirectiveVariant::Open(o) if o.account().item().to_string() == name => {
+                let end = o
+                    .currencies()
+                    .map(|c| c.span().end)
+                    .fold(o.account().span().end, std::cmp::max);
+                Some(directive.date().span().start..end)
+            }
+            _ => None,
+        });
+        if let Some(span) = span {
+            target = Some((path, content, span));
+            break;
+        }
+    }
+    let Some((path, content, span)) = target else {
+        return add_account(data_dir, account);
+    };
+
+    check_account(&account)?;
+    let mut line = format!("{} open {}", account.open_date, account.name);
+    if !account.currencies.is_empty() {
+        line.push_str(&format!(" {}", account.currencies.join(",")));
+    }
+
+    let mut updated = content[..span.start].to_string();
+    updated.push_str(&line);
+    updated.push_str(&content[span.end..]);
+    fsio::write_atomic(&path, &updated)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A data directory holding `files`, as (name, content).
+    fn ledger(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn check_lots_reports_only_unheld_reductions() {
+        let dir = ledger(&[(
+            "2024.bean",
+            r#"
+2024-01-05 * "Buy"
+  Assets:Broker  10 AAPL {100 USD}
+  Assets:Cash  -1000 USD
+
+2024-02-01 * "Sell"
+  Assets:Broker  -5 AAPL {100 USD}
+  Assets:Cash  500 USD
+
+2024-03-01 * "Sell a lot never bought"
+  Assets:Broker  -2 MSFT {50 USD}
+  Assets:Cash  100 USD
+"#,
+        )]);
+        let issues = check_lots(dir.path()).unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].date, "2024-03-01");
+        assert_eq!(issues[0].currency, "MSFT");
+        assert_eq!(issues[0].unmatched_units, "2");
+    }
+
+    fn posting(account: &str, amount: &str, currency: &str) -> Posting {
+        Posting {
+            flag: None,
+            account: account.to_string(),
+            amount: amount.to_string(),
+            currency: currency.to_string(),
+            cost: None,
+            price: None,
+        }
+    }
+
+    fn quick_entry(payee: Option<&str>) -> Transaction {
+        Transaction {
+            id: None,
+            date: "2024-03-01".to_string(),
+            flag: "*".to_string(),
+            payee: payee.map(str::to_string),
+            narration: None,
+            tags: Vec::new(),
+            postings: vec![posting("Expenses:Food", "4.50", "USD"), posting("Assets:Cash", "", "")],
+            document: None,
+        }
+    }
+
+    /// The header line `tx` is written with, after `mode` is applied.
+    fn header_with(mode: NarrationDefault, mut tx: Transaction) -> Result<String> {
+        apply_narration_default(&mut tx, mode)?;
+        let text = transaction_text(&tx, "id", None)?;
+        Ok(text.lines().nth(1).unwrap().to_string())
+    }
+
+    #[test]
+    fn narration_default_payee_copies_the_payee() {
+        let header = header_with(NarrationDefault::Payee, quick_entry(Some("Cafe"))).unwrap();
+        assert_eq!(header, r#"2024-03-01 * "Cafe" "Cafe""#);
+    }
+
+    #[test]
+    fn narration_default_omit_writes_no_empty_string() {
+        let header = header_with(NarrationDefault::Omit, quick_entry(None)).unwrap();
+        assert_eq!(header, "2024-03-01 *");
+    }
+
+    #[test]
+    fn narration_default_require_rejects_a_missing_narration() {
+        let err = header_with(NarrationDefault::Require, quick_entry(Some("Cafe"))).unwrap_err();
+        assert_eq!(crate::error::kind(&err), ErrorKind::Validation);
+        let mut tx = quick_entry(Some("Cafe"));
+        tx.narration = Some("Coffee".to_string());
+        assert_eq!(header_with(NarrationDefault::Require, tx).unwrap(), r#"2024-03-01 * "Cafe" "Coffee""#);
+    }
+
+    #[test]
+    fn header_writes_only_the_strings_present() {
+        assert_eq!(format_txn_strings(None, Some("Lunch")).unwrap(), r#" "Lunch""#);
+        assert_eq!(format_txn_strings(Some("Cafe"), Some("Lunch")).unwrap(), r#" "Cafe" "Lunch""#);
+        assert_eq!(format_txn_strings(None, None).unwrap(), "");
+        assert_eq!(format_txn_strings(Some(""), Some("")).unwrap(), "");
+    }
+
+    #[test]
+    fn account_roots_count_opened_and_used_accounts() {
+        let dir = ledger(&[
+            (
+                "main.bean",
+                "include \"accounts.bean\"\ninclude \"2024-03.bean\"\n",
+            ),
+            (
+                "accounts.bean",
+                "2024-01-01 open Assets:Cash\n2024-01-01 open Assets:Bank:Checking\n2024-01-01 open Expenses:Food\n",
+            ),
+            (
+                "2024-03.bean",
+                r#"
+2024-03-01 * "Lunch"
+  Expenses:Food  12.50 USD
+  Assets:Cash
+
+2024-03-02 * "Gift"
+  Income:Gifts  -5 USD
+  Expenses:Gifts  5 USD
+"#,
+            ),
+        ]);
+        let roots: Vec<(String, usize)> = account_roots(dir.path()).unwrap().into_iter().map(|r| (r.name, r.count)).collect();
+        assert_eq!(
+            roots,
+            [("Assets".to_string(), 2), ("Expenses".to_string(), 2), ("Income".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn attachment_writes_metadata_and_a_document_directive() {
+        let dir = ledger(&[]);
+        let mut tx = quick_entry(Some("Cafe"));
+        tx.narration = Some("Lunch".to_string());
+        tx.document = Some("receipts/lunch.pdf".to_string());
+        add_transaction(dir.path(), FileScheme::Monthly, None, tx).unwrap();
+
+        let content = fs::read_to_string(dir.path().join("2024-03.bean")).unwrap();
+        assert!(content.contains("  document: \"receipts/lunch.pdf\"\n"));
+        assert!(content.contains("2024-03-01 document Expenses:Food \"receipts/lunch.pdf\"\n"));
+        let txs = list_transactions(dir.path()).unwrap();
+        assert_eq!(txs[0].document.as_deref(), Some("receipts/lunch.pdf"));
+    }
+
+    #[test]
+    fn amount_with_currency_crammed_in_is_rejected() {
+        let err = check_posting_amount(&posting("Expenses:Food", "10 USD", "")).unwrap_err();
+        assert_eq!(crate::error::kind(&err), ErrorKind::Invalid);
+        assert!(err.to_string().contains("put \"USD\" in the currency field"));
+        check_posting_amount(&posting("Expenses:Food", "10", "USD")).unwrap();
+        check_posting_amount(&posting("Expenses:Food", "", "")).unwrap();
+    }
+
+    #[test]
+    fn amounts_follow_beancount_number_syntax() {
+        assert_eq!(parse_amount(" +12.50 ").unwrap(), Decimal::new(1250, 2));
+        assert_eq!(parse_amount("-3").unwrap(), Decimal::new(-3, 0));
+        for bad in ["1_000", "1e3", ".5", "10.", "1,000.00", "USD", ""] {
+            assert!(parse_amount(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn posting_flag_round_trips() {
+        let dir = ledger(&[]);
+        let mut tx = quick_entry(None);
+        tx.narration = Some("Check".to_string());
+        tx.postings[1].flag = Some("!".to_string());
+        add_transaction(dir.path(), FileScheme::Monthly, None, tx).unwrap();
+
+        let content = fs::read_to_string(dir.path().join("2024-03.bean")).unwrap();
+        assert!(content.contains("\n  ! Assets:Cash"));
+        let txs = list_transactions(dir.path()).unwrap();
+        assert_eq!(txs[0].postings[0].flag, None);
+        assert_eq!(txs[0].postings[1].flag.as_deref(), Some("!"));
+    }
+
+    #[test]
+    fn posting_flag_must_be_one_flag_character() {
+        for bad in ["!!", "x", "", " "] {
+            let mut tx = quick_entry(None);
+            tx.postings[0].flag = Some(bad.to_string());
+            assert!(transaction_text(&tx, "id", None).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    fn account(name: &str, currencies: &[&str]) -> Account {
+        Account {
+            name: name.to_string(),
+            open_date: "2024-01-01".to_string(),
+            currencies: currencies.iter().map(|c| c.to_string()).collect(),
+            close_date: None,
+            warning: None,
+        }
+    }
+
+    #[test]
+    fn update_account_keeps_metadata_and_position() {
+        let dir = ledger(&[
+            ("main.bean", "include \"accounts.bean\"\n"),
+            (
+                "accounts.bean",
+                "include \"banks.bean\"\n\n2024-01-01 open Assets:Cash USD ; wallet\n  description: \"Wallet\"\n\n2024-01-01 open Expenses:Food\n",
+            ),
+            ("banks.bean", "2024-01-01 open Assets:Bank\n  bank: \"ACME\"\n"),
+        ]);
+
+        update_account(dir.path(), "Assets:Cash", account("Assets:Cash", &["USD", "EUR"])).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("accounts.bean")).unwrap(),
+            "include \"banks.bean\"\n\n2024-01-01 open Assets:Cash USD,EUR ; wallet\n  description: \"Wallet\"\n\n2024-01-01 open Expenses:Food\n"
+        );
+
+        // An open in a nested include is edited where it is.
+        update_account(dir.path(), "Assets:Bank", account("Assets:Bank", &["EUR"])).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("banks.bean")).unwrap(),
+            "2024-01-01 open Assets:Bank EUR\n  bank: \"ACME\"\n"
+        );
+    }
+
+    #[test]
+    fn update_account_refuses_a_different_name_in_the_body() {
+        let dir = ledger(&[("main.bean", "include \"accounts.bean\"\n"), ("accounts.bean", "2024-01-01 open Assets:Cash\n")]);
+        let err = update_account(dir.path(), "Assets:Cash", account("Assets:Wallet", &[])).unwrap_err();
+        assert_eq!(crate::error::kind(&err), ErrorKind::Invalid);
+        assert_eq!(fs::read_to_string(dir.path().join("accounts.bean")).unwrap(), "2024-01-01 open Assets:Cash\n");
+    }
+
+    #[test]
+    fn add_account_rejects_invalid_names() {
+        let dir = ledger(&[]);
+        for bad in ["expenses:food", "Expenses:food", "Expenses:Dining Out", "Expenses", "Expenses::Food", "Stuff:Food", "Expenses:Food!"] {
+            let err = add_account(dir.path(), account(bad, &[])).unwrap_err();
+            assert_eq!(crate::error::kind(&err), ErrorKind::Invalid, "{:?} should be rejected", bad);
+        }
+        assert!(!dir.path().join("accounts.bean").exists());
+
+        add_account(dir.path(), account("Expenses:Food-2024:2Nd", &[])).unwrap();
+        assert!(fs::read_to_string(dir.path().join("accounts.bean")).unwrap().contains("open Expenses:Food-2024:2Nd"));
+    }
+
+    #[test]
+    fn delete_account_leaves_similarly_named_accounts() {
+        let dir = ledger(&[(
+            "accounts.bean",
+            "; banks\n2024-01-01 open Assets:Bank\n\n2024-01-01 open Assets:Bank2 USD\n  bank: \"ACME\"\n\n2024-01-01 open Assets:Banking\n2024-06-01 close Assets:Banking\n",
+        )]);
+
+        delete_account(dir.path(), "Assets:Bank", false).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("accounts.bean")).unwrap(),
+            "; banks\n\n2024-01-01 open Assets:Bank2 USD\n  bank: \"ACME\"\n\n2024-01-01 open Assets:Banking\n2024-06-01 close Assets:Banking\n"
+        );
+        let err = delete_account(dir.path(), "Assets:Bank", false).unwrap_err();
+        assert_eq!(crate::error::kind(&err), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn account_detail_counts_sub_accounts() {
+        let dir = ledger(&[(
+            "main.bean",
+            "2024-01-01 open Assets:Bank USD\n2024-01-01 open Assets:Bank:Savings USD\n2024-01-01 open Assets:Bank2 USD\n2024-01-01 open Income:Job\n\n\
+             2024-01-05 * \"Salary\"\n  Assets:Bank  100.00 USD\n  Income:Job  -100.00 USD\n\n\
+             2024-02-01 * \"Save\"\n  Assets:Bank:Savings  50.00 USD\n  Assets:Bank  -50.00 USD\n\n\
+             2024-03-01 * \"Other bank\"\n  Assets:Bank2  5.00 USD\n  Income:Job  -5.00 USD\n",
+        )]);
+
+        let detail = account_detail(dir.path(), "Assets:Bank").unwrap();
+        assert_eq!(detail.open_date, "2024-01-01");
+        assert_eq!(detail.close_date, None);
+        assert_eq!(detail.currencies, vec!["USD"]);
+        assert_eq!(detail.balances, BTreeMap::from([("USD".to_string(), "100.00".to_string())]));
+        assert_eq!(detail.postings, 3);
+        assert_eq!(detail.first_transaction.as_deref(), Some("2024-01-05"));
+        assert_eq!(detail.last_transaction.as_deref(), Some("2024-02-01"));
+        assert_eq!(detail.children, vec!["Assets:Bank:Savings"]);
+
+        let err = account_detail(dir.path(), "Assets:Nowhere").unwrap_err();
+        assert_eq!(crate::error::kind(&err), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn repeated_add_and_delete_leaves_the_file_unchanged() {
+        let original = "2024-03-01 * \"Coffee\"\n  Expenses:Food  3.00 USD\n  Assets:Cash\n\n; keep this note\n";
+        let dir = ledger(&[("main.bean", "include \"2024-03.bean\"\n"), ("2024-03.bean", original)]);
+        let mut tx = quick_entry(None);
+        tx.narration = Some("Lunch".to_string());
+        for _ in 0..5 {
+            let id = add_transaction(dir.path(), FileScheme::Monthly, None, tx.clone()).unwrap();
+            delete_transaction(dir.path(), &id).unwrap();
+        }
+        assert_eq!(fs::read_to_string(dir.path().join("2024-03.bean")).unwrap(), original);
+    }
+
+    #[test]
+    fn delete_removes_cost_postings_and_their_metadata() {
+        let kept = "2024-03-01 * \"Coffee\"\n  Expenses:Food  3.00 USD\n  Assets:Cash\n";
+        let buy = "\n2024-03-02 * \"Buy\"\n  id: \"buy\"\n  Assets:Broker  10 AAPL {150.00 USD, 2024-03-02} @ 151.00 USD\n    lot: \"first\"\n  Assets:Cash  -1500.00 USD\n    note: \"settled\"\n";
+        let dir = ledger(&[("main.bean", "include \"2024-03.bean\"\n"), ("2024-03.bean", &format!("{}{}", kept, buy))]);
+
+        delete_transaction(dir.path(), "buy").unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("2024-03.bean")).unwrap(), kept);
+        assert_eq!(list_transactions(dir.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn amounts_are_aligned_to_the_configured_column() {
+        let dir = ledger(&[]);
+        let mut tx = quick_entry(None);
+        tx.narration = Some("Lunch".to_string());
+        add_transaction(dir.path(), FileScheme::Monthly, Some(50), tx.clone()).unwrap();
+        let content = fs::read_to_string(dir.path().join("2024-03.bean")).unwrap();
+        let line = content.lines().find(|l| l.contains("Expenses:Food")).unwrap();
+        assert_eq!(line.find(" USD"), Some(50), "{:?}", line);
+        assert!(line.ends_with("4.50 USD"));
+
+        // Without a column the amount follows a single space.
+        assert_eq!(posting_line("", &tx.postings[0], None), "  Expenses:Food 4.50 USD\n");
+        // An account too long for the column still gets two spaces.
+        let long = posting("Expenses:Food:Restaurants:Downtown:Lunch", "4.50", "USD");
+        assert_eq!(posting_line("", &long, Some(30)), "  Expenses:Food:Restaurants:Downtown:Lunch  4.50 USD\n");
+    }
+
+    #[test]
+    fn raw_replacement_must_be_one_transaction_alone() {
+        let original = "2024-03-01 * \"Coffee\"\n  id: \"coffee\"\n  Expenses:Food  3.00 USD\n  Assets:Cash\n";
+        let dir = ledger(&[("main.bean", "include \"2024-03.bean\"\n"), ("2024-03.bean", original)]);
+        let tx = "2024-03-01 * \"Coffee\"\n  id: \"coffee\"\n  Expenses:Food  3.50 USD\n  Assets:Cash";
+        for bad in [
+            format!("{}\ninclude \"/etc/other.bean\"", tx),
+            format!("{}\noption \"operating_currency\" \"EUR\"", tx),
+            format!("{}\nplugin \"beancount.plugins.auto\"", tx),
+            format!("pushtag #sneaky\n{}", tx),
+            format!("{}\n2024-03-02 open Assets:Evil", tx),
+            "2024-03-02 open Assets:Evil".to_string(),
+            "2024-03-01 * \"Coffee\"\n  Expenses:Food 3.50 USD USD".to_string(),
+        ] {
+            let err = replace_transaction_source(dir.path(), "coffee", &bad).unwrap_err();
+            assert_eq!(crate::error::kind(&err), ErrorKind::Invalid, "{:?} should be rejected", bad);
+            assert_eq!(fs::read_to_string(dir.path().join("2024-03.bean")).unwrap(), original);
+        }
+
+        replace_transaction_source(dir.path(), "coffee", &format!("; edited\n{}", tx)).unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("2024-03.bean")).unwrap(), format!("; edited\n{}\n", tx));
+        assert_eq!(transaction_source(dir.path(), "coffee").unwrap().trim_end(), tx);
+    }
+
+    #[test]
+    fn failed_include_update_rolls_back_the_data_write() {
+        let dir = ledger(&[("main.bean", "option \"title\" \"Test\"\n")]);
+        let plan = fsio::faults::FaultPlan {
+            fail_nth_write: Some(1),
+            path: Some(dir.path().join("main.bean").to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let mut tx = quick_entry(None);
+        tx.narration = Some("Lunch".to_string());
+
+        let injected = fsio::faults::inject_blocking(plan);
+        let result = add_transaction(dir.path(), FileScheme::Monthly, None, tx);
+        drop(injected);
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(dir.path().join("main.bean")).unwrap(), "option \"title\" \"Test\"\n");
+        assert!(!dir.path().join("2024-03.bean").exists());
+    }
+
+    #[test]
+    fn accounts_are_read_from_every_ledger_file() {
+        let dir = ledger(&[
+            ("main.bean", "include \"accounts.bean\"\n2024-01-01 open Assets:Cash\n"),
+            ("accounts.bean", "include \"nested/banks.bean\"\n2024-01-01 open Expenses:Food\n"),
+            ("2024-03.bean", "2024-03-01 open Assets:Wallet\n2024-06-01 close Expenses:Food\n"),
+        ]);
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("nested/banks.bean"), "2024-01-01 open Assets:Bank\n").unwrap();
+
+        let accounts = list_accounts(dir.path()).unwrap();
+        let mut names: Vec<&str> = accounts.iter().map(|a| a.name.as_str()).collect();
+        names.sort();
+        // Each open is counted once though accounts.bean is both included
+        // and a top-level file.
+        assert_eq!(names, vec!["Assets:Bank", "Assets:Cash", "Assets:Wallet", "Expenses:Food"]);
+        let food = accounts.iter().find(|a| a.name == "Expenses:Food").unwrap();
+        assert_eq!(food.close_date.as_deref(), Some("2024-06-01"));
+    }
+}
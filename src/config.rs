@@ -0,0 +1,340 @@
+use crate::error::{self, ErrorKind};
+use crate::validation::ValidationProfile;
+use anyhow::{Context, Result};
+use axum::http::StatusCode;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
+const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_DATA_DIR: &str = "data";
+const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+const DEFAULT_AMOUNT_COLUMN: usize = 50;
+
+/// Startup configuration, read from `--flag value` CLI arguments first and
+/// `BEANCOUNTERS_*` environment variables second.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub host: IpAddr,
+    pub port: u16,
+    pub data_dir: PathBuf,
+    /// Whether `data_dir` was given explicitly rather than defaulted.
+    pub data_dir_explicit: bool,
+    pub narration_default: NarrationDefault,
+    /// How new transactions and dated directives are split into files.
+    pub file_scheme: FileScheme,
+    /// Column posting amounts written through the API end at, if aligning.
+    pub amount_column: Option<usize>,
+    /// The profile applied to every transaction write.
+    pub validation_profile: ValidationProfile,
+    pub available_profiles: Vec<String>,
+    /// Commit the data directory to git after every write request.
+    pub git_autocommit: bool,
+    /// Commit message with `{operation}`, `{request_id}` and `{summary}`
+    /// placeholders.
+    pub git_message_template: String,
+    /// Overrides for the default error-kind-to-status mapping.
+    pub error_status: HashMap<ErrorKind, StatusCode>,
+    /// Reuse `/verify` results while the ledger is unchanged.
+    pub verify_cache: bool,
+    /// Largest list returned without `limit`; longer lists must be paginated.
+    pub max_response_items: Option<usize>,
+    /// Decimal mark accepted in incoming amounts (`.` or `,`).
+    pub decimal_separator: char,
+    /// Bearer token required on every route but the docs, if set.
+    pub api_token: Option<String>,
+    /// Largest request body accepted, in bytes.
+    pub max_body_bytes: usize,
+    /// Origins allowed to call the API from a browser; `*` allows any. Empty
+    /// sends no CORS headers.
+    pub cors_origins: Vec<String>,
+}
+
+/// What to do when a new transaction arrives without a narration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NarrationDefault {
+    /// Use the payee as the narration.
+    Payee,
+    /// Leave the narration out of the written directive.
+    #[default]
+    Omit,
+    /// Reject the transaction.
+    Require,
+}
+
+impl std::fmt::Display for NarrationDefault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Payee => "payee",
+            Self::Omit => "omit",
+            Self::Require => "require",
+        })
+    }
+}
+
+impl std::str::FromStr for NarrationDefault {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "payee" => Ok(Self::Payee),
+            "omit" => Ok(Self::Omit),
+            "require" => Ok(Self::Require),
+            _ => Err(anyhow::anyhow!("expected one of payee, omit, require")),
+        }
+    }
+}
+
+/// Which file a dated entry written through the API goes into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileScheme {
+    /// `YYYY-MM.bean`
+    #[default]
+    Monthly,
+    /// `YYYY-Qn.bean`
+    Quarterly,
+    /// `YYYY.bean`
+    Yearly,
+    /// Everything in `ledger.bean`
+    Single,
+}
+
+impl FileScheme {
+    pub fn filename(self, date: chrono::NaiveDate) -> String {
+        use chrono::Datelike;
+        match self {
+            Self::Monthly => format!("{}-{:02}.bean", date.year(), date.month()),
+            Self::Quarterly => format!("{}-Q{}.bean", date.year(), date.month0() / 3 + 1),
+            Self::Yearly => format!("{}.bean", date.year()),
+            Self::Single => "ledger.bean".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for FileScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Monthly => "monthly",
+            Self::Quarterly => "quarterly",
+            Self::Yearly => "yearly",
+            Self::Single => "single",
+        })
+    }
+}
+
+impl std::str::FromStr for FileScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "monthly" => Ok(Self::Monthly),
+            "quarterly" => Ok(Self::Quarterly),
+            "yearly" => Ok(Self::Yearly),
+            "single" => Ok(Self::Single),
+            _ => Err(anyhow::anyhow!("expected one of monthly, quarterly, yearly, single")),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+
+        let host = match setting(&args, "host", "BEANCOUNTERS_HOST") {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("Invalid host '{}': expected an IP address", v))?,
+            None => DEFAULT_HOST,
+        };
+        let port = match setting(&args, "port", "BEANCOUNTERS_PORT") {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("Invalid port '{}': expected a number from 0 to 65535", v))?,
+            None => DEFAULT_PORT,
+        };
+
+        let (data_dir, data_dir_explicit) = match setting(&args, "data-dir", "BEANCOUNTERS_DATA_DIR") {
+            Some(v) => (PathBuf::from(v), true),
+            None => (PathBuf::from(DEFAULT_DATA_DIR), false),
+        };
+        let narration_default = match setting(&args, "narration-default", "BEANCOUNTERS_NARRATION_DEFAULT") {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("Invalid narration default '{}'", v))?,
+            None => NarrationDefault::default(),
+        };
+        let file_scheme = match setting(&args, "file-scheme", "BEANCOUNTERS_FILE_SCHEME") {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("Invalid file scheme '{}'", v))?,
+            None => FileScheme::default(),
+        };
+        let align_amounts = match setting(&args, "align-amounts", "BEANCOUNTERS_ALIGN_AMOUNTS") {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("Invalid align amounts flag '{}': expected true or false", v))?,
+            None => false,
+        };
+        let amount_column = match setting(&args, "amount-column", "BEANCOUNTERS_AMOUNT_COLUMN") {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("Invalid amount column '{}': expected a number", v))?,
+            None => DEFAULT_AMOUNT_COLUMN,
+        };
+        let amount_column = align_amounts.then_some(amount_column);
+
+        let profiles_file = setting(&args, "validation-profiles", "BEANCOUNTERS_VALIDATION_PROFILES");
+        let profiles = ValidationProfile::load_all(profiles_file.as_deref().map(std::path::Path::new))?;
+        let profile_name = setting(&args, "validation-profile", "BEANCOUNTERS_VALIDATION_PROFILE")
+            .unwrap_or_else(|| "lenient".to_string());
+        let validation_profile = profiles
+            .iter()
+            .find(|p| p.name == profile_name)
+            .cloned()
+            .with_context(|| format!("Unknown validation profile '{}'", profile_name))?;
+        let available_profiles = profiles.into_iter().map(|p| p.name).collect();
+
+        let git_autocommit = match setting(&args, "git-autocommit", "BEANCOUNTERS_GIT_AUTOCOMMIT") {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("Invalid git autocommit flag '{}': expected true or false", v))?,
+            None => false,
+        };
+        let git_message_template = setting(&args, "git-message", "BEANCOUNTERS_GIT_MESSAGE")
+            .unwrap_or_else(|| crate::git::DEFAULT_MESSAGE_TEMPLATE.to_string());
+
+        let error_status = match setting(&args, "error-status", "BEANCOUNTERS_ERROR_STATUS") {
+            Some(v) => error::parse_overrides(&v)
+                .with_context(|| format!("Invalid error status mapping '{}'", v))?,
+            None => HashMap::new(),
+        };
+
+        let verify_cache = match setting(&args, "verify-cache", "BEANCOUNTERS_VERIFY_CACHE") {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("Invalid verify cache flag '{}': expected true or false", v))?,
+            None => true,
+        };
+
+        let max_response_items = match setting(&args, "max-response-items", "BEANCOUNTERS_MAX_RESPONSE_ITEMS") {
+            Some(v) => Some(
+                v.parse()
+                    .with_context(|| format!("Invalid max response items '{}': expected a number", v))?,
+            ),
+            None => None,
+        };
+
+        let decimal_separator = match setting(&args, "decimal-separator", "BEANCOUNTERS_DECIMAL_SEPARATOR").as_deref() {
+            Some(".") | None => '.',
+            Some(",") => ',',
+            Some(v) => anyhow::bail!("Invalid decimal separator '{}': expected . or ,", v),
+        };
+
+        let max_body_bytes = match setting(&args, "max-body-bytes", "BEANCOUNTERS_MAX_BODY_BYTES") {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("Invalid max body bytes '{}': expected a number", v))?,
+            None => DEFAULT_MAX_BODY_BYTES,
+        };
+
+        // Never a command-line argument, where other users could read it in
+        // the process list: from the environment or a file only.
+        if args.iter().any(|a| a == "--api-token" || a.starts_with("--api-token=")) {
+            anyhow::bail!("--api-token is not accepted; set BEANCOUNTERS_API_TOKEN or --api-token-file instead");
+        }
+        let api_token = match setting(&args, "api-token-file", "BEANCOUNTERS_API_TOKEN_FILE") {
+            Some(path) => {
+                let token = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read API token file '{}'", path))?;
+                let token = token.trim().to_string();
+                if token.is_empty() {
+                    anyhow::bail!("API token file '{}' is empty", path);
+                }
+                Some(token)
+            }
+            None => std::env::var("BEANCOUNTERS_API_TOKEN").ok().filter(|v| !v.is_empty()),
+        };
+
+        let cors_origins = match setting(&args, "cors-origins", "BEANCOUNTERS_CORS_ORIGINS") {
+            Some(v) => {
+                let origins: Vec<String> = v.split(',').map(|o| o.trim().to_string()).filter(|o| !o.is_empty()).collect();
+                if let Some(bad) = origins.iter().find(|o| o.parse::<axum::http::HeaderValue>().is_err()) {
+                    anyhow::bail!("Invalid CORS origin '{}'", bad);
+                }
+                origins
+            }
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            host,
+            port,
+            data_dir,
+            data_dir_explicit,
+            narration_default,
+            file_scheme,
+            amount_column,
+            validation_profile,
+            available_profiles,
+            git_autocommit,
+            git_message_template,
+            error_status,
+            verify_cache,
+            max_response_items,
+            decimal_separator,
+            max_body_bytes,
+            api_token,
+            cors_origins,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// The defaults `load` gives without any arguments or environment, on
+    /// an existing `data_dir`.
+    pub fn for_tests(data_dir: &std::path::Path) -> Self {
+        let profiles = ValidationProfile::builtins();
+        Self {
+            host: DEFAULT_HOST,
+            port: DEFAULT_PORT,
+            data_dir: data_dir.to_path_buf(),
+            data_dir_explicit: true,
+            narration_default: NarrationDefault::default(),
+            file_scheme: FileScheme::default(),
+            amount_column: None,
+            validation_profile: profiles[0].clone(),
+            available_profiles: profiles.into_iter().map(|p| p.name).collect(),
+            git_autocommit: false,
+            git_message_template: crate::git::DEFAULT_MESSAGE_TEMPLATE.to_string(),
+            error_status: HashMap::new(),
+            verify_cache: true,
+            max_response_items: None,
+            decimal_separator: '.',
+            api_token: None,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+/// Looks up `--name value` / `--name=value` in `args`, falling back to the
+/// environment variable `env`.
+fn setting(args: &[String], name: &str, env: &str) -> Option<String> {
+    let flag = format!("--{}", name);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if *arg == flag {
+            return iter.next().cloned();
+        }
+        if let Some(value) = arg.strip_prefix(&flag).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    std::env::var(env).ok().filter(|v| !v.is_empty())
+}
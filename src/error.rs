@@ -0,0 +1,247 @@
+//! Error kinds raised by the ledger layer, the mapping from those kinds to
+//! HTTP statuses, which deployments can override at startup, and the JSON
+//! error body handlers answer with.
+
+use crate::validation::ValidationError;
+use axum::extract::rejection::JsonRejection;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// A ledger file failed to parse.
+    Parse,
+    NotFound,
+    /// The request itself was malformed.
+    Invalid,
+    /// The request was well-formed but rejected by a validation check.
+    Validation,
+    Conflict,
+    Internal,
+}
+
+impl ErrorKind {
+    /// The name used in override tables and in the `code` of error bodies.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Parse => "parse",
+            Self::NotFound => "not_found",
+            Self::Invalid => "invalid",
+            Self::Validation => "validation",
+            Self::Conflict => "conflict",
+            Self::Internal => "internal",
+        }
+    }
+
+    fn default_status(self) -> StatusCode {
+        match self {
+            Self::Parse => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Invalid => StatusCode::BAD_REQUEST,
+            Self::Validation => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Conflict => StatusCode::CONFLICT,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::str::FromStr for ErrorKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "parse" => Ok(Self::Parse),
+            "not_found" => Ok(Self::NotFound),
+            "invalid" => Ok(Self::Invalid),
+            "validation" => Ok(Self::Validation),
+            "conflict" => Ok(Self::Conflict),
+            "internal" => Ok(Self::Internal),
+            _ => Err(anyhow::anyhow!("Unknown error kind '{}'", s)),
+        }
+    }
+}
+
+/// An error with a known kind, carried inside `anyhow::Error`.
+#[derive(Debug)]
+pub struct LedgerError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl LedgerError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn parse(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Parse, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NotFound, message)
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Invalid, message)
+    }
+}
+
+impl std::fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+pub fn kind(e: &anyhow::Error) -> ErrorKind {
+    if let Some(e) = e.downcast_ref::<LedgerError>() {
+        e.kind
+    } else if e.is::<ValidationError>() {
+        ErrorKind::Validation
+    } else {
+        ErrorKind::Internal
+    }
+}
+
+static OVERRIDES: OnceLock<HashMap<ErrorKind, StatusCode>> = OnceLock::new();
+
+/// Parses an override table like `parse=400,not_found=404`.
+pub fn parse_overrides(spec: &str) -> anyhow::Result<HashMap<ErrorKind, StatusCode>> {
+    spec.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (kind, status) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Expected kind=status, got '{}'", entry))?;
+            let status = StatusCode::from_u16(status.trim().parse()?)?;
+            Ok((kind.trim().parse()?, status))
+        })
+        .collect()
+}
+
+/// Installs the override table; called once at startup.
+pub fn set_overrides(overrides: HashMap<ErrorKind, StatusCode>) {
+    OVERRIDES.set(overrides).ok();
+}
+
+/// The HTTP status for an error coming out of the ledger layer.
+pub fn status(e: &anyhow::Error) -> StatusCode {
+    let kind = kind(e);
+    OVERRIDES
+        .get()
+        .and_then(|o| o.get(&kind).copied())
+        .unwrap_or_else(|| kind.default_status())
+}
+
+/// An error response: `{"error": "<message>", "code": "<kind>"}` with the
+/// matching status.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiError {
+    #[serde(skip)]
+    pub status: StatusCode,
+    pub error: String,
+    /// Machine-readable kind, e.g. `not_found` or `payload_too_large`
+    pub code: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            error: message.into(),
+            code: code.to_string(),
+        }
+    }
+
+    /// An error raised outside the ledger layer, coded by its status.
+    pub fn from_status(status: StatusCode, message: impl Into<String>) -> Self {
+        let code = match status {
+            StatusCode::BAD_REQUEST => "invalid",
+            StatusCode::UNAUTHORIZED => "unauthorized",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::CONFLICT => "conflict",
+            StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+            StatusCode::UNPROCESSABLE_ENTITY => "validation",
+            _ => "internal",
+        };
+        Self::new(status, code, message)
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::from_status(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::from_status(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    /// A body over the configured size limit.
+    pub fn too_large() -> Self {
+        Self::from_status(StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large")
+    }
+
+    /// A ledger-layer error, with the status from the (overridable) mapping
+    /// and its kind as the code.
+    pub fn from_ledger(e: &anyhow::Error) -> Self {
+        Self::new(status(e), kind(e).code(), e.to_string())
+    }
+}
+
+/// Rejections of JSON request bodies, cut down to a short message: `413`
+/// for a body over the size limit, `415` without a JSON content type and
+/// `400` for anything that isn't the expected JSON.
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::JsonDataError(e) => Self::bad_request(e.body_text()),
+            JsonRejection::JsonSyntaxError(e) => Self::bad_request(e.body_text()),
+            JsonRejection::MissingJsonContentType(_) => {
+                Self::from_status(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Expected Content-Type: application/json")
+            }
+            other if other.status() == StatusCode::PAYLOAD_TOO_LARGE => Self::too_large(),
+            other => Self::from_status(other.status(), other.body_text()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_error_maps_to_the_configured_status() {
+        // The table is process-wide and set once; no other test installs one.
+        set_overrides(parse_overrides(" parse = 400 ,").unwrap());
+        let e: anyhow::Error = LedgerError::parse("Parse error: unexpected token").into();
+        let response = ApiError::from_ledger(&e);
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.code, "parse");
+
+        let e: anyhow::Error = LedgerError::new(ErrorKind::Conflict, "exists").into();
+        assert_eq!(status(&e), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn override_table_rejects_unknown_kinds_and_statuses() {
+        assert!(parse_overrides("parse=400").is_ok());
+        assert!(parse_overrides("bogus=400").is_err());
+        assert!(parse_overrides("parse=4000").is_err());
+        assert!(parse_overrides("parse").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::i18n::Locale;
+use crate::model::Transaction;
+
+pub const CSV_HEADER: [&str; 9] = [
+    "id", "date", "flag", "payee", "narration", "account", "amount", "currency", "tags",
+];
+
+/// Renders transactions as CSV with one row per posting, repeating the
+/// transaction's fields on each row. Quoting follows RFC 4180.
+pub fn transactions_csv(transactions: &[Transaction], locale: Locale) -> anyhow::Result<String> {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::CRLF)
+        .from_writer(Vec::new());
+    writer.write_record(CSV_HEADER.iter().map(|h| locale.text(*h)))?;
+    for tx in transactions {
+        let tags = tx.tags.join("|");
+        for p in &tx.postings {
+            writer.write_record([
+                tx.id.as_deref().unwrap_or_default(),
+                tx.date.as_str(),
+                tx.flag.as_str(),
+                tx.payee.as_deref().unwrap_or_default(),
+                tx.narration.as_deref().unwrap_or_default(),
+                p.account.as_str(),
+                p.amount.as_str(),
+                p.currency.as_str(),
+                tags.as_str(),
+            ])?;
+        }
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Posting;
+
+    fn transaction(payee: &str, narration: &str) -> Transaction {
+        let posting = |account: &str, amount: &str| Posting {
+            flag: None,
+            account: account.to_string(),
+            amount: amount.to_string(),
+            currency: "USD".to_string(),
+            cost: None,
+            price: None,
+        };
+        Transaction {
+            id: Some("tx1".to_string()),
+            date: "2024-03-01".to_string(),
+            flag: "*".to_string(),
+            payee: Some(payee.to_string()),
+            narration: Some(narration.to_string()),
+            tags: vec!["trip".to_string(), "work".to_string()],
+            postings: vec![posting("Expenses:Food", "12.50"), posting("Assets:Cash", "-12.50")],
+            document: None,
+        }
+    }
+
+    #[test]
+    fn payee_with_a_comma_is_quoted() {
+        let csv = transactions_csv(&[transaction("Smith, Jones & Co", "Lunch")], Locale::En).unwrap();
+        let rows: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(rows[1], r#"tx1,2024-03-01,*,"Smith, Jones & Co",Lunch,Expenses:Food,12.50,USD,trip|work"#);
+        assert_eq!(rows.len(), 4);
+    }
+
+    #[test]
+    fn output_parses_back() {
+        let tx = transaction("O'Brien \"Bob\", Ltd", "Line one, and \"two\"");
+        let csv = transactions_csv(std::slice::from_ref(&tx), Locale::En).unwrap();
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(reader.headers().unwrap().iter().collect::<Vec<_>>(), CSV_HEADER);
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), tx.postings.len());
+        for (row, posting) in rows.iter().zip(&tx.postings) {
+            assert_eq!(&row[3], "O'Brien \"Bob\", Ltd");
+            assert_eq!(&row[4], "Line one, and \"two\"");
+            assert_eq!(&row[5], posting.account);
+            assert_eq!(&row[6], posting.amount);
+        }
+    }
+}
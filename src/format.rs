@@ -0,0 +1,262 @@
+//! Reformats beancount text the way `bean-format` does: postings indented by
+//! two spaces, amounts right-aligned to one column, and runs of spaces in
+//! directives collapsed. The text is parsed first and each line is treated
+//! according to the directive it belongs to, found from the parser's spans;
+//! comments, blank lines, `include`/`option` lines and anything else outside
+//! a directive keep their content and lose only trailing whitespace.
+
+use crate::beancount;
+use crate::error::LedgerError;
+use anyhow::Result;
+use beancount_parser_lima::{BeancountParser, DirectiveVariant, Spanned};
+use std::ops::Range;
+
+/// Widest column amounts may be aligned to.
+pub const MAX_COLUMN: usize = 200;
+
+/// A line whose amount gets aligned: everything before the number, the
+/// number, and what follows it (currency, cost, price, comment).
+struct Aligned {
+    lead: String,
+    number: String,
+    rest: String,
+}
+
+enum Line {
+    Plain(String),
+    Aligned(Aligned),
+}
+
+/// How a line of the input is formatted.
+#[derive(Clone)]
+enum Role {
+    /// Outside any directive: left as it is.
+    Kept,
+    /// A directive's dated line.
+    Header,
+    /// A dated line whose amount is aligned, as in `balance`; the span is
+    /// the number's.
+    AlignedHeader(Range<usize>),
+    /// A posting, with the span of its amount if it has one.
+    Posting(Option<Range<usize>>),
+    /// Metadata or a comment under a directive; `nested` when it follows a
+    /// posting, so it belongs to that posting.
+    Body { nested: bool },
+}
+
+fn span<T>(spanned: &Spanned<T>) -> Range<usize> {
+    spanned.span().start..spanned.span().end
+}
+
+/// Collapses runs of whitespace to one space, except inside strings and in
+/// a trailing comment.
+fn collapse_spaces(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut chars = text.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => {
+                out.push(c);
+                out.extend(chars);
+                break;
+            }
+            c if c.is_whitespace() && !in_string => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                out.push(' ');
+                continue;
+            }
+            '\\' if in_string => {
+                out.push(c);
+                if let Some(escaped) = chars.next() {
+                    out.push(escaped);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Splits `line` around the number at `number`, a span relative to the
+/// line, or `None` if the span doesn't fall inside it.
+fn aligned(line: &str, number: Range<usize>, indent: &str) -> Option<Aligned> {
+    Some(Aligned {
+        lead: format!("{}{}", indent, collapse_spaces(line.get(..number.start)?)),
+        number: line.get(number.clone())?.trim().to_string(),
+        rest: collapse_spaces(line.get(number.end..)?),
+    })
+}
+
+/// Reformats `text`, which must parse. Amounts end at `column`, or by
+/// default at the narrowest column that fits every aligned line with two
+/// spaces to spare.
+pub fn format(text: &str, column: Option<usize>) -> Result<String> {
+    let sources = beancount::file_sources(text)?;
+    let parser = BeancountParser::new(&sources);
+    let result = parser.parse().map_err(|e| LedgerError::parse(format!("Parse error: {:?}", e)))?;
+
+    let lines: Vec<&str> = text.lines().collect();
+    let starts: Vec<usize> = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+    let line_of = |offset: usize| starts.partition_point(|s| *s <= offset).saturating_sub(1);
+    // A span relative to the start of the line it begins on.
+    let in_line = |span: Range<usize>| {
+        let line = line_of(span.start);
+        (line, span.start - starts[line]..span.end - starts[line])
+    };
+
+    let mut roles = vec![Role::Kept; lines.len()];
+    for directive in &result.directives {
+        let first = line_of(directive.date().span().start);
+        let last = line_of(directive.span().end.saturating_sub(1)).clamp(first, lines.len().saturating_sub(1));
+        roles[first] = Role::Header;
+        for role in &mut roles[first + 1..=last] {
+            *role = Role::Body { nested: false };
+        }
+        match directive.variant() {
+            DirectiveVariant::Transaction(t) => {
+                for p in t.postings() {
+                    let line = line_of(p.account().span().start);
+                    let amount = p.amount().map(span).map(in_line).filter(|(l, _)| *l == line).map(|(_, s)| s);
+                    roles[line] = Role::Posting(amount);
+                }
+                let mut seen_posting = false;
+                for role in &mut roles[first + 1..=last] {
+                    match role {
+                        Role::Posting(_) => seen_posting = true,
+                        Role::Body { nested } => *nested = seen_posting,
+                        _ => {}
+                    }
+                }
+            }
+            DirectiveVariant::Balance(b) => {
+                let (line, number) = in_line(span(b.atol().item().amount().number()));
+                if line == first {
+                    roles[first] = Role::AlignedHeader(number);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = Vec::with_capacity(lines.len());
+    for (line, role) in lines.iter().zip(roles) {
+        let trimmed = line.trim();
+        out.push(match role {
+            Role::Kept => Line::Plain(line.trim_end().to_string()),
+            Role::Header => Line::Plain(collapse_spaces(line)),
+            Role::AlignedHeader(number) => match aligned(line, number, "") {
+                Some(a) => Line::Aligned(a),
+                None => Line::Plain(collapse_spaces(line)),
+            },
+            Role::Posting(amount) => match amount.and_then(|number| aligned(line, number, "  ")) {
+                Some(a) => Line::Aligned(a),
+                None => Line::Plain(format!("  {}", collapse_spaces(trimmed))),
+            },
+            Role::Body { .. } if trimmed.is_empty() => Line::Plain(String::new()),
+            Role::Body { .. } if trimmed.starts_with(';') => Line::Plain(line.trim_end().to_string()),
+            Role::Body { nested } => Line::Plain(format!("{}{}", if nested { "    " } else { "  " }, collapse_spaces(trimmed))),
+        });
+    }
+
+    let column = column.unwrap_or_else(|| {
+        out.iter()
+            .filter_map(|l| match l {
+                Line::Aligned(a) => Some(a.lead.chars().count() + 2 + a.number.chars().count()),
+                Line::Plain(_) => None,
+            })
+            .max()
+            .unwrap_or(0)
+    });
+    let mut formatted = String::with_capacity(text.len());
+    for line in out {
+        match line {
+            Line::Plain(text) => formatted.push_str(&text),
+            Line::Aligned(a) => {
+                let used = a.lead.chars().count() + a.number.chars().count();
+                formatted.push_str(&a.lead);
+                formatted.push_str(&" ".repeat(column.saturating_sub(used).max(2)));
+                formatted.push_str(&a.number);
+                if !a.rest.is_empty() {
+                    formatted.push(' ');
+                    formatted.push_str(&a.rest);
+                }
+            }
+        }
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    const MESSY: &str = r#"option "title" "Test"
+include "other.bean"
+
+; Opening
+2024-01-01   open   Assets:Cash    USD
+  description:   "Wallet  cash"
+
+2024-03-01 *   "Cafe"    "Lunch"   ; noon
+  id:  "abc"
+  Expenses:Food     4.50 USD
+      receipt:  "r1"
+  ; split later
+  Assets:Cash   
+
+2024-03-02 balance   Assets:Cash   -4.50   USD
+"#;
+
+    #[test]
+    fn directives_are_tidied_and_everything_else_kept() {
+        let expected = [
+            "option \"title\" \"Test\"",
+            "include \"other.bean\"",
+            "",
+            "; Opening",
+            "2024-01-01 open Assets:Cash USD",
+            "  description: \"Wallet  cash\"",
+            "",
+            "2024-03-01 * \"Cafe\" \"Lunch\" ; noon",
+            "  id: \"abc\"",
+            &format!("  Expenses:Food{}4.50 USD", " ".repeat(18)),
+            "    receipt: \"r1\"",
+            "  ; split later",
+            "  Assets:Cash",
+            "",
+            "2024-03-02 balance Assets:Cash  -4.50 USD",
+        ];
+        assert_eq!(format(MESSY, None).unwrap(), expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let once = format(MESSY, None).unwrap();
+        assert_eq!(format(&once, None).unwrap(), once);
+    }
+
+    #[test]
+    fn amounts_end_at_the_given_column() {
+        let text = "2024-03-01 * \"Buy\"\n  ! Assets:Broker 10 AAPL   {150.00 USD}  @ 151 USD\n  Assets:Cash -1510 USD\n";
+        let formatted = format(text, Some(30)).unwrap();
+        let lines: Vec<&str> = formatted.lines().collect();
+        assert_eq!(lines[1], format!("  ! Assets:Broker{}10 AAPL {{150.00 USD}} @ 151 USD", " ".repeat(11)));
+        assert_eq!(lines[2], format!("  Assets:Cash{}-1510 USD", " ".repeat(12)));
+
+        // A line too long for the column keeps two spaces before its amount.
+        let narrow = format(text, Some(5)).unwrap();
+        assert!(narrow.contains("  ! Assets:Broker  10 AAPL"));
+    }
+
+    #[test]
+    fn text_that_does_not_parse_is_rejected() {
+        let err = format("2024-03-01 open\n", None).unwrap_err();
+        assert_eq!(crate::error::kind(&err), ErrorKind::Parse);
+    }
+}
@@ -0,0 +1,246 @@
+//! Filesystem helpers for ledger writes. Every write goes through a temp file,
+//! fsync and rename so a failure never leaves a half-written ledger file.
+//!
+//! In tests, and with the `fault-injection` feature (debug builds only),
+//! individual steps can be made to fail, to exercise the error and rollback
+//! paths.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[cfg(all(feature = "fault-injection", not(debug_assertions)))]
+compile_error!("the fault-injection feature must not be enabled in release builds");
+
+/// Replaces `path` with `contents` atomically.
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    write_atomic_bytes(path, contents.as_bytes())
+}
+
+/// Replaces `path` with raw `bytes` atomically, e.g. for uploaded documents.
+pub fn write_atomic_bytes(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    let result = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        faults::check(faults::Op::Write, path)?;
+        file.write_all(&bytes[..faults::write_len(bytes.len(), path)])?;
+        faults::check(faults::Op::Fsync, path)?;
+        file.sync_all()?;
+        faults::check(faults::Op::Rename, path)?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        fs::remove_file(&tmp).ok();
+    }
+    result
+}
+
+/// Appends `text` to `path`, creating it if needed.
+pub fn append(path: &Path, text: &str) -> io::Result<()> {
+    let mut contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    contents.push_str(text);
+    write_atomic(path, &contents)
+}
+
+/// Puts a file back the way it was before a multi-file write started:
+/// `None` means it didn't exist.
+pub fn restore(path: &Path, original: Option<&str>) -> io::Result<()> {
+    match original {
+        Some(contents) => write_atomic(path, contents),
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
+
+#[cfg(not(any(test, feature = "fault-injection")))]
+mod faults {
+    use std::io;
+    use std::path::Path;
+
+    pub enum Op {
+        Write,
+        Fsync,
+        Rename,
+    }
+
+    #[inline(always)]
+    pub fn check(_op: Op, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn write_len(len: usize, _path: &Path) -> usize {
+        len
+    }
+}
+
+#[cfg(any(test, feature = "fault-injection"))]
+pub mod faults {
+    use serde::{Deserialize, Serialize};
+    use std::io;
+    use std::path::Path;
+    use std::sync::Mutex;
+    use utoipa::ToSchema;
+
+    /// Which write steps should fail. Set through `PUT /test/faults`.
+    #[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+    #[serde(default)]
+    pub struct FaultPlan {
+        /// Fail the Nth write from now (1-based).
+        pub fail_nth_write: Option<usize>,
+        pub fail_fsync: bool,
+        pub fail_rename: bool,
+        /// Write only half of the bytes, without reporting an error.
+        pub short_write: bool,
+        /// Only affect writes to paths ending with this, e.g. `main.bean`;
+        /// every write when unset. Only matching writes count towards
+        /// `fail_nth_write`.
+        pub path: Option<String>,
+    }
+
+    pub enum Op {
+        Write,
+        Fsync,
+        Rename,
+    }
+
+    static PLAN: Mutex<(FaultPlan, usize)> = Mutex::new((
+        FaultPlan {
+            fail_nth_write: None,
+            fail_fsync: false,
+            fail_rename: false,
+            short_write: false,
+            path: None,
+        },
+        0,
+    ));
+
+    pub fn set(plan: FaultPlan) {
+        *PLAN.lock().unwrap() = (plan, 0);
+    }
+
+    #[cfg_attr(not(feature = "fault-injection"), allow(dead_code))]
+    pub fn get() -> FaultPlan {
+        PLAN.lock().unwrap().0.clone()
+    }
+
+    fn applies(plan: &FaultPlan, path: &Path) -> bool {
+        plan.path.as_deref().is_none_or(|p| path.ends_with(p))
+    }
+
+    pub fn check(op: Op, path: &Path) -> io::Result<()> {
+        let mut guard = PLAN.lock().unwrap();
+        let (plan, writes) = &mut *guard;
+        if !applies(plan, path) {
+            return Ok(());
+        }
+        let fail = match op {
+            Op::Write => {
+                *writes += 1;
+                plan.fail_nth_write == Some(*writes)
+            }
+            Op::Fsync => plan.fail_fsync,
+            Op::Rename => plan.fail_rename,
+        };
+        if fail {
+            Err(io::Error::other("injected fault"))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write_len(len: usize, path: &Path) -> usize {
+        let plan = &PLAN.lock().unwrap().0;
+        if plan.short_write && applies(plan, path) {
+            len / 2
+        } else {
+            len
+        }
+    }
+
+    /// The plan is global, so tests that set one take turns.
+    #[cfg(test)]
+    static TURN: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    /// A test's fault plan, in force until this is dropped.
+    #[cfg(test)]
+    pub struct Injected {
+        _turn: tokio::sync::MutexGuard<'static, ()>,
+    }
+
+    #[cfg(test)]
+    impl Drop for Injected {
+        fn drop(&mut self) {
+            set(FaultPlan::default());
+        }
+    }
+
+    /// Waits for any other test's plan to be dropped, then puts `plan` in
+    /// force.
+    #[cfg(test)]
+    pub async fn inject(plan: FaultPlan) -> Injected {
+        let turn = TURN.lock().await;
+        set(plan);
+        Injected { _turn: turn }
+    }
+
+    /// `inject`, for tests outside a runtime.
+    #[cfg(test)]
+    pub fn inject_blocking(plan: FaultPlan) -> Injected {
+        let turn = TURN.blocking_lock();
+        set(plan);
+        Injected { _turn: turn }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes a write over an existing file fail as `configure` says and
+    /// checks the file and its directory are as they were.
+    fn assert_failed_write_leaves_file_intact(configure: impl FnOnce(&mut faults::FaultPlan)) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("2024-03.bean");
+        let original = b"2024-03-01 open Assets:Cash\n";
+        fs::write(&path, original).unwrap();
+
+        let mut plan = faults::FaultPlan {
+            path: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        configure(&mut plan);
+        let injected = faults::inject_blocking(plan);
+        let result = write_atomic(&path, "2024-03-02 open Assets:Bank\n");
+        drop(injected);
+
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), original);
+        let names: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, ["2024-03.bean"]);
+    }
+
+    #[test]
+    fn failed_temp_write_leaves_file_intact() {
+        assert_failed_write_leaves_file_intact(|plan| plan.fail_nth_write = Some(1));
+    }
+
+    #[test]
+    fn failed_fsync_leaves_file_intact() {
+        assert_failed_write_leaves_file_intact(|plan| plan.fail_fsync = true);
+    }
+
+    #[test]
+    fn failed_rename_leaves_file_intact() {
+        assert_failed_write_leaves_file_intact(|plan| plan.fail_rename = true);
+    }
+}
@@ -0,0 +1,170 @@
+//! Optional auto-commit of the data directory: every successful write request
+//! becomes exactly one git commit covering all the files it touched.
+
+use crate::state::AppState;
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+pub const DEFAULT_MESSAGE_TEMPLATE: &str = "{operation} [{request_id}]\n\n{summary}";
+
+fn git(data_dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(data_dir)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Makes sure `data_dir` is the top of its own git work tree, initializing
+/// one there if not. A repository further up (such as this project's own
+/// checkout around the default `data/`) doesn't count: auto-commit would
+/// write ledger data into it.
+pub fn ensure_repo(data_dir: &Path) -> Result<()> {
+    let dir = std::fs::canonicalize(data_dir)?;
+    let top = git(data_dir, &["rev-parse", "--show-toplevel"])
+        .ok()
+        .and_then(|top| std::fs::canonicalize(top.trim()).ok());
+    if top.as_deref() != Some(dir.as_path()) {
+        git(data_dir, &["init", "-q"])?;
+    }
+    Ok(())
+}
+
+/// Counts dated directive lines added and removed in the staged diff.
+fn summarize(diff: &str) -> String {
+    let is_directive = |line: &str| {
+        line.len() >= 10 && line.as_bytes()[..10].iter().enumerate().all(|(i, b)| {
+            if i == 4 || i == 7 { *b == b'-' } else { b.is_ascii_digit() }
+        })
+    };
+    let mut added = 0;
+    let mut removed = 0;
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix('+') {
+            added += usize::from(is_directive(rest));
+        } else if let Some(rest) = line.strip_prefix('-') {
+            removed += usize::from(is_directive(rest));
+        }
+    }
+    format!("{} directive(s) added, {} removed", added, removed)
+}
+
+/// Stages every change under `data_dir` and commits it, doing nothing when
+/// the operation didn't change any file.
+pub fn commit_operation(data_dir: &Path, template: &str, operation: &str, request_id: &str) -> Result<()> {
+    git(data_dir, &["add", "-A", "--", "."])?;
+    let diff = git(data_dir, &["diff", "--cached", "-U0", "--", "."])?;
+    if diff.is_empty() {
+        return Ok(());
+    }
+    let message = template
+        .replace("{operation}", operation)
+        .replace("{request_id}", request_id)
+        .replace("{summary}", &summarize(&diff));
+    git(data_dir, &["commit", "-q", "-m", &message, "--", "."])?;
+    Ok(())
+}
+
+/// Middleware committing the data directory after each successful write
+/// request. Write requests run one at a time, each followed by its commit.
+/// The request ID comes from `x-request-id` when the client sends one.
+pub async fn autocommit(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let is_write = matches!(*request.method(), Method::POST | Method::PUT | Method::DELETE | Method::PATCH);
+    let operation = format!("{} {}", request.method(), request.uri().path());
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos());
+            format!("{:x}", nanos)
+        });
+
+    // Another write landing between this request's write and its commit
+    // would end up in this commit, leaving its own empty.
+    let _session = if is_write { Some(state.write_session.lock().await) } else { None };
+    let response = next.run(request).await;
+
+    if is_write && response.status().is_success() {
+        let state = state.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let _lock = state.lock_for_read();
+            commit_operation(&state.data_dir, &state.config.git_message_template, &operation, &request_id)
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::error!("Failed to auto-commit: {}", e),
+            Err(e) => tracing::error!("Auto-commit task failed: {}", e),
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn post_creating_a_month_is_one_commit_of_two_files() {
+        let dir = tempfile::tempdir().unwrap();
+        ensure_repo(dir.path()).unwrap();
+        git(dir.path(), &["config", "user.name", "Test"]).unwrap();
+        git(dir.path(), &["config", "user.email", "test@example.com"]).unwrap();
+
+        let mut config = Config::for_tests(dir.path());
+        config.git_autocommit = true;
+        let state = Arc::new(AppState::new(config).unwrap());
+        let app = axum::Router::new()
+            .route("/transactions", axum::routing::post(crate::api::add_transaction))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), autocommit))
+            .with_state(state);
+
+        let body = r#"{"date": "2024-03-01", "flag": "*", "narration": "Lunch", "tags": [], "postings": [
+            {"account": "Expenses:Food", "amount": "12.50", "currency": "USD"},
+            {"account": "Assets:Cash", "amount": "-12.50", "currency": "USD"}]}"#;
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/transactions")
+            .header("content-type", "application/json")
+            .header("x-request-id", "req-1")
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+
+        assert_eq!(git(dir.path(), &["rev-list", "--count", "HEAD"]).unwrap().trim(), "1");
+        let files = git(dir.path(), &["show", "--name-only", "--format=", "HEAD"]).unwrap();
+        let mut files: Vec<&str> = files.lines().filter(|l| !l.is_empty()).collect();
+        files.sort();
+        assert_eq!(files, ["2024-03.bean", "main.bean"]);
+        let message = git(dir.path(), &["log", "-1", "--format=%B"]).unwrap();
+        assert!(message.starts_with("POST /transactions [req-1]"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Translations for the human-facing strings the server generates, which
+//! so far are the CSV export's column headers; JSON reports carry keys, not
+//! labels. Amounts and dates are never localized.
+
+use axum::http::{header, HeaderMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+}
+
+impl Locale {
+    fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        match primary.as_str() {
+            "en" => Some(Self::En),
+            "de" => Some(Self::De),
+            _ => None,
+        }
+    }
+
+    /// Picks a locale from an explicit `lang=` parameter, then the
+    /// `Accept-Language` header (highest quality first), then English.
+    pub fn negotiate(lang: Option<&str>, headers: &HeaderMap) -> Self {
+        if let Some(locale) = lang.and_then(Self::from_tag) {
+            return locale;
+        }
+        let accept = headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let mut ranges: Vec<(f32, &str)> = accept
+            .split(',')
+            .map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next().unwrap_or_default();
+                let q = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (q, tag)
+            })
+            .collect();
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranges
+            .into_iter()
+            .find_map(|(_, tag)| Self::from_tag(tag))
+            .unwrap_or_default()
+    }
+
+    /// Translates a label key, falling back to the key itself when a
+    /// translation is missing.
+    pub fn text(self, key: &'static str) -> &'static str {
+        let table: &[(&str, &str)] = match self {
+            Self::En => return key,
+            Self::De => DE,
+        };
+        table
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map_or(key, |(_, v)| *v)
+    }
+}
+
+const DE: &[(&str, &str)] = &[
+    ("id", "ID"),
+    ("date", "Datum"),
+    ("flag", "Markierung"),
+    ("payee", "Empfänger"),
+    ("narration", "Beschreibung"),
+    ("account", "Konto"),
+    ("amount", "Betrag"),
+    ("currency", "Währung"),
+    ("tags", "Tags"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn accept_language(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn csv_headers_switch_with_the_locale() {
+        let english = crate::export::transactions_csv(&[], Locale::En).unwrap();
+        assert_eq!(english, "id,date,flag,payee,narration,account,amount,currency,tags\r\n");
+
+        let locale = Locale::negotiate(None, &accept_language("fr;q=0.9, de-DE;q=0.8, en;q=0.5"));
+        assert_eq!(locale, Locale::De);
+        let german = crate::export::transactions_csv(&[], locale).unwrap();
+        assert_eq!(german, "ID,Datum,Markierung,Empfänger,Beschreibung,Konto,Betrag,Währung,Tags\r\n");
+    }
+
+    #[test]
+    fn lang_parameter_wins_and_unknown_locales_fall_back_to_english() {
+        assert_eq!(Locale::negotiate(Some("en"), &accept_language("de")), Locale::En);
+        assert_eq!(Locale::negotiate(Some("xx"), &accept_language("fr")), Locale::En);
+        assert_eq!(Locale::De.text("no_such_label"), "no_such_label");
+    }
+}
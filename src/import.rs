@@ -0,0 +1,343 @@
+use crate::beancount::{self, parse_amount};
+use crate::config::Config;
+use crate::model::{Posting, Transaction};
+use crate::rules;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use utoipa::ToSchema;
+
+/// Describes how the columns of a bank CSV map onto transactions. Columns are
+/// header names when `has_header` is set, otherwise 0-based indices.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CsvMapping {
+    pub date_column: String,
+    pub amount_column: String,
+    pub payee_column: Option<String>,
+    pub narration_column: Option<String>,
+    /// chrono format string for the date column
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    /// Account every row belongs to, e.g. `Assets:Bank:Checking`
+    pub account: String,
+    /// Account that receives the other leg of each row
+    pub counter_account: String,
+    pub currency: String,
+    #[serde(default = "default_true")]
+    pub has_header: bool,
+    #[serde(default = "default_flag")]
+    pub flag: String,
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_flag() -> String {
+    "!".to_string()
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportRowError {
+    /// 1-based line number in the uploaded file
+    pub line: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportedTransaction {
+    pub transaction: Transaction,
+    /// Name of the categorization rule that matched, if any
+    pub rule: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportResult {
+    /// Rows accepted (and written, unless this was a dry run)
+    pub imported: usize,
+    /// Rows rejected; see `errors`
+    pub skipped: usize,
+    pub transactions: Vec<ImportedTransaction>,
+    pub errors: Vec<ImportRowError>,
+    /// False for a dry run, where nothing is written
+    pub written: bool,
+    /// Accounts opened (or, in a dry run, to be opened) because of `auto_open`
+    pub auto_opened: Vec<String>,
+}
+
+fn column_index(headers: Option<&csv::StringRecord>, column: &str) -> Result<usize> {
+    match headers {
+        Some(headers) => headers
+            .iter()
+            .position(|h| h.trim() == column)
+            .ok_or_else(|| anyhow::anyhow!("Column '{}' not found in header", column)),
+        None => column
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Column '{}' must be an index when there is no header", column)),
+    }
+}
+
+fn field<'r>(record: &'r csv::StringRecord, index: usize) -> Result<&'r str> {
+    record
+        .get(index)
+        .map(str::trim)
+        .ok_or_else(|| anyhow::anyhow!("Missing column {}", index))
+}
+
+struct Columns {
+    date: usize,
+    amount: usize,
+    payee: Option<usize>,
+    narration: Option<usize>,
+}
+
+fn row_to_transaction(record: &csv::StringRecord, columns: &Columns, mapping: &CsvMapping, separator: char) -> Result<Transaction> {
+    let date = chrono::NaiveDate::parse_from_str(field(record, columns.date)?, &mapping.date_format)?;
+    // Amounts go through the same decimal separator handling as POST /transactions.
+    let amount = parse_amount(&beancount::normalize_decimal(field(record, columns.amount)?, separator)?)?;
+    let optional = |col: Option<usize>| -> Result<Option<String>> {
+        Ok(match col {
+            Some(c) => Some(field(record, c)?.to_string()).filter(|s| !s.is_empty()),
+            None => None,
+        })
+    };
+    let posting = |account: &str, amount: rust_decimal::Decimal| Posting {
+        flag: None,
+        account: account.to_string(),
+        amount: amount.to_string(),
+        currency: mapping.currency.clone(),
+        cost: None,
+        price: None,
+    };
+
+    Ok(Transaction {
+        id: None,
+        date: date.format("%Y-%m-%d").to_string(),
+        flag: mapping.flag.clone(),
+        payee: optional(columns.payee)?,
+        narration: optional(columns.narration)?,
+        tags: vec![],
+        postings: vec![
+            posting(&mapping.account, amount),
+            posting(&mapping.counter_account, -amount),
+        ],
+        document: None,
+    })
+}
+
+/// Builds a two-posting transaction from each CSV row, reading amounts with
+/// the configured decimal `separator`. Rows that fail are reported
+/// individually instead of aborting the whole file.
+pub fn parse_csv(data: &[u8], mapping: &CsvMapping, separator: char) -> Result<(Vec<(u64, Transaction)>, Vec<ImportRowError>)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(mapping.has_header)
+        .flexible(true)
+        .from_reader(data);
+    let headers = if mapping.has_header {
+        Some(reader.headers()?.clone())
+    } else {
+        None
+    };
+    let resolve = |column: &Option<String>| {
+        column
+            .as_deref()
+            .map(|c| column_index(headers.as_ref(), c))
+            .transpose()
+    };
+    let columns = Columns {
+        date: column_index(headers.as_ref(), &mapping.date_column)?,
+        amount: column_index(headers.as_ref(), &mapping.amount_column)?,
+        payee: resolve(&mapping.payee_column)?,
+        narration: resolve(&mapping.narration_column)?,
+    };
+
+    let mut transactions = Vec::new();
+    let mut errors = Vec::new();
+
+    for record in reader.records() {
+        let (line, result) = match record {
+            Ok(r) => (r.position().map_or(0, |p| p.line()), row_to_transaction(&r, &columns, mapping, separator)),
+            Err(e) => (e.position().map_or(0, |p| p.line()), Err(e.into())),
+        };
+        match result {
+            Ok(tx) => transactions.push((line, tx)),
+            Err(e) => errors.push(ImportRowError {
+                line,
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    Ok((transactions, errors))
+}
+
+/// Categorizes parsed rows with the import rules and runs each through the
+/// same checks as `POST /transactions`, plus a balance check. Rows that fail
+/// are skipped and reported; unless `dry_run` is set, the rest are appended
+/// in date order, all of them or (if a write fails) none. The caller holds
+/// the write lock.
+pub fn import_rows(
+    data_dir: &Path,
+    config: &Config,
+    mapping: &CsvMapping,
+    rows: Vec<(u64, Transaction)>,
+    mut errors: Vec<ImportRowError>,
+    dry_run: bool,
+    auto_open: bool,
+) -> Result<ImportResult> {
+    let mut accounts = beancount::list_accounts(data_dir)?;
+    let rules = rules::compile_rules(&rules::load_rules(data_dir)?)?;
+    let mut accepted = Vec::new();
+    let mut opens = Vec::new();
+    for (line, mut tx) in rows {
+        let rule = rules::apply(&rules, &mut tx, &mapping.counter_account);
+        let checked = beancount::check_balanced(&tx).and_then(|_| beancount::prepare_transaction(&mut tx, config, &accounts, auto_open));
+        match checked {
+            Ok(row_opens) => {
+                // Later rows see the accounts earlier rows open.
+                accounts.extend(row_opens.iter().cloned());
+                opens.extend(row_opens);
+                accepted.push(ImportedTransaction { transaction: tx, rule });
+            }
+            Err(e) => errors.push(ImportRowError {
+                line,
+                message: e.to_string(),
+            }),
+        }
+    }
+    accepted.sort_by(|a, b| a.transaction.date.cmp(&b.transaction.date));
+
+    if !dry_run {
+        let txs = accepted.iter().map(|i| i.transaction.clone()).collect();
+        let ids = beancount::with_opened_accounts(data_dir, &opens, || {
+            beancount::add_transactions(data_dir, config.file_scheme, config.amount_column, txs)
+        })?;
+        for (imported, id) in accepted.iter_mut().zip(ids) {
+            imported.transaction.id = Some(id);
+        }
+    }
+
+    errors.sort_by_key(|e| e.line);
+    Ok(ImportResult {
+        imported: accepted.len(),
+        skipped: errors.len(),
+        transactions: accepted,
+        errors,
+        written: !dry_run,
+        auto_opened: opens.into_iter().map(|a| a.name).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn mapping() -> CsvMapping {
+        CsvMapping {
+            date_column: "Date".to_string(),
+            amount_column: "Amount".to_string(),
+            payee_column: Some("Payee".to_string()),
+            narration_column: Some("Memo".to_string()),
+            date_format: "%Y-%m-%d".to_string(),
+            account: "Assets:Bank".to_string(),
+            counter_account: "Expenses:Uncategorized".to_string(),
+            currency: "USD".to_string(),
+            has_header: true,
+            flag: "!".to_string(),
+        }
+    }
+
+    /// A data directory with the accounts the mapping posts to.
+    fn ledger() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("main.bean"),
+            "2024-01-01 open Assets:Bank\n2024-01-01 open Expenses:Uncategorized\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    const STATEMENT: &str = "Date,Amount,Payee,Memo\n\
+                             2024-04-02,-20.00,Hardware store,Nails\n\
+                             03/02/2024,-1.00,Bad date,\n\
+                             2024-03-03,abc,Bad amount,\n\
+                             2024-03-01,-4.50,Cafe,Coffee\n";
+
+    #[test]
+    fn bad_rows_are_reported_by_line() {
+        let (rows, errors) = parse_csv(STATEMENT.as_bytes(), &mapping(), '.').unwrap();
+        assert_eq!(rows.iter().map(|(line, _)| *line).collect::<Vec<_>>(), vec![2, 5]);
+        assert_eq!(errors.iter().map(|e| e.line).collect::<Vec<_>>(), vec![3, 4]);
+
+        let (_, cafe) = &rows[1];
+        assert_eq!(cafe.date, "2024-03-01");
+        assert_eq!(cafe.payee.as_deref(), Some("Cafe"));
+        assert_eq!(cafe.narration.as_deref(), Some("Coffee"));
+        let legs: Vec<_> = cafe.postings.iter().map(|p| (p.account.as_str(), p.amount.as_str())).collect();
+        assert_eq!(legs, vec![("Assets:Bank", "-4.50"), ("Expenses:Uncategorized", "4.50")]);
+    }
+
+    #[test]
+    fn columns_are_indices_without_a_header() {
+        let mapping = CsvMapping {
+            date_column: "0".to_string(),
+            amount_column: "1".to_string(),
+            payee_column: None,
+            narration_column: Some("2".to_string()),
+            has_header: false,
+            ..mapping()
+        };
+        let (rows, errors) = parse_csv(b"2024-03-01,\"-4,50\",Coffee\n", &mapping, ',').unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(rows[0].0, 1);
+        assert_eq!(rows[0].1.postings[0].amount, "-4.50");
+
+        let missing = CsvMapping { has_header: true, date_column: "When".to_string(), ..mapping };
+        assert!(parse_csv(b"Date,Amount\n", &missing, '.').is_err());
+    }
+
+    #[test]
+    fn dry_run_previews_without_writing() {
+        let dir = ledger();
+        let config = Config::for_tests(dir.path());
+        let (rows, errors) = parse_csv(STATEMENT.as_bytes(), &mapping(), '.').unwrap();
+        let preview = import_rows(dir.path(), &config, &mapping(), rows, errors, true, false).unwrap();
+        assert!(!preview.written);
+        assert_eq!((preview.imported, preview.skipped), (2, 2));
+        // Previewed in date order.
+        let dates: Vec<_> = preview.transactions.iter().map(|t| t.transaction.date.as_str()).collect();
+        assert_eq!(dates, vec!["2024-03-01", "2024-04-02"]);
+        assert!(beancount::list_transactions(dir.path()).unwrap().is_empty());
+
+        let (rows, errors) = parse_csv(STATEMENT.as_bytes(), &mapping(), '.').unwrap();
+        let result = import_rows(dir.path(), &config, &mapping(), rows, errors, false, false).unwrap();
+        assert!(result.written);
+        assert!(result.transactions.iter().all(|t| t.transaction.id.is_some()));
+        // Each row lands in its month's file.
+        assert!(fs::read_to_string(dir.path().join("2024-03.bean")).unwrap().contains("\"Cafe\""));
+        assert!(fs::read_to_string(dir.path().join("2024-04.bean")).unwrap().contains("\"Hardware store\""));
+    }
+
+    #[test]
+    fn unbalanced_rows_are_skipped_and_counted() {
+        let dir = ledger();
+        let config = Config::for_tests(dir.path());
+        let (mut rows, errors) = parse_csv(STATEMENT.as_bytes(), &mapping(), '.').unwrap();
+        // The bank row on line 5 loses a cent on its counter leg.
+        rows[1].1.postings[1].amount = "4.49".to_string();
+
+        let result = import_rows(dir.path(), &config, &mapping(), rows, errors, false, false).unwrap();
+        assert_eq!((result.imported, result.skipped), (1, 3));
+        assert_eq!(result.errors.iter().map(|e| e.line).collect::<Vec<_>>(), vec![3, 4, 5]);
+        assert!(result.errors[2].message.contains("does not balance"));
+        let written = beancount::list_transactions(dir.path()).unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].payee.as_deref(), Some("Hardware store"));
+    }
+}
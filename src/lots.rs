@@ -0,0 +1,185 @@
+//! Lot tracking for postings held at cost. Acquisitions add a lot; reductions
+//! take units from the oldest lots matching the reduction's cost spec
+//! first (FIFO), and report what they couldn't match instead of failing.
+
+use crate::beancount::parse_amount;
+use rust_decimal::Decimal;
+
+/// What a posting's `{...}` says about the lot. Every field is optional: on
+/// a reduction the spec only narrows which lots match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostSpec {
+    /// Cost of one unit, worked out from `{{total}}` when that form is used.
+    pub per_unit: Option<Decimal>,
+    pub currency: Option<String>,
+    pub date: Option<String>,
+}
+
+impl CostSpec {
+    /// Parses `{220.00 USD, 2023-02-01}`, `{{2200 USD}}` or `{}`. A total
+    /// cost is divided by `units`.
+    pub fn parse(text: &str, units: Decimal) -> CostSpec {
+        let text = text.trim();
+        let total = text.starts_with("{{");
+        let inner = text.trim_start_matches('{').trim_end_matches('}');
+        let mut spec = CostSpec::default();
+        for part in inner.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if chrono::NaiveDate::parse_from_str(part, "%Y-%m-%d").is_ok() {
+                spec.date = Some(part.to_string());
+                continue;
+            }
+            if part.starts_with('"') {
+                // A lot label; lots are matched by cost and date only.
+                continue;
+            }
+            let mut tokens = part.split_whitespace();
+            if let Some(number) = tokens.next().and_then(|n| parse_amount(n).ok()) {
+                spec.per_unit = if total && !units.is_zero() { Some(number / units.abs()) } else { Some(number) };
+                spec.currency = tokens.next().map(str::to_string);
+            } else if let Some(currency) = part.split_whitespace().next() {
+                spec.currency = Some(currency.to_string());
+            }
+        }
+        spec
+    }
+
+    fn matches(&self, lot: &Lot) -> bool {
+        self.per_unit.is_none_or(|c| c == lot.cost)
+            && self.currency.as_ref().is_none_or(|c| *c == lot.currency)
+            && self.date.as_ref().is_none_or(|d| *d == lot.date)
+    }
+}
+
+/// Units of one commodity bought at one cost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lot {
+    pub units: Decimal,
+    /// Cost of one unit.
+    pub cost: Decimal,
+    pub currency: String,
+    /// The spec's date, or the date of the acquiring transaction.
+    pub date: String,
+}
+
+/// The open lots of one commodity in one account, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    pub lots: Vec<Lot>,
+}
+
+impl Inventory {
+    /// Adds units bought on `date`. A lot with the same cost, currency and
+    /// date is topped up rather than duplicated.
+    pub fn add(&mut self, units: Decimal, spec: &CostSpec, date: &str) {
+        let lot = Lot {
+            units,
+            cost: spec.per_unit.unwrap_or_default(),
+            currency: spec.currency.clone().unwrap_or_default(),
+            date: spec.date.clone().unwrap_or_else(|| date.to_string()),
+        };
+        match self
+            .lots
+            .iter_mut()
+            .find(|l| l.cost == lot.cost && l.currency == lot.currency && l.date == lot.date)
+        {
+            Some(existing) => existing.units += lot.units,
+            None => {
+                let at = self.lots.partition_point(|l| l.date <= lot.date);
+                self.lots.insert(at, lot);
+            }
+        }
+    }
+
+    /// Takes `units` (a positive number) from the oldest lots matching
+    /// `spec`. Returns the lots taken from, with the units taken, and the
+    /// units that matched no lot.
+    pub fn reduce(&mut self, units: Decimal, spec: &CostSpec) -> (Vec<Lot>, Decimal) {
+        let mut remaining = units;
+        let mut taken = Vec::new();
+        for lot in self.lots.iter_mut().filter(|l| spec.matches(l)) {
+            if remaining.is_zero() {
+                break;
+            }
+            let take = remaining.min(lot.units);
+            lot.units -= take;
+            remaining -= take;
+            taken.push(Lot { units: take, ..lot.clone() });
+        }
+        self.lots.retain(|l| !l.units.is_zero());
+        (taken, remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(cost: i64, date: &str) -> CostSpec {
+        CostSpec { per_unit: Some(Decimal::from(cost)), currency: Some("USD".to_string()), date: Some(date.to_string()) }
+    }
+
+    fn units(inventory: &Inventory) -> Vec<(Decimal, &str)> {
+        inventory.lots.iter().map(|l| (l.units, l.date.as_str())).collect()
+    }
+
+    #[test]
+    fn cost_specs_parse_per_unit_and_total_forms() {
+        assert_eq!(CostSpec::parse("{220.00 USD, 2023-02-01}", Decimal::from(10)), CostSpec {
+            per_unit: Some(Decimal::new(22000, 2)),
+            currency: Some("USD".to_string()),
+            date: Some("2023-02-01".to_string()),
+        });
+        let total = CostSpec::parse("{{2200 USD}}", Decimal::from(-10));
+        assert_eq!(total.per_unit, Some(Decimal::from(220)));
+        assert_eq!(CostSpec::parse("{}", Decimal::ONE), CostSpec::default());
+    }
+
+    #[test]
+    fn partial_sales_take_from_the_oldest_lot_first() {
+        let mut inventory = Inventory::default();
+        inventory.add(Decimal::from(5), &usd(250, "2023-06-01"), "2023-06-01");
+        inventory.add(Decimal::from(10), &usd(220, "2023-02-01"), "2023-02-01");
+        assert_eq!(units(&inventory), vec![(Decimal::from(10), "2023-02-01"), (Decimal::from(5), "2023-06-01")]);
+
+        let (taken, unmatched) = inventory.reduce(Decimal::from(12), &CostSpec::default());
+        assert!(unmatched.is_zero());
+        assert_eq!(taken.iter().map(|l| (l.units, l.cost)).collect::<Vec<_>>(), vec![
+            (Decimal::from(10), Decimal::from(220)),
+            (Decimal::from(2), Decimal::from(250)),
+        ]);
+        assert_eq!(units(&inventory), vec![(Decimal::from(3), "2023-06-01")]);
+    }
+
+    #[test]
+    fn a_cost_spec_narrows_the_lots_reduced() {
+        let mut inventory = Inventory::default();
+        inventory.add(Decimal::from(10), &usd(220, "2023-02-01"), "2023-02-01");
+        inventory.add(Decimal::from(5), &usd(250, "2023-06-01"), "2023-06-01");
+        let (taken, _) = inventory.reduce(Decimal::from(4), &CostSpec { per_unit: Some(Decimal::from(250)), ..Default::default() });
+        assert_eq!(taken[0].date, "2023-06-01");
+        assert_eq!(units(&inventory), vec![(Decimal::from(10), "2023-02-01"), (Decimal::ONE, "2023-06-01")]);
+    }
+
+    #[test]
+    fn selling_more_than_is_held_reports_the_excess() {
+        let mut inventory = Inventory::default();
+        inventory.add(Decimal::from(3), &usd(220, "2023-02-01"), "2023-02-01");
+        let (taken, unmatched) = inventory.reduce(Decimal::from(5), &CostSpec::default());
+        assert_eq!(taken[0].units, Decimal::from(3));
+        assert_eq!(unmatched, Decimal::from(2));
+        assert!(inventory.lots.is_empty());
+
+        // Nothing left to match at all.
+        let (taken, unmatched) = inventory.reduce(Decimal::ONE, &CostSpec::default());
+        assert!(taken.is_empty());
+        assert_eq!(unmatched, Decimal::ONE);
+    }
+
+    #[test]
+    fn buying_the_same_lot_again_tops_it_up() {
+        let mut inventory = Inventory::default();
+        inventory.add(Decimal::from(2), &usd(220, "2023-02-01"), "2023-02-01");
+        inventory.add(Decimal::from(3), &usd(220, "2023-02-01"), "2023-02-01");
+        assert_eq!(units(&inventory), vec![(Decimal::from(5), "2023-02-01")]);
+    }
+}
@@ -1,16 +1,34 @@
 mod api;
+mod auth;
+mod autocomplete;
 mod beancount;
+mod config;
+mod error;
+mod export;
+mod format;
+mod fsio;
+mod git;
+mod i18n;
+mod import;
+mod lots;
 mod model;
+mod quotes;
+mod reports;
+mod rules;
 mod state;
+mod validation;
+mod watch;
 
 use axum::{
+    http::{header, HeaderValue, Method},
     response::Html,
     routing::{get, put},
     Router,
 };
-use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
@@ -19,20 +37,89 @@ use utoipa_swagger_ui::SwaggerUi;
 #[openapi(
     paths(
         api::list_transactions,
+        api::export_transactions,
+        api::find_duplicates,
+        api::pending_transactions,
+        api::pending_by_account,
+        api::export_beancount,
+        api::list_payees,
+        api::last_for_payee,
+        api::autocomplete,
+        api::suggest_account,
+        api::complete_accounts,
+        api::complete_payees,
+        api::list_tags,
+        api::list_currencies,
+        api::list_commodities,
+        api::add_commodity,
+        api::list_prices,
+        api::fetch_prices,
+        api::add_price,
+        api::latest_price,
         api::add_transaction,
         api::update_transaction,
         api::delete_transaction,
         api::clear_transaction,
         api::unclear_transaction,
+        api::get_transaction_raw,
+        api::put_transaction_raw,
+        api::confirm_transaction,
         api::list_accounts,
+        api::list_account_roots,
+        api::account_activity_ranges,
+        api::account_tree,
         api::add_account,
         api::update_account,
         api::delete_account,
         api::close_account,
-        api::verify_ledger
+        api::reopen_account,
+        api::rename_account,
+        api::get_account,
+        api::account_balance,
+        api::check_assertion,
+        api::account_register,
+        api::list_balances,
+        api::list_balance_assertions,
+        api::add_balance_assertion,
+        api::list_directives,
+        api::delete_directive,
+        api::add_pad,
+        api::list_notes,
+        api::add_note,
+        api::list_documents,
+        api::add_document,
+        api::get_document,
+        api::verify_ledger,
+        api::verify_lots,
+        api::metrics,
+        api::stats,
+        api::ledger_updates,
+        api::transaction_events,
+        api::activity_report,
+        api::income_report,
+        api::income_statement_periods,
+        api::trial_balance,
+        api::monthly_report,
+        api::net_worth,
+        api::cash_flow,
+        api::spending_report,
+        api::compare_report,
+        api::list_budgets,
+        api::set_budget,
+        api::budget_report,
+        api::holdings_report,
+        api::month_end_net_worth,
+        api::balance_sheet_report,
+        api::get_config,
+        api::format_ledger,
+        api::ledger_options,
+        api::set_option,
+        api::import_csv,
+        api::get_import_rules,
+        api::put_import_rules
     ),
     components(
-        schemas(model::Transaction, model::Posting, model::Account, model::VerifyResult, model::CloseAccountRequest)
+        schemas(error::ApiError, watch::LedgerChange, model::Transaction, model::Posting, model::TransactionWriteResult, model::Account, model::AccountDetail, model::AccountRoot, model::AccountActivityRange, model::VerifyResult, model::CloseAccountRequest, model::RenameAccountRequest, model::RenameResult, model::RenamedFile, model::LotIssue, model::ActivityDay, model::ConfigInfo, model::PayeeInfo, model::PayeeLast, model::TransactionTemplate, model::Suggestion, model::AccountSuggestion, model::AccountCompletion, model::Metrics, model::LedgerStats, model::PendingCount, model::TagInfo, model::CurrencyInfo, model::Commodity, model::FormatRequest, model::FormatResult, model::LedgerOption, model::LedgerOptions, model::Plugin, model::OptionValue, model::Price, model::PriceFetchResult, model::IncomeStatement, model::IncomeStatementLine, model::IncomeStatementPeriods, model::AccountBalance, model::BalanceAssertion, model::AssertionCheck, model::Directive, model::Pad, model::Note, model::NoteRequest, model::Document, model::BalanceSheet, model::BalanceSheetSection, model::RegisterRow, model::TrialBalance, model::MonthlyReport, model::CashFlow, model::SpendingGroup, model::CompareReport, model::CompareNode, model::Budget, model::BudgetRequest, model::BudgetReport, model::BudgetLine, model::Holdings, model::Holding, model::HoldingLot, model::DatedAmount, model::CashFlowLine, model::NetWorthSeries, model::NetWorthPoint, model::MonthlyTotal, model::TrialBalanceRow, model::AccountNode, model::DuplicateCluster, validation::ValidationProfile, validation::ReceiptThreshold, import::CsvMapping, import::ImportResult, import::ImportRowError, import::ImportedTransaction, rules::ImportRule)
     ),
     tags(
         (name = "beancounters", description = "Beancount API")
@@ -82,25 +169,169 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let app_state = state::AppState::new("data".to_string())?;
+    let config = config::Config::load()?;
+    error::set_overrides(config.error_status.clone());
+    let app_state = Arc::new(state::AppState::new(config.clone())?);
 
-    let app = Router::new()
+    if config.git_autocommit {
+        git::ensure_repo(&app_state.data_dir)?;
+    }
+
+    // Kept alive for as long as the server runs.
+    let _watcher = watch::spawn(&app_state.data_dir, app_state.changes.clone())?;
+
+    let mut app = Router::new()
         .merge(SwaggerUi::new("/docs").url("/docs/openapi.json", ApiDoc::openapi()))
         .route("/references", get(scalar_ui))
         .route("/transactions", get(api::list_transactions).post(api::add_transaction))
+        .route("/transactions/export", get(api::export_transactions))
+        .route("/transactions/duplicates", get(api::find_duplicates))
+        .route("/transactions/pending", get(api::pending_transactions))
+        .route("/transactions.csv", get(api::export_transactions))
+        .route("/export/beancount", get(api::export_beancount))
+        .route("/payees", get(api::list_payees))
+        .route("/payees/complete", get(api::complete_payees))
+        .route("/payees/{name}/last", get(api::last_for_payee))
+        .route("/autocomplete", get(api::autocomplete))
+        .route("/suggest/account", get(api::suggest_account))
+        .route("/tags", get(api::list_tags))
+        .route("/currencies", get(api::list_currencies))
+        .route("/commodities", get(api::list_commodities).post(api::add_commodity))
+        .route("/prices", get(api::list_prices).post(api::add_price))
+        .route("/prices/latest", get(api::latest_price))
+        .route("/prices/fetch", axum::routing::post(api::fetch_prices))
         .route("/transactions/{id}", put(api::update_transaction).delete(api::delete_transaction))
         .route("/transactions/{id}/clear", axum::routing::post(api::clear_transaction))
         .route("/transactions/{id}/unclear", axum::routing::post(api::unclear_transaction))
+        .route("/transactions/{id}/confirm", axum::routing::post(api::confirm_transaction))
+        .route("/transactions/{id}/raw", get(api::get_transaction_raw).put(api::put_transaction_raw))
         .route("/accounts", get(api::list_accounts).post(api::add_account))
-        .route("/accounts/{name}", put(api::update_account).delete(api::delete_account))
+        .route("/accounts/roots", get(api::list_account_roots))
+        .route("/accounts/activity-range", get(api::account_activity_ranges))
+        .route("/accounts/tree", get(api::account_tree))
+        .route("/accounts/complete", get(api::complete_accounts))
+        .route("/accounts/pending", get(api::pending_by_account))
+        .route("/accounts/{name}", get(api::get_account).put(api::update_account).delete(api::delete_account))
         .route("/accounts/{name}/close", axum::routing::post(api::close_account))
+        .route("/accounts/{name}/reopen", axum::routing::post(api::reopen_account))
+        .route("/accounts/{name}/rename", axum::routing::post(api::rename_account))
+        .route("/accounts/{name}/balance", get(api::account_balance))
+        .route("/accounts/{name}/assertion-check", get(api::check_assertion))
+        .route("/accounts/{name}/register", get(api::account_register))
+        .route("/accounts/{name}/notes", get(api::list_notes).post(api::add_note))
+        .route("/accounts/{name}/documents", get(api::list_documents).post(api::add_document))
+        .route("/documents/{*path}", get(api::get_document))
+        .route("/balances", get(api::list_balances))
+        .route("/balances/assertions", get(api::list_balance_assertions).post(api::add_balance_assertion))
+        .route("/directives", get(api::list_directives))
+        .route("/directives/{id}", axum::routing::delete(api::delete_directive))
+        .route("/pads", axum::routing::post(api::add_pad))
+        .route("/ws", get(api::ledger_updates))
+        .route("/events/stream", get(api::transaction_events))
         .route("/verify", get(api::verify_ledger))
-        .with_state(Arc::new(app_state));
+        .route("/verify/lots", get(api::verify_lots))
+        .route("/metrics", get(api::metrics))
+        .route("/stats", get(api::stats))
+        .route("/reports/activity", get(api::activity_report))
+        .route("/reports/income", get(api::income_report))
+        .route("/reports/income-statement", get(api::income_statement_periods))
+        .route("/reports/trial-balance", get(api::trial_balance))
+        .route("/reports/monthly", get(api::monthly_report))
+        .route("/reports/net-worth", get(api::net_worth))
+        .route("/reports/cash-flow", get(api::cash_flow))
+        .route("/reports/spending", get(api::spending_report))
+        .route("/reports/compare", get(api::compare_report))
+        .route("/budgets", get(api::list_budgets))
+        .route("/budgets/{account}", put(api::set_budget))
+        .route("/reports/budget", get(api::budget_report))
+        .route("/reports/holdings", get(api::holdings_report))
+        .route("/reports/networth", get(api::month_end_net_worth))
+        .route("/reports/balance-sheet", get(api::balance_sheet_report))
+        .route("/config", get(api::get_config))
+        .route("/format", axum::routing::post(api::format_ledger))
+        .route("/ledger/options", get(api::ledger_options))
+        .route("/ledger/options/{key}", put(api::set_option))
+        .route("/import/csv", axum::routing::post(api::import_csv))
+        .route("/import/rules", get(api::get_import_rules).put(api::put_import_rules))
+        .with_state(app_state.clone());
+
+    #[cfg(feature = "fault-injection")]
+    {
+        app = app.route("/test/faults", get(api::get_faults).put(api::set_faults));
+    }
+
+    // axum's own 2 MiB default would otherwise cap bodies below a larger
+    // configured limit.
+    app = app
+        .layer(axum::extract::DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(config.max_body_bytes));
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    if config.git_autocommit {
+        app = app.layer(axum::middleware::from_fn_with_state(app_state.clone(), git::autocommit));
+    }
+
+    // Outside everything but CORS, so a rejected request never reaches a
+    // handler or a commit.
+    if config.api_token.is_some() {
+        app = app.layer(axum::middleware::from_fn_with_state(app_state.clone(), auth::require_token));
+    }
+
+    // Outermost, so browser preflights are answered without a token.
+    if !config.cors_origins.is_empty() {
+        app = app.layer(cors_layer(&config.cors_origins));
+    }
+
+    let addr = config.addr();
     tracing::info!("listening on {}", addr);
     let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    // Spawned blocking writes can outlive their request; taking the lock
+    // once more makes sure none is left half-written.
+    let state = app_state.clone();
+    tokio::task::spawn_blocking(move || drop(state.ledger_lock.write()))
+        .await?;
+    tracing::info!("shutdown complete");
 
     Ok(())
 }
+
+/// CORS for browser frontends on `origins`, or any origin for `*`.
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        // Validated when the config was loaded.
+        AllowOrigin::list(origins.iter().filter_map(|o| o.parse::<HeaderValue>().ok()))
+    };
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install SIGINT handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("received SIGINT, shutting down"),
+        _ = terminate => tracing::info!("received SIGTERM, shutting down"),
+    }
+}
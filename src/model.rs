@@ -1,19 +1,25 @@
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use std::collections::BTreeMap;
+use utoipa::{IntoParams, ToSchema};
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct Transaction {
-    pub id: Option<String>, // file:line
+    pub id: Option<String>, // `id:` metadata, or file:offset for older entries
     pub date: String,
     pub flag: String,
     pub payee: Option<String>,
     pub narration: Option<String>,
     pub tags: Vec<String>,
     pub postings: Vec<Posting>,
+    /// Path of a receipt or other document, stored as `document:` metadata
+    /// plus a `document` directive on the expense account
+    pub document: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct Posting {
+    /// Optional posting flag written before the account, e.g. `!`
+    pub flag: Option<String>,
     pub account: String,
     pub amount: String,
     pub currency: String,
@@ -21,12 +27,152 @@ pub struct Posting {
     pub price: Option<String>,
 }
 
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct WriteQuery {
+    /// Open unknown posting accounts on the transaction date instead of rejecting them
+    #[serde(default)]
+    pub auto_open: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TransactionWriteResult {
+    /// Accounts opened because of `auto_open`
+    pub auto_opened: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct TransactionQuery {
+    /// Earliest date to include (YYYY-MM-DD)
+    pub from: Option<String>,
+    /// Latest date to include (YYYY-MM-DD)
+    pub to: Option<String>,
+    /// Only transactions with a posting to this account or its sub-accounts
+    pub account: Option<String>,
+    /// Only transactions flagged `!`, on the transaction or any posting
+    #[serde(default)]
+    pub flagged: bool,
+    /// `date` (default), `payee` or `amount` (largest posting)
+    pub sort: Option<String>,
+    /// `desc` (default) or `asc`
+    pub order: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct Page {
+    /// Maximum number of items to return
+    pub limit: Option<usize>,
+    /// Number of items to skip (default 0)
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ExportQuery {
+    /// Export format; only `csv` is supported
+    pub format: Option<String>,
+    /// Language for column headers (overrides `Accept-Language`)
+    pub lang: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub account: Option<String>,
+}
+
+impl ExportQuery {
+    pub fn filter(&self) -> TransactionQuery {
+        TransactionQuery {
+            from: self.from.clone(),
+            to: self.to.clone(),
+            account: self.account.clone(),
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct Account {
     pub name: String,
     pub open_date: String,
     pub currencies: Vec<String>,
     pub close_date: Option<String>,
+    /// Set on listings for problems such as a close without an open
+    #[serde(default)]
+    pub warning: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct AccountsQuery {
+    /// Also list accounts with a close date (default false)
+    #[serde(default)]
+    pub include_closed: bool,
+    /// `true` lists only accounts without a close date, `false` only closed
+    /// ones; overrides `include_closed`
+    pub open: Option<bool>,
+    /// Only accounts under this top-level type, e.g. `Assets`
+    pub root: Option<String>,
+    /// Only accounts declaring this currency in their open directive
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountRoot {
+    pub name: String,
+    /// Number of distinct accounts under this root
+    pub count: usize,
+}
+
+/// An account with what the ledger records against it. Balance, postings
+/// and dates include sub-accounts.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountDetail {
+    pub name: String,
+    pub open_date: String,
+    pub close_date: Option<String>,
+    pub currencies: Vec<String>,
+    /// Amount per currency
+    pub balances: BTreeMap<String, String>,
+    pub postings: usize,
+    /// Date of the earliest transaction posting to the account
+    pub first_transaction: Option<String>,
+    /// Date of the latest transaction posting to the account
+    pub last_transaction: Option<String>,
+    /// Full names of the accounts one level below
+    pub children: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountActivityRange {
+    pub account: String,
+    /// Date of the earliest posting
+    pub first: String,
+    /// Date of the latest posting
+    pub last: String,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct DeleteAccountQuery {
+    /// Delete even if transactions still post to the account
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RenameAccountRequest {
+    pub new_name: String,
+    /// Merge into the new name if it already exists
+    #[serde(default)]
+    pub merge: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RenamedFile {
+    /// File path relative to the data directory
+    pub file: String,
+    pub occurrences: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RenameResult {
+    pub total: usize,
+    pub files: Vec<RenamedFile>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -34,8 +180,889 @@ pub struct CloseAccountRequest {
     pub date: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct VerifyResult {
     pub errors: Vec<String>,
     pub warnings: Vec<String>,
 }
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct VerifyQuery {
+    /// Largest per-currency residual a transaction may leave (default 0.005)
+    pub tolerance: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LotIssue {
+    pub transaction_id: String,
+    pub date: String,
+    pub account: String,
+    pub currency: String,
+    pub cost: String,
+    pub unmatched_units: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, IntoParams)]
+pub struct ActivityQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// `count` or `amount` (default: both)
+    pub metric: Option<String>,
+    pub account_prefix: Option<String>,
+    /// `raw` (default) or `natural`, which flips income/liability/equity signs
+    pub sign: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ActivityDay {
+    pub date: String,
+    pub count: u64,
+    pub amounts: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConfigInfo {
+    pub narration_default: String,
+    /// `monthly`, `quarterly`, `yearly` or `single`
+    pub file_scheme: String,
+    pub validation_profile: crate::validation::ValidationProfile,
+    pub available_profiles: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ImportQuery {
+    /// Preview the parsed transactions without writing them
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Open unknown posting accounts on the row's date instead of rejecting the row
+    #[serde(default)]
+    pub auto_open: bool,
+    /// JSON CsvMapping, for raw `text/csv` uploads
+    pub mapping: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PayeeInfo {
+    pub name: String,
+    pub count: usize,
+    pub last_used: String,
+}
+
+/// A transaction without its identity (ID and date), for pre-filling a new entry.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TransactionTemplate {
+    pub flag: String,
+    pub payee: Option<String>,
+    pub narration: Option<String>,
+    pub tags: Vec<String>,
+    pub postings: Vec<Posting>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PayeeLast {
+    pub transaction: Transaction,
+    pub template: TransactionTemplate,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct PayeeQuery {
+    /// Case-insensitive prefix filter
+    pub q: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Suggestion {
+    pub value: String,
+    /// Recency-weighted usage; higher is better
+    pub score: f64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct AutocompleteQuery {
+    /// `account`, `payee`, `narration` or `currency`
+    pub field: String,
+    #[serde(default)]
+    pub q: String,
+    /// Maximum number of suggestions (default 10)
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct AccountSuggestionQuery {
+    /// Payee to look up (case-insensitive)
+    pub payee: String,
+    /// Maximum number of accounts (default 5)
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountSuggestion {
+    pub account: String,
+    /// Transactions with the payee that post to this account
+    pub count: usize,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct AccountCompleteQuery {
+    /// Leading part of the account name (case-insensitive)
+    #[serde(default)]
+    pub prefix: String,
+    /// Maximum number of names (default 20)
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct PayeeCompleteQuery {
+    /// Leading part of the payee (case-insensitive); empty lists every payee
+    #[serde(default)]
+    pub prefix: String,
+    /// `recent` (default) or `frequent`
+    pub order: Option<String>,
+    /// Maximum number of payees (default 20)
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountCompletion {
+    pub name: String,
+    /// Postings to this account
+    pub uses: usize,
+    /// False for accounts used in postings without an `open` directive
+    pub opened: bool,
+}
+
+/// Overview of the ledger for a dashboard.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LedgerStats {
+    pub transactions: usize,
+    pub postings: usize,
+    /// Transactions flagged `!`
+    pub uncleared: usize,
+    /// Accounts with an `open` directive, closed ones included
+    pub accounts: usize,
+    pub open_accounts: usize,
+    pub closed_accounts: usize,
+    /// Earliest transaction date, if there are any transactions
+    pub first_date: Option<String>,
+    pub last_date: Option<String>,
+    /// Distinct currencies used in postings
+    pub currencies: usize,
+    /// Ledger files parsed
+    pub files: usize,
+    /// Transaction count per year (YYYY)
+    pub transactions_per_year: BTreeMap<String, usize>,
+    /// Transaction count for each of the last 12 months (YYYY-MM), this one
+    /// included, zero for months without any
+    pub transactions_per_month: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Metrics {
+    /// Full ledger parses performed by `/verify`
+    pub verify_parses: u64,
+    /// `/verify` calls answered from the cache
+    pub verify_cache_hits: u64,
+}
+
+/// Pending (`!`) transactions posting to one account.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PendingCount {
+    pub account: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TagInfo {
+    /// Tag name without the leading `#`
+    pub name: String,
+    pub count: usize,
+    pub first_used: String,
+    pub last_used: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CurrencyInfo {
+    pub currency: String,
+    /// Earliest date of a posting or `commodity` directive using it
+    pub first_seen: String,
+    /// Number of postings in this currency
+    pub postings: usize,
+    /// Whether a `commodity` directive declares it
+    pub declared: bool,
+    /// Used in postings without a `commodity` declaration
+    pub undeclared: bool,
+}
+
+/// A `commodity` directive with the display metadata the API understands.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Commodity {
+    pub date: String,
+    pub currency: String,
+    /// `name:` metadata, e.g. `US Dollar`
+    pub name: Option<String>,
+    /// `precision:` metadata: decimal places amounts in this currency are shown with
+    pub precision: Option<u32>,
+    /// `price:` metadata: where `/prices/fetch` gets quotes, as
+    /// `<quote currency>:<source>/<ticker>`, e.g. `USD:yahoo/AAPL`
+    pub price: Option<String>,
+}
+
+/// An `option "key" "value"` line in `main.bean`.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct LedgerOption {
+    pub key: String,
+    pub value: String,
+}
+
+/// A `plugin "module" "config"` line in `main.bean`.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Plugin {
+    pub name: String,
+    pub config: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LedgerOptions {
+    /// In file order; keys such as `operating_currency` may repeat
+    pub options: Vec<LedgerOption>,
+    pub plugins: Vec<Plugin>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OptionValue {
+    pub value: String,
+}
+
+/// What `/prices/fetch` did for one commodity with a `price:` source.
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct PriceFetchResult {
+    pub currency: String,
+    /// The commodity's `price:` metadata
+    pub source: String,
+    /// `fetched`, `skipped` (already priced today) or `failed`
+    pub status: String,
+    /// The price written, when fetched
+    pub price: Option<Price>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct IncomeStatementQuery {
+    /// Earliest date to include (YYYY-MM-DD)
+    pub from: Option<String>,
+    /// Latest date to include (YYYY-MM-DD)
+    pub to: Option<String>,
+    /// Also break the totals down by account
+    pub detail: Option<bool>,
+    /// Convert amounts into this currency using `price` directives (default
+    /// the `operating_currency` option; empty to leave amounts as they are)
+    pub convert: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IncomeStatement {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Income per currency, as a positive number
+    pub income: BTreeMap<String, String>,
+    /// Expenses per currency
+    pub expenses: BTreeMap<String, String>,
+    /// Income less expenses per currency
+    pub net_income: BTreeMap<String, String>,
+    /// Per-account amounts in the same sign convention; empty unless `detail`
+    pub accounts: Vec<IncomeStatementLine>,
+    /// Currencies left as they are for lack of a price to `convert`
+    pub unconverted: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct IncomeStatementPeriodsQuery {
+    /// Earliest date to include (YYYY-MM-DD; default the first transaction)
+    pub from: Option<String>,
+    /// Latest date to include (YYYY-MM-DD; default the last transaction)
+    pub to: Option<String>,
+    /// `week`, `month`, `quarter` or `year`; without it the whole range is one period
+    pub interval: Option<String>,
+    /// Roll the per-account lines up to this many components
+    pub depth: Option<usize>,
+    /// Convert amounts into this currency using `price` directives (default
+    /// the `operating_currency` option; empty to leave amounts as they are)
+    pub convert: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IncomeStatementPeriods {
+    pub interval: Option<String>,
+    /// One statement per period, oldest first, each with its accounts
+    pub periods: Vec<IncomeStatement>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IncomeStatementLine {
+    pub account: String,
+    pub amounts: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct BalanceQuery {
+    /// Only count postings on or before this date (YYYY-MM-DD)
+    pub at: Option<String>,
+    /// Roll sub-accounts up into their ancestors with this many components
+    pub depth: Option<usize>,
+    /// Convert balances into this currency using `price` directives (default
+    /// the `operating_currency` option; empty to leave balances as they are)
+    pub convert: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountBalance {
+    pub account: String,
+    /// Amount per currency
+    pub balances: BTreeMap<String, String>,
+    /// Currencies left as they are for lack of a price to `convert`
+    pub unconverted: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct MonthlyQuery {
+    /// Account whose subtree is totalled, e.g. `Expenses`
+    pub account: String,
+    /// Earliest date to include (YYYY-MM-DD; default the first posting)
+    pub from: Option<String>,
+    /// Latest date to include (YYYY-MM-DD; default the last posting)
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MonthlyTotal {
+    /// YYYY-MM
+    pub month: String,
+    /// Amount per currency; every currency in the report appears, zero included
+    pub amounts: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MonthlyReport {
+    pub account: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Every month in the range, oldest first
+    pub months: Vec<MonthlyTotal>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct NetWorthQuery {
+    /// First date of the series (YYYY-MM-DD; default the first transaction)
+    pub from: Option<String>,
+    /// Last date of the series (YYYY-MM-DD; default the last transaction)
+    pub to: Option<String>,
+    /// `week`, `month` (default), `quarter` or `year`
+    pub interval: Option<String>,
+    /// Currency to convert into using `price` directives (default the
+    /// `operating_currency` option; empty to leave amounts as they are)
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NetWorthPoint {
+    /// Last day of the interval; balances include it
+    pub date: String,
+    pub assets: BTreeMap<String, String>,
+    /// What is owed, as a positive number
+    pub liabilities: BTreeMap<String, String>,
+    /// Assets less liabilities
+    pub net: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NetWorthSeries {
+    pub currency: Option<String>,
+    pub points: Vec<NetWorthPoint>,
+    /// Currencies that couldn't be converted, with the first date affected
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct MonthEndNetWorthQuery {
+    /// First month of the series (YYYY-MM-DD; default the first transaction)
+    pub from: Option<String>,
+    /// Last month of the series (YYYY-MM-DD; default the last transaction)
+    pub to: Option<String>,
+    /// Convert into this currency using `price` directives (default the
+    /// `operating_currency` option; empty to leave amounts as they are)
+    pub convert: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DatedAmount {
+    pub date: String,
+    /// Amount per currency
+    pub amount: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SpendingQuery {
+    /// `account` (default), `payee` or `tag`
+    pub group_by: Option<String>,
+    /// Earliest date to include (YYYY-MM-DD)
+    pub from: Option<String>,
+    /// Latest date to include (YYYY-MM-DD)
+    pub to: Option<String>,
+    /// Only postings to this account or its sub-accounts (default `Expenses`)
+    pub account: Option<String>,
+    /// With `group_by=account`, roll accounts up to this many components
+    pub depth: Option<usize>,
+}
+
+impl SpendingQuery {
+    pub fn filter(&self) -> TransactionQuery {
+        TransactionQuery {
+            from: self.from.clone(),
+            to: self.to.clone(),
+            account: Some(self.account.clone().unwrap_or_else(|| "Expenses".to_string())),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SpendingGroup {
+    /// The account, payee or tag; `(none)` for transactions without one
+    pub group: String,
+    /// Amount per currency
+    pub total: BTreeMap<String, String>,
+    /// Transactions in the group
+    pub count: usize,
+    /// Total divided by count, per currency
+    pub average: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct CashFlowQuery {
+    /// Earliest date to include (YYYY-MM-DD)
+    pub from: Option<String>,
+    /// Latest date to include (YYYY-MM-DD)
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CashFlowLine {
+    pub account: String,
+    /// Amount per currency, as a positive number
+    pub amounts: BTreeMap<String, String>,
+}
+
+/// Money moving in and out of Assets and Liabilities over a period.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CashFlow {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Money in, by the account it came from
+    pub inflows: Vec<CashFlowLine>,
+    /// Money out, by the account it went to
+    pub outflows: Vec<CashFlowLine>,
+    pub total_inflow: BTreeMap<String, String>,
+    pub total_outflow: BTreeMap<String, String>,
+    /// Inflow less outflow per currency
+    pub net: BTreeMap<String, String>,
+    /// Net change per Assets/Liabilities account from transactions that
+    /// only move money between them; signed, and summing to zero
+    pub transfers: Vec<CashFlowLine>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct TrialBalanceQuery {
+    /// Only count postings on or before this date (YYYY-MM-DD)
+    pub at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TrialBalanceRow {
+    pub account: String,
+    /// Positive balances per currency
+    pub debit: BTreeMap<String, String>,
+    /// Negative balances per currency, as positive numbers
+    pub credit: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TrialBalance {
+    pub at: Option<String>,
+    pub rows: Vec<TrialBalanceRow>,
+    pub total_debit: BTreeMap<String, String>,
+    pub total_credit: BTreeMap<String, String>,
+    /// Debits less credits per currency where they don't cancel out
+    pub residual: BTreeMap<String, String>,
+    /// Whether every currency sums to zero
+    pub balanced: bool,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct BalanceSheetQuery {
+    /// Only count postings on or before this date (YYYY-MM-DD)
+    pub as_of: Option<String>,
+    /// Largest per-currency difference still treated as balanced (default 0.005)
+    pub tolerance: Option<String>,
+    /// Convert balances into this currency using `price` directives (default
+    /// the `operating_currency` option; empty to leave balances as they are)
+    pub convert: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BalanceSheetSection {
+    /// Section total per currency
+    pub total: BTreeMap<String, String>,
+    /// Every account and its ancestors, in hierarchy order
+    pub accounts: Vec<AccountBalance>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BalanceSheet {
+    pub as_of: Option<String>,
+    pub assets: BalanceSheetSection,
+    pub liabilities: BalanceSheetSection,
+    pub equity: BalanceSheetSection,
+    /// Income less expenses not yet closed into equity
+    pub retained_earnings: BTreeMap<String, String>,
+    /// Whether assets equal liabilities plus equity within the tolerance
+    pub balanced: bool,
+    /// Per-currency difference beyond the tolerance, if any
+    pub imbalance: BTreeMap<String, String>,
+    /// Currencies left as they are for lack of a price to `convert`
+    pub unconverted: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct RegisterQuery {
+    /// Earliest date to include (YYYY-MM-DD)
+    pub from: Option<String>,
+    /// Latest date to include (YYYY-MM-DD)
+    pub to: Option<String>,
+    /// Only postings in this currency
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RegisterRow {
+    pub transaction_id: String,
+    pub date: String,
+    pub payee: Option<String>,
+    pub narration: Option<String>,
+    /// The posting's account (the requested account or a sub-account)
+    pub account: String,
+    pub amount: String,
+    pub currency: String,
+    /// Running balance per currency after this posting
+    pub balance: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Price {
+    pub date: String,
+    /// The commodity being priced, e.g. `VTI`
+    pub currency: String,
+    /// Price of one unit in `quote_currency`
+    pub amount: String,
+    pub quote_currency: String,
+}
+
+/// A `balance` directive: `account` must hold exactly `amount` of
+/// `currency` at the start of `date`.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct BalanceAssertion {
+    pub date: String,
+    pub account: String,
+    pub amount: String,
+    pub currency: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct AssertionCheckQuery {
+    /// Date the assertion applies to (YYYY-MM-DD); postings on it aren't counted
+    pub date: String,
+    pub amount: String,
+    pub currency: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AssertionCheck {
+    pub account: String,
+    pub date: String,
+    pub currency: String,
+    /// The asserted amount
+    pub expected: String,
+    /// The ledger balance, including sub-accounts
+    pub actual: String,
+    /// `actual` minus `expected`
+    pub difference: String,
+    /// Whether the difference is within the amount's precision
+    pub passes: bool,
+}
+
+/// A `pad` directive: fills `account` from `source_account` up to the next
+/// balance assertion.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Pad {
+    /// `file:offset` of the directive; ignored on create
+    #[serde(default)]
+    pub id: Option<String>,
+    pub date: String,
+    pub account: String,
+    pub source_account: String,
+}
+
+/// A `note` directive attaching a comment to an account.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Note {
+    /// `file:offset` of the directive
+    pub id: Option<String>,
+    pub date: String,
+    pub account: String,
+    pub comment: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NoteRequest {
+    pub date: String,
+    pub comment: String,
+}
+
+/// A `document` directive linking a file to an account.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Document {
+    /// `file:offset` of the directive
+    pub id: Option<String>,
+    pub date: String,
+    pub account: String,
+    /// File path as written, relative to the data directory for uploads
+    pub path: String,
+}
+
+/// The directive kinds listed by `/directives`.
+pub const DIRECTIVE_KINDS: &[&str] = &["pad", "note", "document"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Directive {
+    Pad(Pad),
+    Note(Note),
+    Document(Document),
+}
+
+impl Directive {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Pad(_) => "pad",
+            Self::Note(_) => "note",
+            Self::Document(_) => "document",
+        }
+    }
+
+    pub fn date(&self) -> &str {
+        match self {
+            Self::Pad(pad) => &pad.date,
+            Self::Note(note) => &note.date,
+            Self::Document(document) => &document.date,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct DirectiveQuery {
+    /// Only directives of this type, e.g. `pad`
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct PriceQuery {
+    /// Only prices of this commodity, e.g. `VTI`
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct LatestPriceQuery {
+    /// The commodity being priced, e.g. `VTI`
+    pub base: String,
+    /// The currency the price is in, e.g. `USD`
+    pub quote: String,
+    /// Latest date to consider (YYYY-MM-DD, default today)
+    pub at: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct AccountTreeQuery {
+    /// Only the subtree under this account, e.g. `Expenses`
+    pub root: Option<String>,
+    /// Only accounts with at most this many name components
+    pub depth: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountNode {
+    /// Last component of the account name
+    pub name: String,
+    pub full_name: String,
+    #[schema(no_recursion)]
+    pub children: Vec<AccountNode>,
+    /// Balance per currency including all descendants
+    pub balance: BTreeMap<String, String>,
+    /// Implied by a sub-account but never opened itself
+    pub synthetic: bool,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct HoldingsQuery {
+    /// Holdings as of this date (YYYY-MM-DD, default today)
+    pub at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HoldingLot {
+    pub units: String,
+    /// Cost of one unit
+    pub cost: String,
+    pub date: String,
+}
+
+/// Units of one commodity held at cost in one account.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Holding {
+    pub account: String,
+    pub currency: String,
+    pub units: String,
+    /// Currency the cost and value are in
+    pub cost_currency: String,
+    pub cost_basis: String,
+    /// Units at the latest price on or before `at`; null without a price
+    pub market_value: Option<String>,
+    /// Market value less cost basis
+    pub unrealized_gain: Option<String>,
+    /// Open lots, oldest first
+    pub lots: Vec<HoldingLot>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Holdings {
+    pub at: String,
+    pub holdings: Vec<Holding>,
+    /// Reductions that sold more units than were held, and the like
+    pub warnings: Vec<String>,
+}
+
+/// A monthly budget for an expense account, from a
+/// `custom "budget" <account> "monthly" <amount> <currency>` directive. It
+/// covers the account's subaccounts too, from `date` on.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Budget {
+    pub account: String,
+    pub amount: String,
+    pub currency: String,
+    /// First day the budget applies
+    pub date: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BudgetRequest {
+    pub amount: String,
+    pub currency: String,
+    /// First day the budget applies (default the first of this month)
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct BudgetQuery {
+    /// Month to report on, YYYY-MM
+    pub period: String,
+}
+
+/// Budget against actual spending for one account and currency.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BudgetLine {
+    pub account: String,
+    pub currency: String,
+    /// Null for unbudgeted spending
+    pub budgeted: Option<String>,
+    pub actual: String,
+    /// Budgeted less actual; negative when over budget
+    pub remaining: Option<String>,
+    /// Actual as a percentage of budgeted; null where budgeted is zero
+    pub percent_used: Option<String>,
+    /// Spending in an account (or currency) no budget covers
+    pub unbudgeted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BudgetReport {
+    pub period: String,
+    pub from: String,
+    pub to: String,
+    /// Budgeted accounts first, then unbudgeted spending, each by account
+    pub lines: Vec<BudgetLine>,
+}
+
+/// Text to format: either `text` or a `file` in the data directory.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FormatRequest {
+    pub text: Option<String>,
+    /// Path of a `.bean` file relative to the data directory; it is read, not rewritten
+    pub file: Option<String>,
+    /// Column amounts end at, at most 200 (default: the narrowest that fits every line)
+    pub column: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FormatResult {
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct CompareQuery {
+    /// `YYYY`, `YYYY-MM` or `YYYY-Qn`
+    pub period: String,
+    /// Period to compare with, in the same forms
+    pub against: String,
+    /// Account subtree to compare (default `Expenses`)
+    pub root: Option<String>,
+}
+
+/// One account's totals in two periods, with its sub-accounts nested.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CompareNode {
+    /// Last component of the account name
+    pub name: String,
+    pub full_name: String,
+    #[schema(no_recursion)]
+    pub children: Vec<CompareNode>,
+    /// Total per currency in `period`, including sub-accounts
+    pub period: BTreeMap<String, String>,
+    /// Total per currency in `against`; zero where only `period` has one
+    pub against: BTreeMap<String, String>,
+    /// `period` less `against` per currency
+    pub delta: BTreeMap<String, String>,
+    /// Delta as a percentage of `against`; null where `against` is zero
+    pub percent: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CompareReport {
+    pub period: String,
+    pub against: String,
+    pub root: CompareNode,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct DuplicateQuery {
+    /// `exact` (default) or `fuzzy`, which ignores case and punctuation in payees
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DuplicateCluster {
+    pub date: String,
+    /// Posting amounts shared by every member, e.g. `-4.5 USD`
+    pub amounts: Vec<String>,
+    /// Payee of the first member
+    pub payee: Option<String>,
+    pub transaction_ids: Vec<String>,
+}
@@ -0,0 +1,235 @@
+use crate::beancount;
+use crate::error::LedgerError;
+use crate::model::{Price, PriceFetchResult};
+use crate::state::AppState;
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A commodity's `price:` metadata, `<quote currency>:<source>/<ticker>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriceSource {
+    pub quote_currency: String,
+    /// Where to ask, e.g. `yahoo` or `coingecko`.
+    pub source: String,
+    pub ticker: String,
+}
+
+/// Tickers go into request URLs as they are, so only the characters real
+/// ones use are accepted: e.g. `VWRL.L`, `^GSPC`, `EURUSD=X`, `bitcoin`.
+fn is_valid_ticker(ticker: &str) -> bool {
+    !ticker.is_empty() && ticker.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '^' | '='))
+}
+
+pub fn parse_source(value: &str) -> Result<PriceSource> {
+    let invalid = || LedgerError::invalid(format!("Invalid price source {:?}: expected <currency>:<source>/<ticker>", value));
+    let (quote_currency, rest) = value.trim().split_once(':').ok_or_else(invalid)?;
+    let (source, ticker) = rest.split_once('/').ok_or_else(invalid)?;
+    if !beancount::is_valid_currency(quote_currency) || source.is_empty() || !is_valid_ticker(ticker) {
+        return Err(invalid().into());
+    }
+    Ok(PriceSource {
+        quote_currency: quote_currency.to_string(),
+        source: source.to_string(),
+        ticker: ticker.to_string(),
+    })
+}
+
+/// Somewhere to get the current price of a ticker. Calls are blocking and
+/// made from `spawn_blocking`, like the rest of the ledger work.
+pub trait QuoteSource: Send + Sync {
+    /// The latest price of `source.ticker` in `source.quote_currency`.
+    fn latest(&self, source: &PriceSource) -> Result<Decimal>;
+}
+
+/// Fetches quotes from the public Yahoo Finance and CoinGecko APIs.
+pub struct HttpQuoteSource;
+
+impl QuoteSource for HttpQuoteSource {
+    fn latest(&self, source: &PriceSource) -> Result<Decimal> {
+        // The blocking client owns a runtime of its own, so it's built here,
+        // on the blocking thread, rather than kept in the async server state.
+        let client = reqwest::blocking::Client::builder()
+            .timeout(TIMEOUT)
+            .user_agent(concat!("beancounters/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        let price = match source.source.as_str() {
+            "yahoo" => {
+                let url = format!("https://query1.finance.yahoo.com/v8/finance/chart/{}", source.ticker);
+                let body: serde_json::Value = client
+                    .get(url)
+                    .query(&[("range", "1d"), ("interval", "1d")])
+                    .send()?
+                    .error_for_status()?
+                    .json()?;
+                let meta = &body["chart"]["result"][0]["meta"];
+                if let Some(currency) = meta["currency"].as_str() {
+                    if !currency.eq_ignore_ascii_case(&source.quote_currency) {
+                        anyhow::bail!("{} is quoted in {}, not {}", source.ticker, currency, source.quote_currency);
+                    }
+                }
+                json_decimal(&meta["regularMarketPrice"])
+            }
+            "coingecko" => {
+                let vs = source.quote_currency.to_lowercase();
+                let body: serde_json::Value = client
+                    .get("https://api.coingecko.com/api/v3/simple/price")
+                    .query(&[("ids", source.ticker.as_str()), ("vs_currencies", vs.as_str())])
+                    .send()?
+                    .error_for_status()?
+                    .json()?;
+                json_decimal(&body[&source.ticker][&vs])
+            }
+            other => anyhow::bail!("Unknown price source '{}'", other),
+        };
+        price.with_context(|| format!("No price for {} in the {} response", source.ticker, source.source))
+    }
+}
+
+fn json_decimal(value: &serde_json::Value) -> Option<Decimal> {
+    let number = value.as_number()?.to_string();
+    Decimal::from_str(&number).or_else(|_| Decimal::from_scientific(&number)).ok()
+}
+
+/// Fetches today's price for every commodity with a `price:` source that
+/// doesn't have one yet, and appends it to `prices.bean`. One commodity
+/// failing doesn't stop the others; each gets its own result.
+pub fn fetch_prices(state: &AppState) -> Result<Vec<PriceFetchResult>> {
+    let today = chrono::Local::now().date_naive().to_string();
+    let priced_today = |prices: Vec<Price>| -> HashSet<(String, String)> {
+        prices
+            .into_iter()
+            .filter(|p| p.date == today)
+            .map(|p| (p.currency, p.quote_currency))
+            .collect()
+    };
+
+    let (commodities, priced) = {
+        let _lock = state.lock_for_read();
+        let commodities = beancount::list_commodities(&state.data_dir)?;
+        (commodities, priced_today(beancount::list_prices(&state.data_dir)?))
+    };
+
+    // Quotes are fetched without holding the ledger lock, so a slow source
+    // doesn't hold up other requests.
+    let mut results = Vec::new();
+    let mut fetched = Vec::new();
+    for commodity in commodities {
+        let Some(source_text) = commodity.price else { continue };
+        let mut result = PriceFetchResult {
+            currency: commodity.currency.clone(),
+            source: source_text.clone(),
+            status: "failed".to_string(),
+            price: None,
+            error: None,
+        };
+        match parse_source(&source_text) {
+            Ok(source) if priced.contains(&(commodity.currency.clone(), source.quote_currency.clone())) => {
+                result.status = "skipped".to_string();
+            }
+            Ok(source) => match state.quote_source.latest(&source) {
+                Ok(amount) => {
+                    fetched.push((results.len(), Price {
+                        date: today.clone(),
+                        currency: commodity.currency,
+                        amount: amount.normalize().to_string(),
+                        quote_currency: source.quote_currency,
+                    }));
+                }
+                Err(e) => result.error = Some(format!("{:#}", e)),
+            },
+            Err(e) => result.error = Some(e.to_string()),
+        }
+        results.push(result);
+    }
+
+    if !fetched.is_empty() {
+        let _lock = state.lock_for_write();
+        // Another request may have added today's price while we were fetching.
+        let priced = priced_today(beancount::list_prices(&state.data_dir)?);
+        for (i, price) in fetched {
+            let result = &mut results[i];
+            if priced.contains(&(price.currency.clone(), price.quote_currency.clone())) {
+                result.status = "skipped".to_string();
+                continue;
+            }
+            match beancount::add_price(&state.data_dir, &price) {
+                Ok(()) => {
+                    result.status = "fetched".to_string();
+                    result.price = Some(price);
+                }
+                Err(e) => result.error = Some(e.to_string()),
+            }
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// Answers from a fixed table instead of the network.
+    struct FakeQuotes;
+
+    impl QuoteSource for FakeQuotes {
+        fn latest(&self, source: &PriceSource) -> Result<Decimal> {
+            match source.ticker.as_str() {
+                "AAPL" => Ok(Decimal::new(18950, 2)),
+                "VTI" => Ok(Decimal::new(250, 0)),
+                other => anyhow::bail!("Unknown ticker {}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn fetch_reports_each_commodity_separately() {
+        let dir = tempfile::tempdir().unwrap();
+        let today = chrono::Local::now().date_naive();
+        std::fs::write(
+            dir.path().join("main.bean"),
+            format!(
+                "2024-01-01 commodity AAPL\n  price: \"USD:yahoo/AAPL\"\n\
+                 2024-01-01 commodity BAD\n  price: \"USD:yahoo/NOPE\"\n\
+                 2024-01-01 commodity JUNK\n  price: \"not a source\"\n\
+                 2024-01-01 commodity VTI\n  price: \"USD:yahoo/VTI\"\n\
+                 2024-01-01 commodity USD\n\n\
+                 {} price VTI 240 USD\n",
+                today
+            ),
+        )
+        .unwrap();
+        let mut state = AppState::new(Config::for_tests(dir.path())).unwrap();
+        state.quote_source = Box::new(FakeQuotes);
+
+        let results = fetch_prices(&state).unwrap();
+        let status: Vec<(&str, &str)> = results.iter().map(|r| (r.currency.as_str(), r.status.as_str())).collect();
+        assert_eq!(status, vec![("AAPL", "fetched"), ("BAD", "failed"), ("JUNK", "failed"), ("VTI", "skipped")]);
+        assert!(results[1].error.as_deref().unwrap().contains("NOPE"));
+        assert!(results[2].error.is_some());
+
+        let prices = beancount::list_prices(dir.path()).unwrap();
+        let aapl: Vec<&Price> = prices.iter().filter(|p| p.currency == "AAPL").collect();
+        assert_eq!(aapl.len(), 1);
+        assert_eq!((aapl[0].date.as_str(), aapl[0].amount.as_str()), (today.to_string().as_str(), "189.5"));
+
+        // Fetching again the same day skips what's already priced.
+        let again = fetch_prices(&state).unwrap();
+        assert_eq!(again[0].status, "skipped");
+        assert_eq!(beancount::list_prices(dir.path()).unwrap().len(), prices.len());
+    }
+
+    #[test]
+    fn sources_with_unsafe_tickers_are_rejected() {
+        let source = parse_source("USD:yahoo/VWRL.L").unwrap();
+        assert_eq!((source.quote_currency.as_str(), source.source.as_str(), source.ticker.as_str()), ("USD", "yahoo", "VWRL.L"));
+        for bad in ["USD:yahoo/AAPL?x=1", "USD:yahoo/../v7", "yahoo/AAPL", "USD:/AAPL", "USD:yahoo/"] {
+            assert!(parse_source(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+}
@@ -0,0 +1,1584 @@
+use crate::beancount::{self, parse_amount};
+use crate::error::LedgerError;
+use crate::lots::{CostSpec, Inventory, Lot};
+use crate::model::{AccountBalance, AccountNode, AssertionCheck, AssertionCheckQuery, Budget, BudgetLine, BudgetQuery, BudgetReport, AccountTreeQuery, ActivityDay, CashFlow, CashFlowLine, CashFlowQuery, CompareNode, CompareQuery, CompareReport, DatedAmount, Holding, HoldingLot, Holdings, HoldingsQuery, MonthEndNetWorthQuery, ActivityQuery, BalanceQuery, BalanceSheet, BalanceSheetQuery, BalanceSheetSection, RegisterRow, RegisterQuery, SpendingGroup, SpendingQuery, TrialBalance, TrialBalanceQuery, TrialBalanceRow, IncomeStatement, IncomeStatementLine, IncomeStatementPeriods, IncomeStatementPeriodsQuery, IncomeStatementQuery, MonthlyQuery, MonthlyReport, MonthlyTotal, NetWorthPoint, NetWorthQuery, NetWorthSeries, Posting, Transaction};
+use anyhow::Result;
+use chrono::{Datelike, Days, Months, NaiveDate};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// Longest range (in days) a daily report will densify.
+const MAX_REPORT_DAYS: i64 = 3660;
+
+/// Most intervals a bucketed report will split its range into.
+const MAX_REPORT_PERIODS: usize = 1200;
+
+/// Default largest per-currency difference still treated as balanced.
+pub const DEFAULT_TOLERANCE: Decimal = Decimal::from_parts(5, 0, 0, false, 3);
+
+pub fn parse_date(date: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", date, e)).into())
+}
+
+/// Whether the "natural" view of `account` flips beancount's raw sign, so
+/// income, liabilities and equity read as positive numbers.
+pub fn is_credit_account(account: &str) -> bool {
+    matches!(account.split(':').next(), Some("Income" | "Liabilities" | "Equity"))
+}
+
+/// Amount per currency, as a map that serializes with string amounts.
+pub type Amounts = BTreeMap<String, Decimal>;
+
+/// Declared display precision per currency, from `commodity` metadata.
+pub type Precisions = HashMap<String, u32>;
+
+/// Renders an amount as a string, rounded to its currency's declared
+/// precision and without the sign of a negated zero.
+pub fn format_amount(currency: &str, amount: Decimal, precisions: &Precisions) -> String {
+    let mut amount = amount;
+    if let Some(&precision) = precisions.get(currency) {
+        amount = amount.round_dp_with_strategy(precision, rust_decimal::RoundingStrategy::MidpointAwayFromZero);
+        amount.rescale(precision);
+    }
+    if amount.is_zero() {
+        amount.set_sign_positive(true);
+    }
+    amount.to_string()
+}
+
+/// Renders amounts as strings with `format_amount`.
+pub fn format_amounts(amounts: &Amounts, precisions: &Precisions) -> BTreeMap<String, String> {
+    amounts
+        .iter()
+        .map(|(currency, amount)| (currency.clone(), format_amount(currency, *amount, precisions)))
+        .collect()
+}
+
+/// Rates from `price` directives, for converting amounts into one currency.
+pub struct PriceTable {
+    /// (currency, quote currency) -> (date, rate), oldest first
+    rates: HashMap<(String, String), Vec<(String, Decimal)>>,
+}
+
+impl PriceTable {
+    pub fn load(data_dir: &Path) -> Result<Self> {
+        let mut rates: HashMap<(String, String), Vec<(String, Decimal)>> = HashMap::new();
+        for price in beancount::list_prices(data_dir)? {
+            // Prices written as expressions rather than plain numbers are skipped.
+            if let Ok(rate) = parse_amount(&price.amount) {
+                rates
+                    .entry((price.currency, price.quote_currency))
+                    .or_default()
+                    .push((price.date, rate));
+            }
+        }
+        Ok(Self { rates })
+    }
+
+    /// The most recent rate on or before `date` for one unit of `base` in
+    /// `quote`, falling back to the inverse of a `quote` price in `base`.
+    pub fn rate(&self, base: &str, quote: &str, date: &str) -> Option<Decimal> {
+        if base == quote {
+            return Some(Decimal::ONE);
+        }
+        let latest = |from: &str, to: &str| {
+            self.rates
+                .get(&(from.to_string(), to.to_string()))
+                .and_then(|rates| rates.iter().rev().find(|(d, _)| d.as_str() <= date))
+                .map(|(_, rate)| *rate)
+        };
+        latest(base, quote).or_else(|| {
+            latest(quote, base)
+                .filter(|rate| !rate.is_zero())
+                .map(|rate| Decimal::ONE / rate)
+        })
+    }
+
+    /// Converts every currency with a rate into `target`. Currencies without
+    /// one are kept as they are and also returned separately.
+    pub fn convert(&self, amounts: &Amounts, target: &str, date: &str) -> (Amounts, BTreeSet<String>) {
+        let mut converted = Amounts::new();
+        let mut unconverted = BTreeSet::new();
+        for (currency, amount) in amounts {
+            match self.rate(currency, target, date) {
+                Some(rate) => *converted.entry(target.to_string()).or_default() += *amount * rate,
+                None => {
+                    *converted.entry(currency.clone()).or_default() += *amount;
+                    unconverted.insert(currency.clone());
+                }
+            }
+        }
+        (converted, unconverted)
+    }
+}
+
+/// The price table and target for a `convert` parameter, with the date
+/// rates are looked up at (the report date, or today).
+struct Conversion {
+    prices: PriceTable,
+    target: String,
+    date: String,
+}
+
+impl Conversion {
+    fn load(data_dir: &Path, target: Option<&str>, date: Option<&str>) -> Result<Option<Self>> {
+        // Reports show the operating currency unless told otherwise; an
+        // empty `convert` turns conversion off.
+        let target = match target {
+            Some(target) => Some(target.to_string()),
+            None => beancount::operating_currency(data_dir)?,
+        };
+        let Some(target) = target.filter(|t| !t.is_empty()) else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            prices: PriceTable::load(data_dir)?,
+            target,
+            date: date
+                .map(str::to_string)
+                .unwrap_or_else(|| chrono::Local::now().date_naive().to_string()),
+        }))
+    }
+}
+
+/// Applies an optional conversion, collecting currencies it couldn't convert.
+fn convert(conversion: Option<&Conversion>, amounts: Amounts, unconverted: &mut BTreeSet<String>) -> Amounts {
+    match conversion {
+        Some(c) => {
+            let (converted, missing) = c.prices.convert(&amounts, &c.target, &c.date);
+            unconverted.extend(missing);
+            converted
+        }
+        None => amounts,
+    }
+}
+
+/// What a posting weighs towards the transaction balancing: its units, or
+/// the units converted through its cost or price. `None` for an empty
+/// (interpolated) amount.
+fn posting_weight(p: &Posting) -> Result<Option<(Decimal, String)>> {
+    if p.amount.trim().is_empty() {
+        return Ok(None);
+    }
+    let units = parse_amount(&p.amount)?;
+    let conversion = p
+        .cost
+        .as_deref()
+        .map(|c| (c.trim_start().starts_with("{{"), c))
+        .or_else(|| p.price.as_deref().map(|pr| (pr.trim_start().starts_with("@@"), pr)));
+    if let Some((total, text)) = conversion {
+        let tokens: Vec<&str> = text
+            .split(|c: char| c.is_whitespace() || matches!(c, '{' | '}' | '@' | ','))
+            .filter(|t| !t.is_empty())
+            .collect();
+        if let Some(i) = tokens.iter().position(|t| parse_amount(t).is_ok()) {
+            if let Some(currency) = tokens.get(i + 1).filter(|c| beancount::is_valid_currency(c)) {
+                let number = parse_amount(tokens[i])?;
+                let weight = if total { number * units.signum() } else { number * units };
+                return Ok(Some((weight, currency.to_string())));
+            }
+        }
+    }
+    Ok(Some((units, p.currency.clone())))
+}
+
+/// What a transaction's postings fail to net to, per currency. A posting
+/// without an amount absorbs any residual, so such transactions always
+/// balance.
+pub fn residual(tx: &Transaction) -> Result<Amounts> {
+    let mut residual = Amounts::new();
+    for p in &tx.postings {
+        match posting_weight(p)? {
+            Some((weight, currency)) => *residual.entry(currency).or_default() += weight,
+            None => return Ok(Amounts::new()),
+        }
+    }
+    residual.retain(|_, sum| !sum.is_zero());
+    Ok(residual)
+}
+
+/// The units each posting of `tx` adds to its account, as (account,
+/// currency, amount). A posting without an amount gets the residual that
+/// balances the others, one entry per unbalanced currency.
+pub fn posting_amounts(tx: &Transaction) -> Result<Vec<(String, String, Decimal)>> {
+    let mut residual = Amounts::new();
+    let mut amounts = Vec::new();
+    let mut missing = None;
+    for p in &tx.postings {
+        match posting_weight(p)? {
+            Some((weight, currency)) => {
+                *residual.entry(currency).or_default() += weight;
+                amounts.push((p.account.clone(), p.currency.clone(), parse_amount(&p.amount)?));
+            }
+            None if missing.is_none() => missing = Some(p),
+            None => {
+                return Err(LedgerError::invalid(format!(
+                    "Transaction on {} has more than one posting without an amount",
+                    tx.date
+                ))
+                .into())
+            }
+        }
+    }
+    if let Some(p) = missing {
+        for (currency, sum) in residual {
+            if sum.is_zero() || (!p.currency.is_empty() && p.currency != currency) {
+                continue;
+            }
+            amounts.push((p.account.clone(), currency, -sum));
+        }
+    }
+    Ok(amounts)
+}
+
+/// Per-day posting counts and amounts for a calendar heatmap. Every day in
+/// the range is present, including days with no activity. A posting without
+/// an amount counts with the amount that balances its transaction.
+pub fn activity(data_dir: &Path, query: &ActivityQuery) -> Result<Vec<ActivityDay>> {
+    let transactions = beancount::list_transactions(data_dir)?;
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let from = query.from.as_deref().map(parse_date).transpose()?;
+    let to = query.to.as_deref().map(parse_date).transpose()?;
+    let natural = query.sign.as_deref() == Some("natural");
+    let with_amounts = query.metric.as_deref() != Some("count");
+
+    let mut days: BTreeMap<NaiveDate, (u64, Amounts)> = BTreeMap::new();
+    for tx in &transactions {
+        let date = parse_date(&tx.date)?;
+        if from.is_some_and(|f| date < f) || to.is_some_and(|t| date > t) {
+            continue;
+        }
+        let matches = |account: &str| query.account_prefix.as_ref().is_none_or(|prefix| beancount::account_matches(account, prefix));
+        let count = tx.postings.iter().filter(|p| matches(&p.account)).count() as u64;
+        if count == 0 {
+            continue;
+        }
+        let day = days.entry(date).or_default();
+        day.0 += count;
+        if with_amounts {
+            for (account, currency, mut amount) in posting_amounts(tx)? {
+                if !matches(&account) {
+                    continue;
+                }
+                if natural && is_credit_account(&account) {
+                    amount = -amount;
+                }
+                *day.1.entry(currency).or_default() += amount;
+            }
+        }
+    }
+
+    let (Some(start), Some(end)) = (
+        from.or_else(|| days.keys().next().copied()),
+        to.or_else(|| days.keys().next_back().copied()),
+    ) else {
+        return Ok(vec![]);
+    };
+    if (end - start).num_days() > MAX_REPORT_DAYS {
+        return Err(LedgerError::invalid(format!("Range too large: at most {} days", MAX_REPORT_DAYS)).into());
+    }
+
+    Ok(start
+        .iter_days()
+        .take_while(|d| *d <= end)
+        .map(|date| {
+            let (count, amounts) = days.remove(&date).unwrap_or_default();
+            ActivityDay {
+                date: date.to_string(),
+                count,
+                amounts: format_amounts(&amounts, &precisions),
+            }
+        })
+        .collect())
+}
+
+/// Income and expenses over a period. Income is shown as a positive number
+/// (beancount records it as negative) and net income is income less
+/// expenses, per currency.
+pub fn income_statement(data_dir: &Path, query: &IncomeStatementQuery) -> Result<IncomeStatement> {
+    let from = query.from.as_deref().map(parse_date).transpose()?;
+    let to = query.to.as_deref().map(parse_date).transpose()?;
+
+    let transactions = beancount::list_transactions(data_dir)?;
+    let mut in_range = Vec::new();
+    for tx in &transactions {
+        let date = parse_date(&tx.date)?;
+        if from.is_some_and(|f| date < f) || to.is_some_and(|t| date > t) {
+            continue;
+        }
+        in_range.push(tx);
+    }
+
+    let conversion = Conversion::load(data_dir, query.convert.as_deref(), query.to.as_deref())?;
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let period = IncomePeriod {
+        from: query.from.clone(),
+        to: query.to.clone(),
+        detail: query.detail.unwrap_or(false),
+        depth: None,
+    };
+    period.summarize(&in_range, conversion.as_ref(), &precisions)
+}
+
+/// One period of an income statement and how to break it down.
+struct IncomePeriod {
+    from: Option<String>,
+    to: Option<String>,
+    detail: bool,
+    /// Roll the per-account lines up to this many components.
+    depth: Option<usize>,
+}
+
+impl IncomePeriod {
+    /// Totals `transactions`, which must already be restricted to the period.
+    fn summarize(self, transactions: &[&Transaction], conversion: Option<&Conversion>, precisions: &Precisions) -> Result<IncomeStatement> {
+        let mut income = Amounts::new();
+        let mut expenses = Amounts::new();
+        let mut accounts: BTreeMap<String, Amounts> = BTreeMap::new();
+        for tx in transactions {
+            for (account, currency, amount) in posting_amounts(tx)? {
+                let (totals, amount) = match account.split(':').next() {
+                    Some("Income") => (&mut income, -amount),
+                    Some("Expenses") => (&mut expenses, amount),
+                    _ => continue,
+                };
+                *totals.entry(currency.clone()).or_default() += amount;
+                if self.detail {
+                    let account = match self.depth {
+                        Some(depth) => truncate_account(&account, depth),
+                        None => account,
+                    };
+                    *accounts.entry(account).or_default().entry(currency).or_default() += amount;
+                }
+            }
+        }
+
+        let mut unconverted = BTreeSet::new();
+        let income = convert(conversion, income, &mut unconverted);
+        let expenses = convert(conversion, expenses, &mut unconverted);
+
+        let mut net_income = income.clone();
+        for (currency, amount) in &expenses {
+            *net_income.entry(currency.clone()).or_default() -= *amount;
+        }
+
+        Ok(IncomeStatement {
+            from: self.from,
+            to: self.to,
+            income: format_amounts(&income, precisions),
+            expenses: format_amounts(&expenses, precisions),
+            net_income: format_amounts(&net_income, precisions),
+            accounts: accounts
+                .into_iter()
+                .map(|(account, amounts)| IncomeStatementLine {
+                    account,
+                    amounts: format_amounts(&convert(conversion, amounts, &mut unconverted), precisions),
+                })
+                .collect(),
+            unconverted: unconverted.into_iter().collect(),
+        })
+    }
+}
+
+/// A reporting interval. Weeks start on Monday; months, quarters and years
+/// follow the calendar.
+#[derive(Debug, Clone, Copy)]
+enum Interval {
+    Week,
+    Months(u32),
+}
+
+impl Interval {
+    fn parse(interval: &str) -> Result<Self> {
+        match interval {
+            "week" => Ok(Self::Week),
+            "month" => Ok(Self::Months(1)),
+            "quarter" => Ok(Self::Months(3)),
+            "year" => Ok(Self::Months(12)),
+            other => Err(LedgerError::invalid(format!("Unknown interval '{}': expected week, month, quarter or year", other)).into()),
+        }
+    }
+
+    /// The start of the interval `date` falls in.
+    fn start_of(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Week => date - Days::new(date.weekday().num_days_from_monday().into()),
+            Self::Months(months) => {
+                let first_month = date.month0() / months * months + 1;
+                NaiveDate::from_ymd_opt(date.year(), first_month, 1).expect("valid month start")
+            }
+        }
+    }
+
+    fn next(self, start: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Self::Week => start.checked_add_days(Days::new(7)),
+            Self::Months(months) => start.checked_add_months(Months::new(months)),
+        }
+    }
+}
+
+/// Splits `start..=end` into whole intervals, clipped to the range at both
+/// ends. A range needing more than `MAX_REPORT_PERIODS` is refused.
+fn interval_periods(start: NaiveDate, end: NaiveDate, interval: Interval) -> Result<Vec<(NaiveDate, NaiveDate)>> {
+    let mut period_start = interval.start_of(start);
+    let mut periods = Vec::new();
+    while period_start <= end {
+        if periods.len() == MAX_REPORT_PERIODS {
+            return Err(LedgerError::invalid(format!("Range too large: at most {} periods", MAX_REPORT_PERIODS)).into());
+        }
+        let Some(next) = interval.next(period_start) else { break };
+        let last = next.pred_opt().unwrap_or(next);
+        periods.push((period_start.max(start), last.min(end)));
+        period_start = next;
+    }
+    Ok(periods)
+}
+
+/// An income statement per `interval` bucket (or one for the whole range),
+/// each with the per-account breakdown and its own net income. Without
+/// `from`/`to` the range runs from the first to the last transaction.
+pub fn income_statement_periods(data_dir: &Path, query: &IncomeStatementPeriodsQuery) -> Result<IncomeStatementPeriods> {
+    if query.depth == Some(0) {
+        return Err(LedgerError::invalid("depth must be at least 1").into());
+    }
+    let interval = query.interval.as_deref().map(Interval::parse).transpose()?;
+    let from = query.from.as_deref().map(parse_date).transpose()?;
+    let to = query.to.as_deref().map(parse_date).transpose()?;
+
+    let mut dated = Vec::new();
+    for tx in beancount::list_transactions(data_dir)? {
+        let date = parse_date(&tx.date)?;
+        if from.is_some_and(|f| date < f) || to.is_some_and(|t| date > t) {
+            continue;
+        }
+        dated.push((date, tx));
+    }
+
+    let (Some(start), Some(end)) = (
+        from.or_else(|| dated.iter().map(|(d, _)| *d).min()),
+        to.or_else(|| dated.iter().map(|(d, _)| *d).max()),
+    ) else {
+        return Ok(IncomeStatementPeriods { interval: query.interval.clone(), periods: Vec::new() });
+    };
+    let bounds = match interval {
+        Some(interval) => interval_periods(start, end, interval)?,
+        None => vec![(start, end)],
+    };
+
+    // Each period converts at rates as of its own last day.
+    let mut conversion = Conversion::load(data_dir, query.convert.as_deref(), None)?;
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let mut periods = Vec::new();
+    for (period_start, period_end) in bounds {
+        let transactions: Vec<&Transaction> = dated
+            .iter()
+            .filter(|(d, _)| *d >= period_start && *d <= period_end)
+            .map(|(_, tx)| tx)
+            .collect();
+        if let Some(conversion) = conversion.as_mut() {
+            conversion.date = period_end.to_string();
+        }
+        let period = IncomePeriod {
+            from: Some(period_start.to_string()),
+            to: Some(period_end.to_string()),
+            detail: true,
+            depth: query.depth,
+        };
+        periods.push(period.summarize(&transactions, conversion.as_ref(), &precisions)?);
+    }
+    Ok(IncomeStatementPeriods { interval: query.interval.clone(), periods })
+}
+
+/// Units per currency for every account with postings on or before `at`.
+pub fn balances(data_dir: &Path, at: Option<&str>) -> Result<BTreeMap<String, Amounts>> {
+    let at = at.map(parse_date).transpose()?;
+    let mut balances: BTreeMap<String, Amounts> = BTreeMap::new();
+    for tx in beancount::list_transactions(data_dir)? {
+        if at.is_some_and(|at| parse_date(&tx.date).is_ok_and(|d| d > at)) {
+            continue;
+        }
+        for (account, currency, amount) in posting_amounts(&tx)? {
+            *balances.entry(account).or_default().entry(currency).or_default() += amount;
+        }
+    }
+    Ok(balances)
+}
+
+/// The first `depth` components of `account`.
+fn truncate_account(account: &str, depth: usize) -> String {
+    account.split(':').take(depth).collect::<Vec<_>>().join(":")
+}
+
+/// Per-month totals for an account and its sub-accounts. Every month from
+/// the first to the last is present, months without postings as zeros.
+pub fn monthly(data_dir: &Path, query: &MonthlyQuery) -> Result<MonthlyReport> {
+    let from = query.from.as_deref().map(parse_date).transpose()?;
+    let to = query.to.as_deref().map(parse_date).transpose()?;
+
+    let mut months: BTreeMap<String, Amounts> = BTreeMap::new();
+    let mut currencies = BTreeSet::new();
+    let (mut first, mut last) = (None, None);
+    for tx in beancount::list_transactions(data_dir)? {
+        let date = parse_date(&tx.date)?;
+        if from.is_some_and(|f| date < f) || to.is_some_and(|t| date > t) {
+            continue;
+        }
+        for (account, currency, amount) in posting_amounts(&tx)? {
+            if !beancount::account_matches(&account, &query.account) {
+                continue;
+            }
+            first = Some(first.map_or(date, |f: NaiveDate| f.min(date)));
+            last = Some(last.map_or(date, |l: NaiveDate| l.max(date)));
+            currencies.insert(currency.clone());
+            *months.entry(date.format("%Y-%m").to_string()).or_default().entry(currency).or_default() += amount;
+        }
+    }
+
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let range = from.or(first).zip(to.or(last));
+    let months = range
+        .map(|(start, end)| interval_periods(start, end, Interval::Months(1)))
+        .transpose()?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(start, _)| {
+            let month = start.format("%Y-%m").to_string();
+            let mut amounts = months.remove(&month).unwrap_or_default();
+            for currency in &currencies {
+                amounts.entry(currency.clone()).or_default();
+            }
+            MonthlyTotal {
+                month,
+                amounts: format_amounts(&amounts, &precisions),
+            }
+        })
+        .collect();
+    Ok(MonthlyReport {
+        account: query.account.clone(),
+        from: query.from.clone(),
+        to: query.to.clone(),
+        months,
+    })
+}
+
+/// Assets, liabilities and net worth at the end of each interval. One pass
+/// over the transactions in date order accumulates the balances and takes a
+/// snapshot at every interval boundary.
+pub fn net_worth(data_dir: &Path, query: &NetWorthQuery) -> Result<NetWorthSeries> {
+    let interval = Interval::parse(query.interval.as_deref().unwrap_or("month"))?;
+    let from = query.from.as_deref().map(parse_date).transpose()?;
+    let to = query.to.as_deref().map(parse_date).transpose()?;
+
+    let mut dated = Vec::new();
+    for tx in beancount::list_transactions(data_dir)? {
+        dated.push((parse_date(&tx.date)?, tx));
+    }
+    dated.sort_by_key(|(date, _)| *date);
+
+    let mut conversion = Conversion::load(data_dir, query.currency.as_deref(), None)?;
+    let (Some(start), Some(end)) = (
+        from.or_else(|| dated.first().map(|(d, _)| *d)),
+        to.or_else(|| dated.last().map(|(d, _)| *d)),
+    ) else {
+        return Ok(NetWorthSeries {
+            currency: conversion.map(|c| c.target),
+            points: Vec::new(),
+            warnings: Vec::new(),
+        });
+    };
+
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let mut assets = Amounts::new();
+    let mut liabilities = Amounts::new();
+    let mut missing: BTreeMap<String, NaiveDate> = BTreeMap::new();
+    let mut points = Vec::new();
+    let mut transactions = dated.iter().peekable();
+    for (_, period_end) in interval_periods(start, end, interval)? {
+        while let Some((_, tx)) = transactions.next_if(|(date, _)| *date <= period_end) {
+            for (account, currency, amount) in posting_amounts(tx)? {
+                match account.split(':').next() {
+                    Some("Assets") => *assets.entry(currency).or_default() += amount,
+                    Some("Liabilities") => *liabilities.entry(currency).or_default() -= amount,
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(conversion) = conversion.as_mut() {
+            conversion.date = period_end.to_string();
+        }
+        let mut unconverted = BTreeSet::new();
+        let point_assets = convert(conversion.as_ref(), assets.clone(), &mut unconverted);
+        let point_liabilities = convert(conversion.as_ref(), liabilities.clone(), &mut unconverted);
+        for currency in unconverted {
+            missing.entry(currency).or_insert(period_end);
+        }
+        let mut net = point_assets.clone();
+        for (currency, amount) in &point_liabilities {
+            *net.entry(currency.clone()).or_default() -= *amount;
+        }
+        points.push(NetWorthPoint {
+            date: period_end.to_string(),
+            assets: format_amounts(&point_assets, &precisions),
+            liabilities: format_amounts(&point_liabilities, &precisions),
+            net: format_amounts(&net, &precisions),
+        });
+    }
+
+    let currency = conversion.map(|c| c.target);
+    let warnings = missing
+        .into_iter()
+        .map(|(c, date)| match &currency {
+            Some(target) => format!("No price for {} in {} on or before {}; left unconverted", c, target, date),
+            None => format!("{} left unconverted", c),
+        })
+        .collect();
+    Ok(NetWorthSeries { currency, points, warnings })
+}
+
+/// Net worth at each month-end, from the same single pass as `net_worth`.
+pub fn month_end_net_worth(data_dir: &Path, query: &MonthEndNetWorthQuery) -> Result<Vec<DatedAmount>> {
+    let series = net_worth(
+        data_dir,
+        &NetWorthQuery {
+            from: query.from.clone(),
+            to: query.to.clone(),
+            interval: Some("month".to_string()),
+            currency: query.convert.clone(),
+        },
+    )?;
+    Ok(series
+        .points
+        .into_iter()
+        .map(|p| DatedAmount { date: p.date, amount: p.net })
+        .collect())
+}
+
+/// Parses `YYYY`, `YYYY-MM` or `YYYY-Qn` into its first and last day.
+pub fn parse_period(period: &str) -> Result<(NaiveDate, NaiveDate)> {
+    let invalid = || LedgerError::invalid(format!("Invalid period '{}': expected YYYY, YYYY-MM or YYYY-Qn", period));
+    let (year, rest) = period.split_once('-').map_or((period, None), |(y, r)| (y, Some(r)));
+    if year.len() != 4 {
+        return Err(invalid().into());
+    }
+    let year: i32 = year.parse().map_err(|_| invalid())?;
+    let (month, interval) = match rest {
+        None => (1, Interval::Months(12)),
+        Some(q) if q.starts_with('Q') => match q[1..].parse::<u32>() {
+            Ok(n @ 1..=4) if q.len() == 2 => (n * 3 - 2, Interval::Months(3)),
+            _ => return Err(invalid().into()),
+        },
+        Some(m) if m.len() == 2 => (m.parse().map_err(|_| invalid())?, Interval::Months(1)),
+        Some(_) => return Err(invalid().into()),
+    };
+    let start = NaiveDate::from_ymd_opt(year, month, 1).ok_or_else(invalid)?;
+    let end = interval.next(start).and_then(|d| d.pred_opt()).ok_or_else(invalid)?;
+    Ok((start, end))
+}
+
+/// Per-account totals under `root` for two periods, nested by the account
+/// hierarchy, with the change between them.
+pub fn compare(data_dir: &Path, query: &CompareQuery) -> Result<CompareReport> {
+    let root = query.root.as_deref().unwrap_or("Expenses");
+    let periods = [parse_period(&query.period)?, parse_period(&query.against)?];
+
+    // Account and ancestor (from `root` down) -> totals for each period.
+    let mut nodes: BTreeMap<String, [Amounts; 2]> = BTreeMap::new();
+    nodes.insert(root.to_string(), Default::default());
+    for tx in beancount::list_transactions(data_dir)? {
+        let date = parse_date(&tx.date)?;
+        // The periods may overlap, e.g. a month against its year.
+        let sides: Vec<usize> = (0..2).filter(|&i| date >= periods[i].0 && date <= periods[i].1).collect();
+        if sides.is_empty() {
+            continue;
+        }
+        for (account, currency, amount) in posting_amounts(&tx)? {
+            if !beancount::account_matches(&account, root) {
+                continue;
+            }
+            let components: Vec<&str> = account.split(':').collect();
+            for depth in root.split(':').count()..=components.len() {
+                let totals = nodes.entry(components[..depth].join(":")).or_default();
+                for &side in &sides {
+                    *totals[side].entry(currency.clone()).or_default() += amount;
+                }
+            }
+        }
+    }
+
+    fn build(full_name: &str, nodes: &BTreeMap<String, [Amounts; 2]>, precisions: &Precisions) -> CompareNode {
+        let prefix = format!("{}:", full_name);
+        let children = nodes
+            .range(prefix.clone()..)
+            .take_while(|(name, _)| name.starts_with(&prefix))
+            .filter(|(name, _)| !name[prefix.len()..].contains(':'))
+            .map(|(name, _)| build(name, nodes, precisions))
+            .collect();
+
+        let [mut period, mut against] = nodes[full_name].clone();
+        for currency in period.keys().chain(against.keys()).cloned().collect::<Vec<_>>() {
+            period.entry(currency.clone()).or_default();
+            against.entry(currency).or_default();
+        }
+        let delta: Amounts = period.iter().map(|(c, amount)| (c.clone(), *amount - against[c])).collect();
+        let percent = delta
+            .iter()
+            .map(|(c, change)| {
+                let base = against[c];
+                let percent = (!base.is_zero()).then(|| (*change / base.abs() * Decimal::ONE_HUNDRED).round_dp(2).to_string());
+                (c.clone(), percent)
+            })
+            .collect();
+        CompareNode {
+            name: full_name.rsplit(':').next().unwrap_or(full_name).to_string(),
+            full_name: full_name.to_string(),
+            children,
+            period: format_amounts(&period, precisions),
+            against: format_amounts(&against, precisions),
+            delta: format_amounts(&delta, precisions),
+            percent,
+        }
+    }
+
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    Ok(CompareReport {
+        period: query.period.clone(),
+        against: query.against.clone(),
+        root: build(root, &nodes, &precisions),
+    })
+}
+
+/// What asset accounts hold at cost as of `at`, lot by lot, with cost basis,
+/// market value from `price` directives and unrealized gain. Lots are
+/// matched first in, first out; a sale of more than is held becomes a
+/// warning.
+pub fn holdings(data_dir: &Path, query: &HoldingsQuery) -> Result<Holdings> {
+    let at = match query.at.as_deref() {
+        Some(at) => parse_date(at)?,
+        None => chrono::Local::now().date_naive(),
+    };
+    let mut transactions = beancount::list_transactions(data_dir)?;
+    transactions.sort_by(|a, b| a.date.cmp(&b.date));
+
+    // (account, commodity) -> open lots
+    let mut inventories: BTreeMap<(String, String), Inventory> = BTreeMap::new();
+    let mut warnings = Vec::new();
+    for tx in &transactions {
+        if parse_date(&tx.date)? > at {
+            break;
+        }
+        for p in &tx.postings {
+            let Some(cost) = p.cost.as_deref() else { continue };
+            if !beancount::account_matches(&p.account, "Assets") {
+                continue;
+            }
+            let Ok(units) = parse_amount(&p.amount) else { continue };
+            let spec = CostSpec::parse(cost, units);
+            let inventory = inventories.entry((p.account.clone(), p.currency.clone())).or_default();
+            if units.is_sign_positive() {
+                inventory.add(units, &spec, &tx.date);
+                continue;
+            }
+            let (_, unmatched) = inventory.reduce(-units, &spec);
+            if !unmatched.is_zero() {
+                warnings.push(format!(
+                    "{}: {} reduces {} {} more than the matching lots hold",
+                    tx.date, p.account, unmatched, p.currency
+                ));
+            }
+        }
+    }
+
+    let prices = PriceTable::load(data_dir)?;
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let at = at.to_string();
+    let mut holdings = Vec::new();
+    for ((account, currency), inventory) in inventories {
+        // Lots bought in different currencies are separate holdings.
+        let mut by_currency: BTreeMap<String, Vec<Lot>> = BTreeMap::new();
+        for lot in inventory.lots {
+            by_currency.entry(lot.currency.clone()).or_default().push(lot);
+        }
+        for (cost_currency, lots) in by_currency {
+            let units: Decimal = lots.iter().map(|l| l.units).sum();
+            let cost_basis: Decimal = lots.iter().map(|l| l.units * l.cost).sum();
+            let market_value = prices.rate(&currency, &cost_currency, &at).map(|rate| units * rate);
+            let money = |amount: Decimal| format_amount(&cost_currency, amount, &precisions);
+            holdings.push(Holding {
+                account: account.clone(),
+                currency: currency.clone(),
+                units: units.normalize().to_string(),
+                cost_basis: money(cost_basis),
+                market_value: market_value.map(money),
+                unrealized_gain: market_value.map(|value| money(value - cost_basis)),
+                lots: lots
+                    .into_iter()
+                    .map(|l| HoldingLot {
+                        units: l.units.normalize().to_string(),
+                        cost: l.cost.normalize().to_string(),
+                        date: l.date,
+                    })
+                    .collect(),
+                cost_currency,
+            });
+        }
+    }
+    Ok(Holdings { at, holdings, warnings })
+}
+
+/// Each budgeted account's spending in a month against its budget, plus
+/// spending no budget covers. A budget covers its account's subaccounts in
+/// its own currency; the deepest covering budget gets the posting.
+pub fn budget(data_dir: &Path, query: &BudgetQuery) -> Result<BudgetReport> {
+    let (from, to) = parse_period(&query.period)?;
+    if (from.year(), from.month()) != (to.year(), to.month()) {
+        return Err(LedgerError::invalid(format!("Invalid period '{}': budgets are monthly, expected YYYY-MM", query.period)).into());
+    }
+
+    // The latest budget on or before the month's end, per account.
+    let mut budgets: BTreeMap<String, Budget> = BTreeMap::new();
+    for budget in beancount::list_budgets(data_dir)? {
+        if parse_date(&budget.date)? <= to {
+            budgets.insert(budget.account.clone(), budget);
+        }
+    }
+    let mut actual: BTreeMap<String, Decimal> = budgets.keys().map(|a| (a.clone(), Decimal::ZERO)).collect();
+    let mut unbudgeted: BTreeMap<(String, String), Decimal> = BTreeMap::new();
+
+    for tx in beancount::list_transactions(data_dir)? {
+        let date = parse_date(&tx.date)?;
+        if date < from || date > to {
+            continue;
+        }
+        for (account, currency, amount) in posting_amounts(&tx)? {
+            if !beancount::account_matches(&account, "Expenses") {
+                continue;
+            }
+            let covering = budgets
+                .values()
+                .filter(|b| b.currency == currency && beancount::account_matches(&account, &b.account))
+                .max_by_key(|b| b.account.len());
+            match covering {
+                Some(b) => *actual.get_mut(&b.account).unwrap() += amount,
+                None => *unbudgeted.entry((account, currency)).or_default() += amount,
+            }
+        }
+    }
+
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let mut lines = Vec::new();
+    for (account, budget) in &budgets {
+        let budgeted = parse_amount(&budget.amount)?;
+        let spent = actual[account];
+        let money = |amount: Decimal| format_amount(&budget.currency, amount, &precisions);
+        lines.push(BudgetLine {
+            account: account.clone(),
+            currency: budget.currency.clone(),
+            budgeted: Some(money(budgeted)),
+            actual: money(spent),
+            remaining: Some(money(budgeted - spent)),
+            percent_used: (!budgeted.is_zero()).then(|| (spent / budgeted * Decimal::ONE_HUNDRED).round_dp(2).to_string()),
+            unbudgeted: false,
+        });
+    }
+    for ((account, currency), spent) in unbudgeted {
+        lines.push(BudgetLine {
+            actual: format_amount(&currency, spent, &precisions),
+            account,
+            currency,
+            budgeted: None,
+            remaining: None,
+            percent_used: None,
+            unbudgeted: true,
+        });
+    }
+    Ok(BudgetReport {
+        period: query.period.clone(),
+        from: from.to_string(),
+        to: to.to_string(),
+        lines,
+    })
+}
+
+/// The group for transactions without a payee or tag.
+const NO_GROUP: &str = "(none)";
+
+/// Totals of the postings under `account` per account, payee or tag, largest
+/// first. Transactions with several tags count towards each; ones without a
+/// payee or tag go into `(none)`.
+pub fn spending(data_dir: &Path, query: &SpendingQuery) -> Result<Vec<SpendingGroup>> {
+    if query.depth == Some(0) {
+        return Err(LedgerError::invalid("depth must be at least 1").into());
+    }
+    let group_by = query.group_by.as_deref().unwrap_or("account");
+    if !matches!(group_by, "account" | "payee" | "tag") {
+        return Err(LedgerError::invalid(format!("Unknown group_by '{}': expected account, payee or tag", group_by)).into());
+    }
+    let filter = query.filter();
+    let prefix = filter.account.as_deref().unwrap_or_default();
+
+    let mut groups: BTreeMap<String, (Amounts, usize)> = BTreeMap::new();
+    for tx in beancount::filter_transactions(beancount::list_transactions(data_dir)?, &filter) {
+        let mut amounts: BTreeMap<String, Amounts> = BTreeMap::new();
+        for (account, currency, amount) in posting_amounts(&tx)? {
+            if !beancount::account_matches(&account, prefix) {
+                continue;
+            }
+            let keys = match group_by {
+                "account" => vec![match query.depth {
+                    Some(depth) => truncate_account(&account, depth),
+                    None => account,
+                }],
+                "payee" => vec![tx.payee.clone().filter(|p| !p.is_empty()).unwrap_or_else(|| NO_GROUP.to_string())],
+                _ if tx.tags.is_empty() => vec![NO_GROUP.to_string()],
+                _ => tx.tags.iter().map(|t| t.trim_start_matches('#').to_string()).collect(),
+            };
+            for key in keys {
+                *amounts.entry(key).or_default().entry(currency.clone()).or_default() += amount;
+            }
+        }
+        for (key, amounts) in amounts {
+            let group = groups.entry(key).or_default();
+            group.1 += 1;
+            for (currency, amount) in amounts {
+                *group.0.entry(currency).or_default() += amount;
+            }
+        }
+    }
+
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let mut sorted: Vec<(String, Amounts, usize)> = groups.into_iter().map(|(k, (a, c))| (k, a, c)).collect();
+    // Largest single-currency total first.
+    let largest = |amounts: &Amounts| amounts.values().copied().max().unwrap_or_default();
+    sorted.sort_by(|a, b| largest(&b.1).cmp(&largest(&a.1)).then_with(|| a.0.cmp(&b.0)));
+    Ok(sorted
+        .into_iter()
+        .map(|(group, total, count)| {
+            let average: Amounts = total
+                .iter()
+                .map(|(currency, amount)| {
+                    let average = *amount / Decimal::from(count);
+                    (currency.clone(), average.round_dp(amount.scale().max(2)))
+                })
+                .collect();
+            SpendingGroup {
+                group,
+                total: format_amounts(&total, &precisions),
+                count,
+                average: format_amounts(&average, &precisions),
+            }
+        })
+        .collect())
+}
+
+/// Where money moved: each Income, Expenses or Equity posting in a
+/// transaction that touches Assets or Liabilities is the other side of a
+/// cash movement, an inflow when the posting is negative and an outflow when
+/// positive. Transactions with no such posting are transfers and reported
+/// apart.
+pub fn cash_flow(data_dir: &Path, query: &CashFlowQuery) -> Result<CashFlow> {
+    let from = query.from.as_deref().map(parse_date).transpose()?;
+    let to = query.to.as_deref().map(parse_date).transpose()?;
+    let is_cash = |account: &str| matches!(account.split(':').next(), Some("Assets" | "Liabilities"));
+
+    let mut inflows: BTreeMap<String, Amounts> = BTreeMap::new();
+    let mut outflows: BTreeMap<String, Amounts> = BTreeMap::new();
+    let mut transfers: BTreeMap<String, Amounts> = BTreeMap::new();
+    for tx in beancount::list_transactions(data_dir)? {
+        let date = parse_date(&tx.date)?;
+        if from.is_some_and(|f| date < f) || to.is_some_and(|t| date > t) {
+            continue;
+        }
+        let amounts = posting_amounts(&tx)?;
+        if !amounts.iter().any(|(account, _, _)| is_cash(account)) {
+            continue;
+        }
+        let transfer = amounts.iter().all(|(account, _, _)| is_cash(account));
+        for (account, currency, amount) in amounts {
+            if transfer {
+                *transfers.entry(account).or_default().entry(currency).or_default() += amount;
+            } else if !is_cash(&account) {
+                let (flows, amount) = if amount.is_sign_negative() { (&mut inflows, -amount) } else { (&mut outflows, amount) };
+                *flows.entry(account).or_default().entry(currency).or_default() += amount;
+            }
+        }
+    }
+
+    let total = |flows: &BTreeMap<String, Amounts>| {
+        let mut total = Amounts::new();
+        for (currency, amount) in flows.values().flatten() {
+            *total.entry(currency.clone()).or_default() += *amount;
+        }
+        total
+    };
+    let total_inflow = total(&inflows);
+    let total_outflow = total(&outflows);
+    let mut net = total_inflow.clone();
+    for (currency, amount) in &total_outflow {
+        *net.entry(currency.clone()).or_default() -= *amount;
+    }
+
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let lines = |flows: BTreeMap<String, Amounts>| -> Vec<CashFlowLine> {
+        flows
+            .into_iter()
+            .map(|(account, amounts)| CashFlowLine {
+                account,
+                amounts: format_amounts(&amounts, &precisions),
+            })
+            .collect()
+    };
+    Ok(CashFlow {
+        from: query.from.clone(),
+        to: query.to.clone(),
+        total_inflow: format_amounts(&total_inflow, &precisions),
+        total_outflow: format_amounts(&total_outflow, &precisions),
+        net: format_amounts(&net, &precisions),
+        inflows: lines(inflows),
+        outflows: lines(outflows),
+        transfers: lines(transfers),
+    })
+}
+
+/// Every account's balance split into debits and credits, with totals. A
+/// ledger that doesn't sum to zero still gets its rows, plus the residual
+/// per currency.
+pub fn trial_balance(data_dir: &Path, query: &TrialBalanceQuery) -> Result<TrialBalance> {
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let mut total_debit = Amounts::new();
+    let mut total_credit = Amounts::new();
+    let mut rows = Vec::new();
+    for (account, amounts) in balances(data_dir, query.at.as_deref())? {
+        let mut debit = Amounts::new();
+        let mut credit = Amounts::new();
+        for (currency, amount) in amounts.into_iter().filter(|(_, a)| !a.is_zero()) {
+            if amount.is_sign_positive() {
+                *total_debit.entry(currency.clone()).or_default() += amount;
+                debit.insert(currency, amount);
+            } else {
+                *total_credit.entry(currency.clone()).or_default() -= amount;
+                credit.insert(currency, -amount);
+            }
+        }
+        if debit.is_empty() && credit.is_empty() {
+            continue;
+        }
+        rows.push(TrialBalanceRow {
+            account,
+            debit: format_amounts(&debit, &precisions),
+            credit: format_amounts(&credit, &precisions),
+        });
+    }
+
+    let mut residual = total_debit.clone();
+    for (currency, amount) in &total_credit {
+        *residual.entry(currency.clone()).or_default() -= *amount;
+    }
+    residual.retain(|_, amount| !amount.is_zero());
+    Ok(TrialBalance {
+        at: query.at.clone(),
+        rows,
+        total_debit: format_amounts(&total_debit, &precisions),
+        total_credit: format_amounts(&total_credit, &precisions),
+        balanced: residual.is_empty(),
+        residual: format_amounts(&residual, &precisions),
+    })
+}
+
+/// Balances of every account, optionally rolled up into their ancestors at
+/// `depth` components.
+pub fn balance_report(data_dir: &Path, query: &BalanceQuery) -> Result<Vec<AccountBalance>> {
+    if query.depth == Some(0) {
+        return Err(LedgerError::invalid("depth must be at least 1").into());
+    }
+    let mut rolled: BTreeMap<String, Amounts> = BTreeMap::new();
+    for (account, amounts) in balances(data_dir, query.at.as_deref())? {
+        let account = match query.depth {
+            Some(depth) => truncate_account(&account, depth),
+            None => account,
+        };
+        let totals = rolled.entry(account).or_default();
+        for (currency, amount) in amounts {
+            *totals.entry(currency).or_default() += amount;
+        }
+    }
+    let conversion = Conversion::load(data_dir, query.convert.as_deref(), query.at.as_deref())?;
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    Ok(rolled
+        .into_iter()
+        .map(|(account, amounts)| {
+            let mut unconverted = BTreeSet::new();
+            let amounts = convert(conversion.as_ref(), amounts, &mut unconverted);
+            AccountBalance {
+                account,
+                balances: format_amounts(&amounts, &precisions),
+                unconverted: unconverted.into_iter().collect(),
+            }
+        })
+        .collect())
+}
+
+/// Balance of `name` including its sub-accounts.
+pub fn account_balance(data_dir: &Path, name: &str, query: &BalanceQuery) -> Result<AccountBalance> {
+    let mut known = beancount::list_accounts(data_dir)?
+        .iter()
+        .any(|a| beancount::account_matches(&a.name, name));
+    let mut totals = Amounts::new();
+    for (account, amounts) in balances(data_dir, query.at.as_deref())? {
+        if !beancount::account_matches(&account, name) {
+            continue;
+        }
+        known = true;
+        for (currency, amount) in amounts {
+            *totals.entry(currency).or_default() += amount;
+        }
+    }
+    if !known {
+        return Err(LedgerError::not_found(format!("Account {} not found", name)).into());
+    }
+    let conversion = Conversion::load(data_dir, query.convert.as_deref(), query.at.as_deref())?;
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let mut unconverted = BTreeSet::new();
+    let totals = convert(conversion.as_ref(), totals, &mut unconverted);
+    Ok(AccountBalance {
+        account: name.to_string(),
+        balances: format_amounts(&totals, &precisions),
+        unconverted: unconverted.into_iter().collect(),
+    })
+}
+
+/// Whether a `balance` assertion for `name` would pass. Like beancount, it
+/// counts postings to the account and its sub-accounts before `date`, and
+/// allows a difference of half the last digit of the asserted amount.
+pub fn check_assertion(data_dir: &Path, name: &str, query: &AssertionCheckQuery) -> Result<AssertionCheck> {
+    let date = parse_date(&query.date)?;
+    let expected = parse_amount(&query.amount)?;
+    let mut known = beancount::list_accounts(data_dir)?
+        .iter()
+        .any(|a| beancount::account_matches(&a.name, name));
+    let mut actual = Decimal::ZERO;
+    for tx in beancount::list_transactions(data_dir)? {
+        let before = parse_date(&tx.date).is_ok_and(|d| d < date);
+        for (account, currency, amount) in posting_amounts(&tx)? {
+            if !beancount::account_matches(&account, name) {
+                continue;
+            }
+            known = true;
+            if before && currency == query.currency {
+                actual += amount;
+            }
+        }
+    }
+    if !known {
+        return Err(LedgerError::not_found(format!("Account {} not found", name)).into());
+    }
+    let difference = actual - expected;
+    let tolerance = Decimal::new(5, expected.scale() + 1);
+    Ok(AssertionCheck {
+        account: name.to_string(),
+        date: query.date.clone(),
+        currency: query.currency.clone(),
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+        difference: difference.to_string(),
+        passes: difference.abs() <= tolerance,
+    })
+}
+
+/// Assets, liabilities and equity as of a date. Each section lists every
+/// account together with its ancestors (rolled up), in hierarchy order.
+/// Liabilities and equity read as positive numbers; income and expenses
+/// not yet closed into equity count as retained earnings, so a consistent
+/// ledger has assets equal to liabilities plus equity.
+pub fn balance_sheet(data_dir: &Path, query: &BalanceSheetQuery) -> Result<BalanceSheet> {
+    let tolerance = query
+        .tolerance
+        .as_deref()
+        .map(parse_amount)
+        .transpose()?
+        .unwrap_or(DEFAULT_TOLERANCE);
+
+    let conversion = Conversion::load(data_dir, query.convert.as_deref(), query.as_of.as_deref())?;
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let mut unconverted = BTreeSet::new();
+
+    let mut sections: BTreeMap<&str, (Amounts, BTreeMap<String, Amounts>)> = BTreeMap::new();
+    let mut retained_earnings = Amounts::new();
+    for (account, amounts) in balances(data_dir, query.as_of.as_deref())? {
+        let amounts = convert(conversion.as_ref(), amounts, &mut unconverted);
+        let root = account.split(':').next().unwrap_or_default();
+        let section = match root {
+            "Assets" => "Assets",
+            "Liabilities" => "Liabilities",
+            "Equity" => "Equity",
+            "Income" | "Expenses" => {
+                for (currency, amount) in amounts {
+                    *retained_earnings.entry(currency).or_default() -= amount;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let sign = if is_credit_account(&account) { -Decimal::ONE } else { Decimal::ONE };
+        let (total, accounts) = sections.entry(section).or_default();
+        let components: Vec<&str> = account.split(':').collect();
+        for (currency, amount) in amounts {
+            *total.entry(currency.clone()).or_default() += amount * sign;
+            for depth in 1..=components.len() {
+                let name = components[..depth].join(":");
+                *accounts.entry(name).or_default().entry(currency.clone()).or_default() += amount * sign;
+            }
+        }
+    }
+
+    let mut section = |name: &str| {
+        let (total, accounts) = sections.remove(name).unwrap_or_default();
+        (
+            total.clone(),
+            BalanceSheetSection {
+                total: format_amounts(&total, &precisions),
+                accounts: accounts
+                    .into_iter()
+                    .map(|(account, amounts)| AccountBalance {
+                        account,
+                        balances: format_amounts(&amounts, &precisions),
+                        unconverted: Vec::new(),
+                    })
+                    .collect(),
+            },
+        )
+    };
+    let (assets_total, assets) = section("Assets");
+    let (liabilities_total, liabilities) = section("Liabilities");
+    let (equity_total, equity) = section("Equity");
+
+    // Assets - (liabilities + equity + retained earnings), per currency.
+    let mut imbalance = assets_total;
+    for totals in [&liabilities_total, &equity_total, &retained_earnings] {
+        for (currency, amount) in totals {
+            *imbalance.entry(currency.clone()).or_default() -= *amount;
+        }
+    }
+    imbalance.retain(|_, amount| amount.abs() > tolerance);
+
+    Ok(BalanceSheet {
+        as_of: query.as_of.clone(),
+        assets,
+        liabilities,
+        equity,
+        retained_earnings: format_amounts(&retained_earnings, &precisions),
+        balanced: imbalance.is_empty(),
+        imbalance: format_amounts(&imbalance, &precisions),
+        unconverted: unconverted.into_iter().collect(),
+    })
+}
+
+/// Every posting to `name` or its sub-accounts in date order, with the
+/// running balance per currency after each one. With `from`, the running
+/// balance starts from the postings before it rather than from zero.
+pub fn register(data_dir: &Path, name: &str, query: &RegisterQuery) -> Result<Vec<RegisterRow>> {
+    if !beancount::list_accounts(data_dir)?.iter().any(|a| a.name == name && !a.open_date.is_empty()) {
+        return Err(LedgerError::not_found(format!("Account {} was never opened", name)).into());
+    }
+    let from = query.from.as_deref().map(parse_date).transpose()?;
+    let to = query.to.as_deref().map(parse_date).transpose()?;
+
+    let mut transactions = beancount::list_transactions(data_dir)?;
+    transactions.sort_by(|a, b| a.date.cmp(&b.date));
+
+    let precisions = beancount::commodity_precisions(data_dir)?;
+    let mut running = Amounts::new();
+    let mut rows = Vec::new();
+    for tx in &transactions {
+        let date = parse_date(&tx.date)?;
+        if to.is_some_and(|t| date > t) {
+            break;
+        }
+        let before = from.is_some_and(|f| date < f);
+        for (account, currency, amount) in posting_amounts(tx)? {
+            if !beancount::account_matches(&account, name)
+                || query.currency.as_ref().is_some_and(|c| *c != currency)
+            {
+                continue;
+            }
+            *running.entry(currency.clone()).or_default() += amount;
+            if before {
+                continue;
+            }
+            rows.push(RegisterRow {
+                transaction_id: tx.id.clone().unwrap_or_default(),
+                date: tx.date.clone(),
+                payee: tx.payee.clone(),
+                narration: tx.narration.clone(),
+                account,
+                amount: format_amount(&currency, amount, &precisions),
+                balance: format_amounts(&running, &precisions),
+                currency,
+            });
+        }
+    }
+    Ok(rows)
+}
+
+/// Opened and posted-to accounts as a tree, with every implied parent
+/// present and balances rolled up into parents. `root` limits the tree to
+/// one subtree and `depth` to that many name components.
+pub fn account_tree(data_dir: &Path, query: &AccountTreeQuery) -> Result<Vec<AccountNode>> {
+    if query.depth == Some(0) {
+        return Err(LedgerError::invalid("depth must be at least 1").into());
+    }
+    let opened: BTreeSet<String> = beancount::list_accounts(data_dir)?.into_iter().map(|a| a.name).collect();
+    let balances = balances(data_dir, None)?;
+    let precisions = beancount::commodity_precisions(data_dir)?;
+
+    // Every account and ancestor, with its rolled-up balance.
+    let mut nodes: BTreeMap<String, Amounts> = BTreeMap::new();
+    for account in opened.iter().chain(balances.keys()) {
+        let components: Vec<&str> = account.split(':').collect();
+        for depth in 1..=components.len() {
+            nodes.entry(components[..depth].join(":")).or_default();
+        }
+    }
+    for (account, amounts) in &balances {
+        let components: Vec<&str> = account.split(':').collect();
+        for depth in 1..=components.len() {
+            let totals = nodes.entry(components[..depth].join(":")).or_default();
+            for (currency, amount) in amounts {
+                *totals.entry(currency.clone()).or_default() += *amount;
+            }
+        }
+    }
+
+    fn build(full_name: &str, nodes: &BTreeMap<String, Amounts>, opened: &BTreeSet<String>, depth: Option<usize>, precisions: &Precisions) -> AccountNode {
+        let prefix = format!("{}:", full_name);
+        let level = full_name.split(':').count();
+        let children = if depth.is_some_and(|d| level >= d) {
+            Vec::new()
+        } else {
+            nodes
+                .range(prefix.clone()..)
+                .take_while(|(name, _)| name.starts_with(&prefix))
+                .filter(|(name, _)| !name[prefix.len()..].contains(':'))
+                .map(|(name, _)| build(name, nodes, opened, depth, precisions))
+                .collect()
+        };
+        AccountNode {
+            name: full_name.rsplit(':').next().unwrap_or(full_name).to_string(),
+            full_name: full_name.to_string(),
+            children,
+            balance: format_amounts(&nodes[full_name], precisions),
+            synthetic: !opened.contains(full_name),
+        }
+    }
+
+    let roots: Vec<&String> = match &query.root {
+        Some(root) => match nodes.get_key_value(root) {
+            Some((name, _)) => vec![name],
+            None => return Err(LedgerError::not_found(format!("Account {} not found", root)).into()),
+        },
+        None => nodes.keys().filter(|name| !name.contains(':')).collect(),
+    };
+    Ok(roots
+        .into_iter()
+        .filter(|name| query.depth.is_none_or(|d| name.split(':').count() <= d))
+        .map(|name| build(name, &nodes, &opened, query.depth, &precisions))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A data directory holding `files`, as (name, content).
+    fn ledger(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    const YEAR: &str = r#"
+2024-01-01 commodity USD
+  precision: 2
+
+2024-01-01 * "Coffee"
+  Expenses:Food  3.5 USD
+  Assets:Cash
+
+2024-06-15 * "Groceries"
+  Expenses:Food  20 USD
+  Expenses:Home  10 USD
+  Assets:Cash
+
+2024-12-31 * "Refund"
+  Assets:Cash  5 USD
+  Income:Refunds
+"#;
+
+    fn activity_query(account_prefix: &str, sign: Option<&str>) -> ActivityQuery {
+        ActivityQuery {
+            from: Some("2024-01-01".to_string()),
+            to: Some("2024-12-31".to_string()),
+            metric: None,
+            account_prefix: Some(account_prefix.to_string()),
+            sign: sign.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn activity_covers_every_day_of_the_year() {
+        let dir = ledger(&[("2024.bean", YEAR)]);
+        let days = activity(dir.path(), &activity_query("Expenses", None)).unwrap();
+        assert_eq!(days.len(), 366);
+        assert_eq!(days[0].date, "2024-01-01");
+        assert_eq!(days[0].count, 1);
+        assert_eq!(days[0].amounts["USD"], "3.50");
+
+        let june = days.iter().find(|d| d.date == "2024-06-15").unwrap();
+        assert_eq!(june.count, 2);
+        assert_eq!(june.amounts["USD"], "30.00");
+
+        let quiet = days.iter().find(|d| d.date == "2024-03-01").unwrap();
+        assert_eq!(quiet.count, 0);
+        assert!(quiet.amounts.is_empty());
+        assert_eq!(days.iter().map(|d| d.count).sum::<u64>(), 3);
+    }
+
+    #[test]
+    fn activity_counts_elided_amounts_and_flips_natural_signs() {
+        let dir = ledger(&[("2024.bean", YEAR)]);
+        let raw = activity(dir.path(), &activity_query("Income", None)).unwrap();
+        assert_eq!(raw.last().unwrap().amounts["USD"], "-5.00");
+        let natural = activity(dir.path(), &activity_query("Income", Some("natural"))).unwrap();
+        assert_eq!(natural.last().unwrap().amounts["USD"], "5.00");
+    }
+
+    fn quarters(depth: Option<usize>) -> IncomeStatementPeriodsQuery {
+        IncomeStatementPeriodsQuery {
+            from: None,
+            to: None,
+            interval: Some("quarter".to_string()),
+            depth,
+            convert: Some(String::new()),
+        }
+    }
+
+    fn lines(statement: &IncomeStatement) -> Vec<(&str, &str)> {
+        statement.accounts.iter().map(|l| (l.account.as_str(), l.amounts["USD"].as_str())).collect()
+    }
+
+    #[test]
+    fn income_statement_buckets_by_quarter() {
+        let dir = ledger(&[("2024.bean", YEAR)]);
+        let report = income_statement_periods(dir.path(), &quarters(Some(2))).unwrap();
+        let periods = &report.periods;
+        assert_eq!(periods.len(), 4);
+        assert_eq!((periods[0].from.as_deref(), periods[0].to.as_deref()), (Some("2024-01-01"), Some("2024-03-31")));
+        assert_eq!(periods[0].expenses["USD"], "3.50");
+        assert_eq!(periods[0].net_income["USD"], "-3.50");
+        assert_eq!(lines(&periods[1]), vec![("Expenses:Food", "20.00"), ("Expenses:Home", "10.00")]);
+        assert!(periods[2].net_income.is_empty());
+
+        // Income reads as a positive number.
+        assert_eq!(periods[3].income["USD"], "5.00");
+        assert_eq!(periods[3].net_income["USD"], "5.00");
+        assert_eq!(lines(&periods[3]), vec![("Income:Refunds", "5.00")]);
+    }
+
+    #[test]
+    fn income_statement_rolls_accounts_up_to_depth() {
+        let dir = ledger(&[("2024.bean", YEAR)]);
+        let report = income_statement_periods(dir.path(), &quarters(Some(1))).unwrap();
+        assert_eq!(lines(&report.periods[1]), vec![("Expenses", "30.00")]);
+
+        let err = income_statement_periods(dir.path(), &quarters(Some(0))).unwrap_err();
+        assert_eq!(crate::error::kind(&err), crate::error::ErrorKind::Invalid);
+    }
+
+    #[test]
+    fn cash_flow_separates_transfers() {
+        let dir = ledger(&[(
+            "2024.bean",
+            r#"
+2024-05-31 * "Last month's salary"
+  Assets:Checking  3000.00 USD
+  Income:Salary
+
+2024-06-01 * "Salary"
+  Assets:Checking  3000.00 USD
+  Income:Salary
+
+2024-06-02 * "Rent"
+  Expenses:Rent  1200.00 USD
+  Assets:Checking
+
+2024-06-03 * "Save"
+  Assets:Savings  500.00 USD
+  Assets:Checking
+"#,
+        )]);
+        let query = CashFlowQuery { from: Some("2024-06-01".to_string()), to: Some("2024-06-30".to_string()) };
+        let report = cash_flow(dir.path(), &query).unwrap();
+
+        let lines = |lines: &[CashFlowLine]| -> Vec<(String, String)> {
+            lines.iter().map(|l| (l.account.clone(), l.amounts["USD"].clone())).collect()
+        };
+        assert_eq!(lines(&report.inflows), vec![("Income:Salary".to_string(), "3000.00".to_string())]);
+        assert_eq!(lines(&report.outflows), vec![("Expenses:Rent".to_string(), "1200.00".to_string())]);
+        assert_eq!(
+            lines(&report.transfers),
+            vec![("Assets:Checking".to_string(), "-500.00".to_string()), ("Assets:Savings".to_string(), "500.00".to_string())]
+        );
+        assert_eq!(report.total_inflow["USD"], "3000.00");
+        assert_eq!(report.total_outflow["USD"], "1200.00");
+        assert_eq!(report.net["USD"], "1800.00");
+    }
+
+    #[test]
+    fn periods_parse_years_months_and_quarters() {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        assert_eq!(parse_period("2024").unwrap(), (date("2024-01-01"), date("2024-12-31")));
+        assert_eq!(parse_period("2024-02").unwrap(), (date("2024-02-01"), date("2024-02-29")));
+        assert_eq!(parse_period("2024-12").unwrap(), (date("2024-12-01"), date("2024-12-31")));
+        assert_eq!(parse_period("2024-Q3").unwrap(), (date("2024-07-01"), date("2024-09-30")));
+        for bad in ["24", "2024-00", "2024-13", "2024-1", "2024-Q0", "2024-Q5", "2024-Q", "2024-Q12", "2024-05-01", "year"] {
+            assert!(parse_period(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn income_statement_refuses_too_many_periods() {
+        let dir = ledger(&[("2024.bean", YEAR)]);
+        let query = IncomeStatementPeriodsQuery {
+            from: Some("0001-01-01".to_string()),
+            to: Some("9999-12-31".to_string()),
+            interval: Some("week".to_string()),
+            ..quarters(None)
+        };
+        let err = income_statement_periods(dir.path(), &query).unwrap_err();
+        assert_eq!(crate::error::kind(&err), crate::error::ErrorKind::Invalid);
+    }
+
+    #[test]
+    fn net_worth_refuses_too_many_periods() {
+        let dir = ledger(&[("2024.bean", YEAR)]);
+        let query = NetWorthQuery {
+            from: Some("0001-01-01".to_string()),
+            to: Some("9999-12-31".to_string()),
+            interval: Some("week".to_string()),
+            currency: Some(String::new()),
+        };
+        let err = net_worth(dir.path(), &query).unwrap_err();
+        assert_eq!(crate::error::kind(&err), crate::error::ErrorKind::Invalid);
+
+        let monthly = NetWorthQuery { from: Some("2024-01-01".to_string()), to: Some("2024-12-31".to_string()), interval: None, ..query };
+        let series = net_worth(dir.path(), &monthly).unwrap();
+        assert_eq!(series.points.len(), 12);
+        assert_eq!(series.points[11].assets["USD"], "-28.50");
+    }
+}
@@ -0,0 +1,251 @@
+//! Categorization rules applied to imported transactions, stored in
+//! `rules.toml` in the data directory as one `[[rule]]` table per rule:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "Coffee"
+//! payee_pattern = "(?i)starbucks"
+//! account = "Expenses:Food:Coffee"
+//! tags = ["coffee"]
+//! payee = "Starbucks"
+//! ```
+//!
+//! This sits beside `import` rather than inside `beancount`, which only
+//! reads and writes ledger files; rules aren't part of the ledger.
+
+use crate::fsio;
+use crate::model::Transaction;
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use utoipa::ToSchema;
+
+const RULES_FILE: &str = "rules.toml";
+
+/// The layout of `rules.toml`.
+#[derive(Default, Serialize, Deserialize)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<ImportRule>,
+}
+
+/// Matches on payee and/or narration (both must match when both are set) and
+/// rewrites the counter-account, tags and payee of the transaction.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ImportRule {
+    pub name: String,
+    /// Regex tested against the payee
+    pub payee_pattern: Option<String>,
+    /// Regex tested against the narration
+    pub narration_pattern: Option<String>,
+    /// Replaces the counter-account posting
+    pub account: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Normalized payee to write instead of the bank's description
+    pub payee: Option<String>,
+}
+
+pub struct CompiledRule {
+    rule: ImportRule,
+    payee: Option<Regex>,
+    narration: Option<Regex>,
+}
+
+fn compile(pattern: &Option<String>, rule: &str) -> Result<Option<Regex>> {
+    pattern
+        .as_deref()
+        .map(|p| Regex::new(p).with_context(|| format!("Invalid pattern in rule '{}'", rule)))
+        .transpose()
+}
+
+pub fn compile_rules(rules: &[ImportRule]) -> Result<Vec<CompiledRule>> {
+    rules
+        .iter()
+        .map(|rule| {
+            if rule.payee_pattern.is_none() && rule.narration_pattern.is_none() {
+                anyhow::bail!("Rule '{}' needs a payee or narration pattern", rule.name);
+            }
+            Ok(CompiledRule {
+                payee: compile(&rule.payee_pattern, &rule.name)?,
+                narration: compile(&rule.narration_pattern, &rule.name)?,
+                rule: rule.clone(),
+            })
+        })
+        .collect()
+}
+
+pub fn load_rules(data_dir: &Path) -> Result<Vec<ImportRule>> {
+    let path = data_dir.join(RULES_FILE);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let text = std::fs::read_to_string(&path)?;
+    let file: RulesFile = toml::from_str(&text).with_context(|| format!("Invalid rules in {}", path.display()))?;
+    Ok(file.rules)
+}
+
+pub fn save_rules(data_dir: &Path, rules: &[ImportRule]) -> Result<()> {
+    compile_rules(rules)?;
+    let file = RulesFile { rules: rules.to_vec() };
+    fsio::write_atomic(&data_dir.join(RULES_FILE), &toml::to_string(&file)?)?;
+    Ok(())
+}
+
+/// Applies the first matching rule to `tx`, replacing the posting on
+/// `counter_account`, and returns the rule's name.
+pub fn apply(rules: &[CompiledRule], tx: &mut Transaction, counter_account: &str) -> Option<String> {
+    let matches = |re: &Option<Regex>, text: &Option<String>| {
+        re.as_ref()
+            .is_none_or(|re| re.is_match(text.as_deref().unwrap_or_default()))
+    };
+    let compiled = rules
+        .iter()
+        .find(|r| matches(&r.payee, &tx.payee) && matches(&r.narration, &tx.narration))?;
+    let rule = &compiled.rule;
+
+    if let Some(account) = &rule.account {
+        for p in tx.postings.iter_mut().filter(|p| p.account == counter_account) {
+            p.account = account.clone();
+        }
+    }
+    for tag in &rule.tags {
+        if !tx.tags.contains(tag) {
+            tx.tags.push(tag.clone());
+        }
+    }
+    if let Some(payee) = &rule.payee {
+        tx.payee = Some(payee.clone());
+    }
+    Some(rule.name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::import::{self, CsvMapping};
+    use crate::model::Posting;
+
+    fn rule(name: &str, payee_pattern: Option<&str>, narration_pattern: Option<&str>) -> ImportRule {
+        ImportRule {
+            name: name.to_string(),
+            payee_pattern: payee_pattern.map(str::to_string),
+            narration_pattern: narration_pattern.map(str::to_string),
+            account: None,
+            tags: Vec::new(),
+            payee: None,
+        }
+    }
+
+    fn coffee() -> ImportRule {
+        ImportRule {
+            account: Some("Expenses:Food:Coffee".to_string()),
+            tags: vec!["coffee".to_string()],
+            payee: Some("Starbucks".to_string()),
+            ..rule("Coffee", Some("(?i)starbucks"), None)
+        }
+    }
+
+    fn bank_row(payee: &str, narration: &str) -> Transaction {
+        let posting = |account: &str, amount: &str| Posting {
+            flag: None,
+            account: account.to_string(),
+            amount: amount.to_string(),
+            currency: "USD".to_string(),
+            cost: None,
+            price: None,
+        };
+        Transaction {
+            id: None,
+            date: "2024-03-01".to_string(),
+            flag: "!".to_string(),
+            payee: Some(payee.to_string()),
+            narration: Some(narration.to_string()),
+            tags: vec!["coffee".to_string()],
+            postings: vec![posting("Assets:Bank", "-4.50"), posting("Expenses:Uncategorized", "4.50")],
+            document: None,
+        }
+    }
+
+    #[test]
+    fn rules_need_a_valid_pattern() {
+        assert!(compile_rules(&[rule("Empty", None, None)]).is_err());
+        let err = compile_rules(&[rule("Broken", Some("(unclosed"), None)]).err().unwrap();
+        assert!(format!("{:#}", err).contains("Broken"));
+        assert_eq!(compile_rules(&[coffee()]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn first_matching_rule_rewrites_the_row() {
+        let rules = compile_rules(&[rule("Never", Some("^nothing$"), None), coffee(), rule("Later", Some("(?i)star"), None)]).unwrap();
+        let mut tx = bank_row("STARBUCKS #1234 SEATTLE", "Card payment");
+        assert_eq!(apply(&rules, &mut tx, "Expenses:Uncategorized").as_deref(), Some("Coffee"));
+        assert_eq!(tx.postings[0].account, "Assets:Bank");
+        assert_eq!(tx.postings[1].account, "Expenses:Food:Coffee");
+        assert_eq!(tx.payee.as_deref(), Some("Starbucks"));
+        // A tag the row already has isn't added twice.
+        assert_eq!(tx.tags, vec!["coffee"]);
+    }
+
+    #[test]
+    fn both_patterns_must_match_when_both_are_set() {
+        let rules = compile_rules(&[rule("Rent", Some("ACME"), Some("(?i)rent"))]).unwrap();
+        let mut tx = bank_row("ACME Properties", "Deposit");
+        assert_eq!(apply(&rules, &mut tx, "Expenses:Uncategorized"), None);
+        assert_eq!(tx.postings[1].account, "Expenses:Uncategorized");
+        let mut tx = bank_row("ACME Properties", "March rent");
+        assert_eq!(apply(&rules, &mut tx, "Expenses:Uncategorized").as_deref(), Some("Rent"));
+    }
+
+    #[test]
+    fn rules_round_trip_through_rules_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_rules(dir.path()).unwrap().is_empty());
+        save_rules(dir.path(), &[coffee(), rule("Rent", Some("ACME"), Some("rent"))]).unwrap();
+        let text = std::fs::read_to_string(dir.path().join("rules.toml")).unwrap();
+        assert!(text.contains("[[rule]]"));
+
+        let loaded = load_rules(dir.path()).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].account.as_deref(), Some("Expenses:Food:Coffee"));
+        assert_eq!(loaded[1].narration_pattern.as_deref(), Some("rent"));
+        assert_eq!(loaded[1].account, None);
+
+        // Invalid rules are refused without touching the file.
+        assert!(save_rules(dir.path(), &[rule("Empty", None, None)]).is_err());
+        assert_eq!(std::fs::read_to_string(dir.path().join("rules.toml")).unwrap(), text);
+    }
+
+    #[test]
+    fn import_reports_the_rule_each_row_matched() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("main.bean"),
+            "2024-01-01 open Assets:Bank\n2024-01-01 open Expenses:Uncategorized\n2024-01-01 open Expenses:Food:Coffee\n",
+        )
+        .unwrap();
+        save_rules(dir.path(), &[coffee()]).unwrap();
+        let mapping = CsvMapping {
+            date_column: "Date".to_string(),
+            amount_column: "Amount".to_string(),
+            payee_column: Some("Payee".to_string()),
+            narration_column: None,
+            date_format: "%Y-%m-%d".to_string(),
+            account: "Assets:Bank".to_string(),
+            counter_account: "Expenses:Uncategorized".to_string(),
+            currency: "USD".to_string(),
+            has_header: true,
+            flag: "!".to_string(),
+        };
+        let csv = "Date,Amount,Payee\n2024-03-01,-4.50,STARBUCKS #1234\n2024-03-02,-20.00,Hardware store\n";
+        let (rows, errors) = import::parse_csv(csv.as_bytes(), &mapping, '.').unwrap();
+        let config = Config::for_tests(dir.path());
+        let result = import::import_rows(dir.path(), &config, &mapping, rows, errors, true, false).unwrap();
+
+        let matched: Vec<_> = result.transactions.iter().map(|t| (t.transaction.date.as_str(), t.rule.as_deref())).collect();
+        assert_eq!(matched, vec![("2024-03-01", Some("Coffee")), ("2024-03-02", None)]);
+        assert_eq!(result.transactions[0].transaction.postings[1].account, "Expenses:Food:Coffee");
+    }
+}
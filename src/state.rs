@@ -1,23 +1,228 @@
+use crate::beancount;
+use crate::config::Config;
+use crate::model::{ActivityDay, ActivityQuery, Metrics, VerifyResult};
+use crate::quotes::{HttpQuoteSource, QuoteSource};
+use crate::reports;
+use crate::watch::LedgerChange;
+use rust_decimal::Decimal;
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
+use tokio::sync::broadcast;
 
+/// Identifies one state of the ledger on disk: every file in the include
+/// graph with its modification time and size.
+type LedgerKey = Vec<(PathBuf, Option<SystemTime>, u64)>;
+
+struct CachedVerify {
+    key: LedgerKey,
+    tolerance: Decimal,
+    generation: u64,
+    result: VerifyResult,
+}
+
+struct CachedActivity {
+    key: Option<LedgerKey>,
+    query: ActivityQuery,
+    generation: u64,
+    result: Vec<ActivityDay>,
+}
+
+/// Shared server state.
+///
+/// Ledger access is blocking: the `beancount`, `reports`, `import`, `rules`
+/// and `autocomplete` functions, `verify` and the git commit all read or
+/// write files and parse synchronously, and taking `ledger_lock` can wait on
+/// a writer. Handlers run them inside `tokio::task::spawn_blocking`, lock
+/// included, so they never stall the async workers.
 pub struct AppState {
     pub data_dir: PathBuf,
-    // We might want a cache or lock here if we were doing more complex things,
-    // but for now we'll just read/write files directly.
-    // A mutex might be needed if we want to ensure sequential writes.
-    pub write_lock: Mutex<()>,
+    pub config: Config,
+    /// Guards the ledger files: writers hold it exclusively for the whole
+    /// write, readers share it so they never see a write half done. All
+    /// ledger IO is synchronous and runs under `spawn_blocking`, so a std
+    /// lock is enough.
+    pub ledger_lock: RwLock<()>,
+    /// Bumped when a write starts and again when it ends, so an odd value
+    /// means a write is in progress.
+    write_generation: AtomicU64,
+    verify_cache: Mutex<Option<CachedVerify>>,
+    verify_parses: AtomicU64,
+    verify_cache_hits: AtomicU64,
+    activity_cache: Mutex<Option<CachedActivity>>,
+    /// Debounced changes to ledger files, fed by the file watcher.
+    pub changes: broadcast::Sender<LedgerChange>,
+    /// IDs of transactions added through the API, for `/events/stream`.
+    pub transactions_added: broadcast::Sender<String>,
+    /// Where `/prices/fetch` gets quotes; replaceable so tests can avoid the
+    /// network.
+    pub quote_source: Box<dyn QuoteSource>,
+    /// Held by the git auto-commit middleware from the start of a write
+    /// request until its commit is made, so each commit holds exactly one
+    /// request's changes.
+    pub write_session: tokio::sync::Mutex<()>,
+}
+
+/// Holds the write lock; dropping it marks the write as finished.
+pub struct WriteGuard<'a> {
+    _lock: RwLockWriteGuard<'a, ()>,
+    generation: &'a AtomicU64,
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
 }
 
 impl AppState {
-    pub fn new(data_dir: String) -> anyhow::Result<Self> {
-        let path = PathBuf::from(data_dir);
+    pub fn new(config: Config) -> anyhow::Result<Self> {
+        let path = config.data_dir.clone();
         if !path.exists() {
+            // Only the default directory is created on demand; an explicit
+            // path that doesn't exist is more likely a typo.
+            if config.data_dir_explicit {
+                anyhow::bail!("Data directory {} does not exist", path.display());
+            }
             std::fs::create_dir_all(&path)?;
         }
+        tracing::info!("using data directory {}", std::fs::canonicalize(&path)?.display());
         Ok(Self {
             data_dir: path,
-            write_lock: Mutex::new(()),
+            config,
+            ledger_lock: RwLock::new(()),
+            write_generation: AtomicU64::new(0),
+            verify_cache: Mutex::new(None),
+            verify_parses: AtomicU64::new(0),
+            verify_cache_hits: AtomicU64::new(0),
+            activity_cache: Mutex::new(None),
+            changes: broadcast::channel(16).0,
+            transactions_added: broadcast::channel(64).0,
+            quote_source: Box::new(HttpQuoteSource),
+            write_session: tokio::sync::Mutex::new(()),
         })
     }
+
+    /// Takes the lock shared by ledger reads.
+    pub fn lock_for_read(&self) -> RwLockReadGuard<'_, ()> {
+        self.ledger_lock.read().unwrap()
+    }
+
+    /// Takes the write lock for a ledger write, invalidating cached results.
+    pub fn lock_for_write(&self) -> WriteGuard<'_> {
+        let lock = self.ledger_lock.write().unwrap();
+        self.write_generation.fetch_add(1, Ordering::SeqCst);
+        self.verify_cache.lock().unwrap().take();
+        self.activity_cache.lock().unwrap().take();
+        WriteGuard {
+            _lock: lock,
+            generation: &self.write_generation,
+        }
+    }
+
+    fn ledger_key(&self) -> anyhow::Result<LedgerKey> {
+        beancount::include_graph(&self.data_dir)?
+            .into_iter()
+            .map(|path| {
+                let meta = std::fs::metadata(&path)?;
+                Ok((path, meta.modified().ok(), meta.len()))
+            })
+            .collect()
+    }
+
+    /// Verifies the ledger, reusing the last result while no file in the
+    /// include graph has changed and no write has happened since.
+    pub fn verify(&self, tolerance: Decimal) -> anyhow::Result<VerifyResult> {
+        let _lock = self.lock_for_read();
+        if !self.config.verify_cache {
+            self.verify_parses.fetch_add(1, Ordering::Relaxed);
+            return beancount::verify(&self.data_dir, tolerance);
+        }
+
+        let generation = self.write_generation.load(Ordering::SeqCst);
+        let key = self.ledger_key()?;
+        if let Some(cached) = &*self.verify_cache.lock().unwrap() {
+            if cached.generation == generation && cached.key == key && cached.tolerance == tolerance {
+                self.verify_cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(cached.result.clone());
+            }
+        }
+
+        self.verify_parses.fetch_add(1, Ordering::Relaxed);
+        let result = beancount::verify(&self.data_dir, tolerance)?;
+        // Only cache if no write started or finished while we were parsing.
+        if generation.is_multiple_of(2) && self.write_generation.load(Ordering::SeqCst) == generation {
+            *self.verify_cache.lock().unwrap() = Some(CachedVerify {
+                key,
+                tolerance,
+                generation,
+                result: result.clone(),
+            });
+        }
+        Ok(result)
+    }
+
+    /// The activity report, reused like `verify`'s result while the ledger
+    /// is unchanged and the query is the same.
+    pub fn activity(&self, query: &ActivityQuery) -> anyhow::Result<Vec<ActivityDay>> {
+        let _lock = self.lock_for_read();
+        let generation = self.write_generation.load(Ordering::SeqCst);
+        // The report reads every ledger file, so it works without a
+        // `main.bean`; the include graph is then just unknown.
+        let key = self.ledger_key().ok();
+        if let Some(cached) = &*self.activity_cache.lock().unwrap() {
+            if cached.generation == generation && cached.key == key && cached.query == *query {
+                return Ok(cached.result.clone());
+            }
+        }
+
+        let result = reports::activity(&self.data_dir, query)?;
+        // Only cache if no write started or finished while we were reading.
+        if generation.is_multiple_of(2) && self.write_generation.load(Ordering::SeqCst) == generation {
+            *self.activity_cache.lock().unwrap() = Some(CachedActivity {
+                key,
+                query: query.clone(),
+                generation,
+                result: result.clone(),
+            });
+        }
+        Ok(result)
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            verify_parses: self.verify_parses.load(Ordering::Relaxed),
+            verify_cache_hits: self.verify_cache_hits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger_state() -> (tempfile::TempDir, AppState) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.bean"), "include \"accounts.bean\"\n").unwrap();
+        std::fs::write(dir.path().join("accounts.bean"), "2024-01-01 open Assets:Cash\n").unwrap();
+        let state = AppState::new(Config::for_tests(dir.path())).unwrap();
+        (dir, state)
+    }
+
+    #[test]
+    fn unchanged_ledger_is_verified_once() {
+        let (_dir, state) = ledger_state();
+        let first = state.verify(Decimal::ZERO).unwrap();
+        let second = state.verify(Decimal::ZERO).unwrap();
+        assert_eq!(first.errors, second.errors);
+        let metrics = state.metrics();
+        assert_eq!(metrics.verify_parses, 1);
+        assert_eq!(metrics.verify_cache_hits, 1);
+
+        // A write invalidates the cached result.
+        drop(state.lock_for_write());
+        state.verify(Decimal::ZERO).unwrap();
+        assert_eq!(state.metrics().verify_parses, 2);
+    }
 }
@@ -0,0 +1,234 @@
+use crate::beancount::{account_matches, parse_amount};
+use crate::error::LedgerError;
+use crate::model::{Account, Transaction};
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use utoipa::ToSchema;
+
+/// A named set of write-time checks. Every toggle defaults to off, so a
+/// profile only needs to list the checks it wants.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ValidationProfile {
+    pub name: String,
+    /// Reject postings to accounts without an `open` directive.
+    pub reject_unknown_accounts: bool,
+    /// Reject transactions without a payee.
+    pub require_payee: bool,
+    /// Reject `!` transactions dated more than this many days ago.
+    pub max_pending_age_days: Option<i64>,
+    /// Require a `document` attachment on expense postings above this amount.
+    pub receipt_required_above: Option<ReceiptThreshold>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReceiptThreshold {
+    pub amount: String,
+    pub currency: String,
+}
+
+/// Returned (wrapped in `anyhow::Error`) when a transaction fails its
+/// profile's checks; handlers turn it into a 422.
+#[derive(Debug)]
+pub struct ValidationError {
+    pub profile: String,
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rejected by validation profile '{}': {}", self.profile, self.problems.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl ValidationProfile {
+    /// The built-in profiles: `lenient` (no extra checks) and `strict`.
+    pub fn builtins() -> Vec<Self> {
+        vec![
+            Self {
+                name: "lenient".to_string(),
+                ..Default::default()
+            },
+            Self {
+                name: "strict".to_string(),
+                reject_unknown_accounts: true,
+                require_payee: true,
+                max_pending_age_days: Some(30),
+                receipt_required_above: None,
+            },
+        ]
+    }
+
+    /// Loads the built-in profiles plus any defined in a JSON file holding an
+    /// array of profiles; file entries replace built-ins of the same name.
+    pub fn load_all(file: Option<&Path>) -> Result<Vec<Self>> {
+        let mut profiles = Self::builtins();
+        if let Some(file) = file {
+            let text = std::fs::read_to_string(file)
+                .with_context(|| format!("Failed to read validation profiles from {}", file.display()))?;
+            let custom: Vec<Self> = serde_json::from_str(&text)
+                .with_context(|| format!("Invalid validation profiles in {}", file.display()))?;
+            for profile in custom {
+                profiles.retain(|p| p.name != profile.name);
+                profiles.push(profile);
+            }
+        }
+        Ok(profiles)
+    }
+
+    /// Runs every enabled check against `tx`, collecting all problems.
+    pub fn validate(&self, tx: &Transaction, accounts: &[Account]) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if self.require_payee && tx.payee.as_deref().is_none_or(str::is_empty) {
+            problems.push("payee is required".to_string());
+        }
+
+        if self.reject_unknown_accounts {
+            for p in &tx.postings {
+                if !accounts.iter().any(|a| a.name == p.account && !a.open_date.is_empty()) {
+                    problems.push(format!("unknown account {}", p.account));
+                }
+            }
+        }
+
+        if let Some(max_age) = self.max_pending_age_days {
+            if tx.flag == "!" {
+                let date = chrono::NaiveDate::parse_from_str(&tx.date, "%Y-%m-%d")
+                    .map_err(|e| LedgerError::invalid(format!("Invalid date '{}': {}", tx.date, e)))?;
+                let age = (chrono::Local::now().date_naive() - date).num_days();
+                if age > max_age {
+                    problems.push(format!("pending (!) transactions may be at most {} days old", max_age));
+                }
+            }
+        }
+
+        if let Some(threshold) = &self.receipt_required_above {
+            let limit: Decimal = parse_amount(&threshold.amount)?;
+            let needs_receipt = tx.postings.iter().any(|p| {
+                account_matches(&p.account, "Expenses")
+                    && p.currency == threshold.currency
+                    && parse_amount(&p.amount).is_ok_and(|a| a.abs() > limit)
+            });
+            if needs_receipt && tx.document.is_none() {
+                problems.push(format!(
+                    "expenses over {} {} need a receipt document",
+                    threshold.amount, threshold.currency
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError {
+                profile: self.name.clone(),
+                problems,
+            }
+            .into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Posting;
+
+    fn profile(configure: impl FnOnce(&mut ValidationProfile)) -> ValidationProfile {
+        let mut profile = ValidationProfile {
+            name: "test".to_string(),
+            ..Default::default()
+        };
+        configure(&mut profile);
+        profile
+    }
+
+    fn transaction(date: &str, flag: &str, payee: Option<&str>, amount: &str) -> Transaction {
+        let posting = |account: &str, amount: &str| Posting {
+            flag: None,
+            account: account.to_string(),
+            amount: amount.to_string(),
+            currency: "EUR".to_string(),
+            cost: None,
+            price: None,
+        };
+        Transaction {
+            id: None,
+            date: date.to_string(),
+            flag: flag.to_string(),
+            payee: payee.map(str::to_string),
+            narration: Some("Supplies".to_string()),
+            tags: Vec::new(),
+            postings: vec![posting("Expenses:Office", amount), posting("Assets:Bank", &format!("-{}", amount))],
+            document: None,
+        }
+    }
+
+    fn open(name: &str) -> Account {
+        Account {
+            name: name.to_string(),
+            open_date: "2020-01-01".to_string(),
+            currencies: Vec::new(),
+            close_date: None,
+            warning: None,
+        }
+    }
+
+    fn problems(profile: &ValidationProfile, tx: &Transaction, accounts: &[Account]) -> Vec<String> {
+        match profile.validate(tx, accounts) {
+            Ok(()) => Vec::new(),
+            Err(e) => e.downcast::<ValidationError>().unwrap().problems,
+        }
+    }
+
+    #[test]
+    fn reject_unknown_accounts() {
+        let strict = profile(|p| p.reject_unknown_accounts = true);
+        let tx = transaction("2024-03-01", "*", None, "10");
+        assert_eq!(problems(&strict, &tx, &[open("Expenses:Office")]), ["unknown account Assets:Bank"]);
+        assert!(problems(&strict, &tx, &[open("Expenses:Office"), open("Assets:Bank")]).is_empty());
+        assert!(problems(&profile(|_| {}), &tx, &[]).is_empty());
+    }
+
+    #[test]
+    fn require_payee() {
+        let strict = profile(|p| p.require_payee = true);
+        assert_eq!(problems(&strict, &transaction("2024-03-01", "*", None, "10"), &[]), ["payee is required"]);
+        assert_eq!(problems(&strict, &transaction("2024-03-01", "*", Some(""), "10"), &[]), ["payee is required"]);
+        assert!(problems(&strict, &transaction("2024-03-01", "*", Some("Shop"), "10"), &[]).is_empty());
+    }
+
+    #[test]
+    fn max_pending_age_days() {
+        let strict = profile(|p| p.max_pending_age_days = Some(30));
+        let today = chrono::Local::now().date_naive();
+        let old = (today - chrono::Days::new(31)).to_string();
+        let recent = (today - chrono::Days::new(5)).to_string();
+        assert_eq!(problems(&strict, &transaction(&old, "!", None, "10"), &[]).len(), 1);
+        assert!(problems(&strict, &transaction(&recent, "!", None, "10"), &[]).is_empty());
+        assert!(problems(&strict, &transaction(&old, "*", None, "10"), &[]).is_empty());
+
+        let err = strict.validate(&transaction("2024-13-40", "!", None, "10"), &[]).unwrap_err();
+        assert_eq!(crate::error::kind(&err), crate::error::ErrorKind::Invalid);
+    }
+
+    #[test]
+    fn receipt_required_above() {
+        let strict = profile(|p| {
+            p.receipt_required_above = Some(ReceiptThreshold {
+                amount: "100".to_string(),
+                currency: "EUR".to_string(),
+            })
+        });
+        assert_eq!(problems(&strict, &transaction("2024-03-01", "*", None, "150"), &[]).len(), 1);
+        assert!(problems(&strict, &transaction("2024-03-01", "*", None, "50"), &[]).is_empty());
+        let mut with_receipt = transaction("2024-03-01", "*", None, "150");
+        with_receipt.document = Some("receipts/invoice.pdf".to_string());
+        assert!(problems(&strict, &with_receipt, &[]).is_empty());
+    }
+}
@@ -0,0 +1,73 @@
+//! Watches the data directory for changes to `.bean` files, whether made
+//! through the API or in an editor, and broadcasts them to `/ws` clients.
+
+use anyhow::{Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use utoipa::ToSchema;
+
+/// How long to wait for a burst of events (one editor save is often
+/// several) to settle before announcing it.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Sent to WebSocket clients when ledger files change.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LedgerChange {
+    /// Always `ledger_changed`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Changed files, relative to the data directory
+    pub files: Vec<String>,
+}
+
+/// Starts watching `data_dir`, feeding debounced changes into `changes`.
+/// The watcher stops when the returned handle is dropped.
+pub fn spawn(data_dir: &Path, changes: broadcast::Sender<LedgerChange>) -> Result<RecommendedWatcher> {
+    let root = std::fs::canonicalize(data_dir)?;
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<PathBuf>>();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+        Ok(event) => {
+            let paths: Vec<PathBuf> = event
+                .paths
+                .into_iter()
+                .filter(|p| p.extension().is_some_and(|e| e == "bean"))
+                .collect();
+            if !paths.is_empty() {
+                tx.send(paths).ok();
+            }
+        }
+        Err(e) => tracing::warn!("file watcher error: {}", e),
+    })
+    .context("Failed to start the file watcher")?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", root.display()))?;
+
+    tokio::spawn(async move {
+        while let Some(mut paths) = rx.recv().await {
+            let settle = tokio::time::sleep(DEBOUNCE);
+            tokio::pin!(settle);
+            loop {
+                tokio::select! {
+                    _ = &mut settle => break,
+                    more = rx.recv() => match more {
+                        Some(more) => paths.extend(more),
+                        None => break,
+                    },
+                }
+            }
+            let mut files: Vec<String> = paths
+                .iter()
+                .map(|p| p.strip_prefix(&root).unwrap_or(p).to_string_lossy().to_string())
+                .collect();
+            files.sort();
+            files.dedup();
+            // Nobody listening is not an error.
+            changes.send(LedgerChange { kind: "ledger_changed", files }).ok();
+        }
+    });
+    Ok(watcher)
+}